@@ -0,0 +1,40 @@
+use caffeinate2::duration_parser::try_parse_duration;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+use std::process::Command;
+
+// Parsing the timeout is on the startup path of every timed session
+fn bench_parse_duration(c: &mut Criterion) {
+    c.bench_function("parse plain seconds", |b| {
+        b.iter(|| try_parse_duration(black_box("600")))
+    });
+    c.bench_function("parse unit string", |b| {
+        b.iter(|| try_parse_duration(black_box("1 day 2 hours 3mins 4s")))
+    });
+}
+
+// The whole fast-start path, like `caffeinate2 -- make`: parsing arguments, loading IOKit and
+// its private symbols, creating the assertion (CFStrings included), running the command and
+// releasing it again. Everything caffeinate2 adds to a short command shows up here.
+fn bench_wrap_command(c: &mut Criterion) {
+    let mut group = c.benchmark_group("startup");
+    // Each iteration is a couple of process launches
+    group.sample_size(20);
+    group.bench_function("wrap a command that exits right away", |b| {
+        b.iter(|| {
+            let status = Command::new(env!("CARGO_BIN_EXE_caffeinate2"))
+                .args(["--", "/usr/bin/true"])
+                .output()
+                .expect("couldn't run caffeinate2")
+                .status;
+            assert!(status.success());
+        })
+    });
+    group.bench_function("run the command directly, for comparison", |b| {
+        b.iter(|| Command::new("/usr/bin/true").status().unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse_duration, bench_wrap_command);
+criterion_main!(benches);
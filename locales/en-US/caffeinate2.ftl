@@ -0,0 +1,78 @@
+# Messages printed while a session is running.
+# To add a language, copy this file to locales/<language>/ and translate the values,
+# then add it to LOCALES in src/i18n.rs.
+
+## Sleep types
+
+sleep-type-display = Display
+sleep-type-display-no-dim = Display (no dimming)
+sleep-type-disk = Disk
+sleep-type-disk-volume = Disk ({ $volume })
+sleep-type-system = System
+sleep-type-system-on-ac = System (if on AC)
+sleep-type-entirely = Entirely
+sleep-type-user-active = User active
+sleep-type-network-client = Network client
+
+## Session banner, like "Preventing sleep types: [ System ] for 10 minutes 0 seconds."
+
+preventing-sleep = Preventing sleep types: [ { $types } ]
+until-command = until command finishes.
+until-commands = until { $count } commands finish.
+until-ctrl-c = until Ctrl+C pressed.
+for-duration = for { $duration }
+until-pid = until PID { $pid } finishes
+until-job = until launchd job { $label } is unloaded.
+until-reachable = until { $host } is reachable.
+until-unreachable = until { $host } is unreachable.
+until-http = until { $url } gives the expected response.
+while-http = while { $url } gives the expected response.
+until-logout = until you log out.
+logged-out = Logged out.
+or = or
+ends-with-command = The session ends when the command does, even if there's time left on the timeout (add --hold-full-timeout to keep it going).
+resuming = Resuming { $time }.
+pid-finished = PID { $pid } finished { $time } with exit code { $code }
+still-preventing = Still preventing sleep for { $duration }.
+hold-full-timeout = The command finished, still preventing sleep until { $time } (--hold-full-timeout).
+
+## Heartbeat lines, like "Still holding: system, 2h13m remaining."
+
+heartbeat = { $holding }, { $time }.
+still-holding = Still holding: { $types }
+holding-nothing = Holding nothing right now
+time-remaining = { $duration } remaining
+time-so-far = { $duration } so far
+
+## --repeat rounds
+
+round-done = Round { $round } of { $rounds } done, taking a { $duration } break.
+break-over = Break's over, round { $round } of { $rounds } until { $time }.
+
+## Times, like "at 5:00:00 PM" or "on March 2 at 5:00:00 PM"
+
+at-time = at { $time }
+on-date-at-time = on { $date } at { $time }
+
+## Durations
+
+days = { $count ->
+    [one] { $count } day
+   *[other] { $count } days
+}
+hours = { $count ->
+    [one] { $count } hour
+   *[other] { $count } hours
+}
+minutes = { $count ->
+    [one] { $count } minute
+   *[other] { $count } minutes
+}
+seconds = { $count ->
+    [one] { $count } second
+   *[other] { $count } seconds
+}
+milliseconds = { $count ->
+    [one] { $count } millisecond
+   *[other] { $count } milliseconds
+}
@@ -0,0 +1,143 @@
+// App Intents for driving caffeinate2 from Shortcuts.app and Focus filters.
+//
+// This is a thin wrapper around the command line interface: every intent runs
+// `caffeinate2 toggle`, `ctl stop` or `ctl status --format json`, so sessions
+// started here show up in `caffeinate2 status` like any other.
+// See shortcuts/README.md for how to build it into a helper app.
+
+import AppIntents
+import Foundation
+
+/// Places caffeinate2 is usually installed, checked in order
+private let searchPaths = [
+    "/opt/homebrew/bin/caffeinate2",
+    "/usr/local/bin/caffeinate2",
+    NSString(string: "~/.cargo/bin/caffeinate2").expandingTildeInPath,
+]
+
+struct Caffeinate2Error: Error, CustomLocalizedStringResourceConvertible {
+    let message: String
+
+    var localizedStringResource: LocalizedStringResource { "\(message)" }
+}
+
+/// Mirrors the JSON objects printed by `caffeinate2 ctl status`
+struct SessionInfo: Decodable {
+    let pid: Int32
+    let name: String?
+    let started: Int64
+    let until: Int64?
+    let types: [String]
+    let command: String?
+    let waitfor: Int32?
+}
+
+/// Run caffeinate2 with the given arguments and return its stdout
+private func caffeinate2(_ arguments: [String]) throws -> Data {
+    guard let path = searchPaths.first(where: FileManager.default.isExecutableFile) else {
+        throw Caffeinate2Error(message: "caffeinate2 isn't installed")
+    }
+
+    let process = Process()
+    process.executableURL = URL(fileURLWithPath: path)
+    process.arguments = arguments
+    let stdout = Pipe()
+    let stderr = Pipe()
+    process.standardOutput = stdout
+    process.standardError = stderr
+    try process.run()
+    let output = stdout.fileHandleForReading.readDataToEndOfFile()
+    let errors = stderr.fileHandleForReading.readDataToEndOfFile()
+    process.waitUntilExit()
+
+    if process.terminationStatus != 0 {
+        let message = String(decoding: errors, as: UTF8.self)
+            .trimmingCharacters(in: .whitespacesAndNewlines)
+        throw Caffeinate2Error(message: message.isEmpty ? "caffeinate2 failed" : message)
+    }
+    return output
+}
+
+private func sessions(named name: String?) throws -> [SessionInfo] {
+    var arguments = ["ctl", "status", "--format", "json"]
+    if let name, !name.isEmpty {
+        arguments += ["--name", name]
+    }
+    return try JSONDecoder().decode([SessionInfo].self, from: caffeinate2(arguments))
+}
+
+struct StartKeepAwake: AppIntent {
+    static var title: LocalizedStringResource = "Start Keep-Awake"
+    static var description = IntentDescription("Prevents your Mac from sleeping, optionally for a limited time.")
+
+    @Parameter(title: "Duration", description: "Like \"30m\" or \"2h\". Leave empty to keep awake until stopped.")
+    var duration: String?
+
+    @Parameter(title: "Session Name")
+    var name: String?
+
+    func perform() async throws -> some IntentResult & ReturnsValue<Bool> {
+        // toggle would stop a running session, so only call it when nothing matches
+        if try sessions(named: name).isEmpty {
+            var arguments = ["toggle"]
+            if let duration, !duration.isEmpty {
+                arguments.append(duration)
+            }
+            if let name, !name.isEmpty {
+                arguments += ["--name", name]
+            }
+            _ = try caffeinate2(arguments)
+        }
+        return .result(value: true)
+    }
+}
+
+struct StopKeepAwake: AppIntent {
+    static var title: LocalizedStringResource = "Stop Keep-Awake"
+    static var description = IntentDescription("Lets your Mac sleep again.")
+
+    @Parameter(title: "Session Name", description: "Leave empty to stop every session.")
+    var name: String?
+
+    func perform() async throws -> some IntentResult & ReturnsValue<Int> {
+        let running = try sessions(named: name)
+        if !running.isEmpty {
+            var arguments = ["ctl", "stop"]
+            if let name, !name.isEmpty {
+                arguments += ["--name", name]
+            }
+            _ = try caffeinate2(arguments)
+        }
+        return .result(value: running.count)
+    }
+}
+
+struct KeepAwakeStatus: AppIntent {
+    static var title: LocalizedStringResource = "Get Keep-Awake Status"
+    static var description = IntentDescription("Returns whether caffeinate2 is keeping your Mac awake.")
+
+    @Parameter(title: "Session Name")
+    var name: String?
+
+    func perform() async throws -> some IntentResult & ReturnsValue<Bool> & ProvidesDialog {
+        let running = try sessions(named: name)
+        guard let latest = running.last else {
+            return .result(value: false, dialog: "Sleep is allowed.")
+        }
+
+        var dialog = "Preventing \(latest.types.joined(separator: ", ")) sleep"
+        if let until = latest.until {
+            let end = Date(timeIntervalSince1970: TimeInterval(until))
+            dialog += " until \(end.formatted(date: .omitted, time: .shortened))"
+        }
+        return .result(value: true, dialog: "\(dialog).")
+    }
+}
+
+struct Caffeinate2Shortcuts: AppShortcutsProvider {
+    static var appShortcuts: [AppShortcut] {
+        AppShortcut(intent: StartKeepAwake(), phrases: ["Keep my Mac awake with \(.applicationName)"])
+        AppShortcut(intent: StopKeepAwake(), phrases: ["Let my Mac sleep with \(.applicationName)"])
+        AppShortcut(intent: KeepAwakeStatus(), phrases: ["Is \(.applicationName) keeping my Mac awake"])
+    }
+}
@@ -0,0 +1,121 @@
+use std::process;
+use std::str::FromStr;
+
+/// Sound played by a bare `--alarm sound`
+const DEFAULT_SOUND: &str = "Glass";
+
+/// How to tell the user a session is over, from --alarm
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alarm {
+    /// One of the sounds in /System/Library/Sounds, like "Glass"
+    Sound(String),
+    /// Fade the screens to white and back
+    Flash,
+    /// Speak this with the system voice
+    Say(String),
+}
+
+impl FromStr for Alarm {
+    type Err = String;
+
+    fn from_str(alarm: &str) -> Result<Alarm, String> {
+        match alarm.split_once(':') {
+            None if alarm == "sound" => Ok(Alarm::Sound(String::from(DEFAULT_SOUND))),
+            None if alarm == "flash" => Ok(Alarm::Flash),
+            Some(("sound", name)) if !name.is_empty() => Ok(Alarm::Sound(name.to_string())),
+            Some(("say", text)) if !text.is_empty() => Ok(Alarm::Say(text.to_string())),
+            _ => Err(format!(
+                "\"{alarm}\" isn't sound, sound:NAME, flash or say:TEXT"
+            )),
+        }
+    }
+}
+
+type CGDisplayFadeReservationToken = u32;
+
+/// kCGDisplayBlendNormal and kCGDisplayBlendSolidColor
+const BLEND_NORMAL: f32 = 0.0;
+const BLEND_SOLID_COLOR: f32 = 1.0;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGAcquireDisplayFadeReservation(
+        seconds: f32,
+        token: *mut CGDisplayFadeReservationToken,
+    ) -> i32;
+    fn CGDisplayFade(
+        token: CGDisplayFadeReservationToken,
+        duration: f32,
+        start_blend: f32,
+        end_blend: f32,
+        red: f32,
+        green: f32,
+        blue: f32,
+        synchronous: u32,
+    ) -> i32;
+    fn CGReleaseDisplayFadeReservation(token: CGDisplayFadeReservationToken) -> i32;
+}
+
+/// Fade every display to white and back, like the accessibility "flash the screen" option
+fn flash() -> Result<(), i32> {
+    let mut token: CGDisplayFadeReservationToken = 0;
+    let result = unsafe { CGAcquireDisplayFadeReservation(2.0, &mut token) };
+    trace!("CGAcquireDisplayFadeReservation = {result}, token {token}");
+    if result != 0 {
+        return Err(result);
+    }
+    let fade = |from, to| unsafe { CGDisplayFade(token, 0.25, from, to, 1.0, 1.0, 1.0, 1) };
+    let mut result = fade(BLEND_NORMAL, BLEND_SOLID_COLOR);
+    if result == 0 {
+        result = fade(BLEND_SOLID_COLOR, BLEND_NORMAL);
+    }
+    unsafe { CGReleaseDisplayFadeReservation(token) };
+    match result {
+        0 => Ok(()),
+        err => Err(err),
+    }
+}
+
+fn run(program: &str, args: &[&str]) {
+    match process::Command::new(program).args(args).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Warning: {program} failed for --alarm ({status})."),
+        Err(err) => eprintln!("Warning: Couldn't run {program} for --alarm: {err}"),
+    }
+}
+
+impl Alarm {
+    /// Go off. Waits until the sound or speech is over, so it isn't cut off by exiting.
+    pub fn ring(&self) {
+        match self {
+            Alarm::Sound(name) => {
+                let path = format!("/System/Library/Sounds/{name}.aiff");
+                run("/usr/bin/afplay", &[&path]);
+            }
+            Alarm::Flash => {
+                if let Err(code) = flash() {
+                    eprintln!("Warning: Couldn't flash the screen (error {code}).");
+                }
+            }
+            Alarm::Say(text) => run("/usr/bin/say", &["--", text]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Alarm;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("sound".parse(), Ok(Alarm::Sound(String::from("Glass"))));
+        assert_eq!("sound:Ping".parse(), Ok(Alarm::Sound(String::from("Ping"))));
+        assert_eq!("flash".parse(), Ok(Alarm::Flash));
+        assert_eq!(
+            "say:Time's up: stretch".parse(),
+            Ok(Alarm::Say(String::from("Time's up: stretch")))
+        );
+        assert!("say:".parse::<Alarm>().is_err());
+        assert!("beep".parse::<Alarm>().is_err());
+    }
+}
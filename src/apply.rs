@@ -0,0 +1,506 @@
+use crate::ctl;
+use crate::session::Session;
+use crate::wake_on_lan;
+use caffeinate2::duration_parser::try_parse_duration_ms;
+use caffeinate2::i18n::format_duration;
+use chrono::{NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::process;
+
+/// A policy file, listing the sessions that should be running
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Policy {
+    #[serde(default, rename = "session")]
+    sessions: Vec<DesiredSession>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct DesiredSession {
+    name: String,
+    /// What to keep awake. Defaults to system sleep, like the command line.
+    #[serde(default = "default_types")]
+    types: Vec<SleepType>,
+    /// Same format as --timeout
+    timeout: Option<String>,
+    /// Only run between these times of day
+    schedule: Option<Schedule>,
+    /// Keep the Mac reachable over the network, and wake it when its schedule starts
+    #[serde(default)]
+    wake_on_lan: bool,
+}
+
+#[derive(Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum SleepType {
+    Display,
+    Disk,
+    System,
+    SystemOnAc,
+    Entirely,
+    UserActive,
+    NetworkClient,
+}
+
+/// Times of day like "22:00" and "06:00". The end can be before the start to go past midnight.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Schedule {
+    start: String,
+    end: String,
+}
+
+fn default_types() -> Vec<SleepType> {
+    vec![SleepType::System]
+}
+
+impl SleepType {
+    pub fn flag(self) -> &'static str {
+        match self {
+            SleepType::Display => "--display",
+            SleepType::Disk => "--disk",
+            SleepType::System => "--system",
+            SleepType::SystemOnAc => "--system-on-ac",
+            SleepType::Entirely => "--entirely",
+            SleepType::UserActive => "--user-active",
+            SleepType::NetworkClient => "--network-client",
+        }
+    }
+
+    /// The sleep type an --assert assertion type amounts to, if it's one of ours
+    pub fn for_assertion(assertion_type: &str) -> Option<SleepType> {
+        match assertion_type {
+            "PreventUserIdleDisplaySleep" => Some(SleepType::Display),
+            "PreventDiskIdle" => Some(SleepType::Disk),
+            "PreventUserIdleSystemSleep" => Some(SleepType::System),
+            "NetworkClientActive" => Some(SleepType::NetworkClient),
+            _ => None,
+        }
+    }
+
+    /// How the session file lists it (see `sleep_types` in main.rs)
+    pub fn session_name(self) -> &'static str {
+        match self {
+            SleepType::Display => "display",
+            SleepType::Disk => "disk",
+            SleepType::System => "system",
+            SleepType::SystemOnAc => "system on AC",
+            SleepType::Entirely => "entire system",
+            SleepType::UserActive => "user active",
+            SleepType::NetworkClient => "network client",
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| format!("\"{time}\" isn't a time like \"22:00\""))
+}
+
+/// Milliseconds left in the window if `now` is inside it
+fn window_remaining(now: NaiveTime, start: NaiveTime, end: NaiveTime) -> Option<i64> {
+    const DAY_MILLIS: i64 = 86_400_000;
+    let millis = |time: NaiveTime| {
+        time.num_seconds_from_midnight() as i64 * 1000 + (time.nanosecond() / 1_000_000) as i64
+    };
+    let (now, start, end) = (millis(now), millis(start), millis(end));
+    let inside = if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    };
+    inside.then(|| (end - now).rem_euclid(DAY_MILLIS))
+}
+
+/// When a window starting at `start` next opens, after `now`
+fn next_window_start(now: NaiveDateTime, start: NaiveTime) -> NaiveDateTime {
+    let today = now.date().and_time(start);
+    if today > now {
+        today
+    } else {
+        today + TimeDelta::days(1)
+    }
+}
+
+impl DesiredSession {
+    fn validate(&self) -> Result<(), String> {
+        if self.types.is_empty() {
+            return Err(String::from("types can't be empty"));
+        }
+        if let Some(timeout) = &self.timeout {
+            try_parse_duration_ms(timeout)?;
+        }
+        if let Some(schedule) = &self.schedule {
+            parse_time(&schedule.start)?;
+            parse_time(&schedule.end)?;
+        }
+        Ok(())
+    }
+
+    /// Whether it should be running at this time of day, and if so, for how long (in milliseconds)
+    fn wanted_at(&self, now: NaiveTime) -> Option<Option<i64>> {
+        let timeout = self
+            .timeout
+            .as_ref()
+            .map(|timeout| try_parse_duration_ms(timeout).unwrap());
+        let Some(schedule) = &self.schedule else {
+            return Some(timeout);
+        };
+        let remaining = window_remaining(
+            now,
+            parse_time(&schedule.start).unwrap(),
+            parse_time(&schedule.end).unwrap(),
+        )?;
+        Some(Some(
+            timeout.map_or(remaining, |timeout| timeout.min(remaining)),
+        ))
+    }
+
+    fn sorted_types(&self) -> Vec<SleepType> {
+        let mut types = self.types.clone();
+        types.sort();
+        types.dedup();
+        types
+    }
+
+    fn matches(&self, session: &Session) -> bool {
+        let types: Vec<&str> = self
+            .sorted_types()
+            .into_iter()
+            .map(SleepType::session_name)
+            .collect();
+        session.types == types
+    }
+
+    fn args(&self, timeout: Option<i64>, managed_by: &str) -> Vec<String> {
+        let mut args = vec![String::from("--name"), self.name.clone()];
+        args.extend(
+            self.sorted_types()
+                .into_iter()
+                .map(|sleep_type| sleep_type.flag().to_string()),
+        );
+        if let Some(timeout) = timeout {
+            args.extend([String::from("-t"), format!("{timeout}ms")]);
+        }
+        if self.wake_on_lan {
+            args.push(String::from("--maintain-wake-on-lan"));
+        }
+        args.extend([String::from("--managed-by"), managed_by.to_string()]);
+        args
+    }
+}
+
+/// What happens to a session during a simulated run
+#[derive(Debug, PartialEq)]
+enum Event {
+    /// `apply` starts it, to run until then (or until it's stopped)
+    Start(Option<NaiveDateTime>),
+    /// Its timeout runs out
+    TimedOut,
+    /// `apply` stops it because it's outside its schedule
+    Stop,
+}
+
+/// Run `apply` once a minute on a virtual clock, starting at `from` with nothing running
+fn simulate(
+    sessions: &[DesiredSession],
+    from: NaiveDateTime,
+    span: TimeDelta,
+) -> Vec<(NaiveDateTime, &str, Event)> {
+    let mut running: HashMap<&str, Option<NaiveDateTime>> = HashMap::new();
+    let mut events = Vec::new();
+    let mut now = from;
+    while now <= from + span {
+        for desired in sessions {
+            let name = desired.name.as_str();
+            if running
+                .get(name)
+                .copied()
+                .flatten()
+                .is_some_and(|until| until <= now)
+            {
+                running.remove(name);
+                events.push((now, name, Event::TimedOut));
+            }
+            match (desired.wanted_at(now.time()), running.contains_key(name)) {
+                (Some(timeout), false) => {
+                    let until = timeout.map(|timeout| now + TimeDelta::milliseconds(timeout));
+                    running.insert(name, until);
+                    events.push((now, name, Event::Start(until)));
+                }
+                (None, true) => {
+                    running.remove(name);
+                    events.push((now, name, Event::Stop));
+                }
+                _ => {}
+            }
+        }
+        now += TimeDelta::minutes(1);
+    }
+    events
+}
+
+fn print_simulation(sessions: &[DesiredSession], span: TimeDelta) {
+    let from = chrono::Local::now()
+        .naive_local()
+        .with_second(0)
+        .and_then(|now| now.with_nanosecond(0))
+        .unwrap();
+    println!(
+        "Simulating the next {}, with `apply` run every minute and nothing running at first:",
+        format_duration(span)
+    );
+    let events = simulate(sessions, from, span);
+    if events.is_empty() {
+        println!("Nothing would be started.");
+    }
+    for (time, name, event) in events {
+        let time = time.format("%a %H:%M");
+        match event {
+            Event::Start(Some(until)) => println!(
+                "{time}  Start \"{name}\" until {}.",
+                until.format("%a %H:%M")
+            ),
+            Event::Start(None) => println!("{time}  Start \"{name}\"."),
+            Event::TimedOut => println!("{time}  \"{name}\" times out."),
+            Event::Stop => println!("{time}  Stop \"{name}\"."),
+        }
+    }
+}
+
+fn start(desired: &DesiredSession, timeout: Option<i64>, managed_by: &str, dry_run: bool) {
+    if dry_run {
+        println!("Would start \"{}\".", desired.name);
+        return;
+    }
+    match ctl::spawn_background(&desired.args(timeout, managed_by)) {
+        Ok(pid) => println!("Started \"{}\" (PID {pid}).", desired.name),
+        Err(err) => eprintln!("Error: Couldn't start \"{}\": {err}", desired.name),
+    }
+}
+
+/// Wake the Mac when the session's window next opens, so it's there to be started
+fn schedule_wake(desired: &DesiredSession, dry_run: bool) {
+    let Some(schedule) = desired.schedule.as_ref().filter(|_| desired.wake_on_lan) else {
+        return;
+    };
+    let at = next_window_start(
+        chrono::Local::now().naive_local(),
+        parse_time(&schedule.start).unwrap(),
+    );
+    if dry_run {
+        println!(
+            "Would schedule a wake at {} for \"{}\".",
+            at.format("%a %H:%M"),
+            desired.name
+        );
+        return;
+    }
+    match wake_on_lan::schedule_wake(at) {
+        Ok(true) => println!(
+            "Scheduled a wake at {} for \"{}\".",
+            at.format("%a %H:%M"),
+            desired.name
+        ),
+        Ok(false) => {}
+        Err(err) => eprintln!(
+            "Warning: Couldn't schedule a wake for \"{}\" ({err}). Scheduling wakes needs root.",
+            desired.name
+        ),
+    }
+}
+
+fn stop(session: &Session, dry_run: bool) -> bool {
+    let name = session.name.as_deref().unwrap_or_default();
+    if dry_run {
+        println!("Would stop \"{name}\" (PID {}).", session.pid);
+        return true;
+    }
+    match ctl::stop_and_wait(session.pid) {
+        Ok(()) => {
+            println!("Stopped \"{name}\" (PID {}).", session.pid);
+            true
+        }
+        Err(err) => {
+            eprintln!(
+                "Error: Couldn't stop \"{name}\" (PID {}): {err}",
+                session.pid
+            );
+            false
+        }
+    }
+}
+
+/// Start the sessions the policy wants that aren't running, restart ones whose types changed,
+/// and stop ones this policy started that it doesn't want anymore.
+/// Sessions started some other way are left alone.
+/// With `simulate`, only prints when sessions would start and stop over that long.
+pub fn apply(path: &Path, dry_run: bool, simulate: Option<&str>) {
+    let policy: Policy = fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| toml::from_str(&contents).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            eprintln!("Error: Couldn't read {}: {err}", path.display());
+            process::exit(1);
+        });
+
+    let mut names = HashSet::new();
+    for desired in &policy.sessions {
+        if !names.insert(&desired.name) {
+            eprintln!("Error: Session \"{}\" is declared twice.", desired.name);
+            process::exit(1);
+        }
+        if let Err(err) = desired.validate() {
+            eprintln!("Error: Session \"{}\": {err}", desired.name);
+            process::exit(1);
+        }
+    }
+
+    if let Some(span) = simulate {
+        match try_parse_duration_ms(span) {
+            Ok(span) => print_simulation(&policy.sessions, TimeDelta::milliseconds(span)),
+            Err(err) => {
+                eprintln!("Error: Couldn't parse --simulate: {err}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // The same policy file should always be recognized, however it's spelled
+    let managed_by = fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string();
+    let running = Session::list();
+    let mut wanted = HashSet::new();
+
+    for desired in &policy.sessions {
+        schedule_wake(desired, dry_run);
+        let Some(timeout) = desired.wanted_at(chrono::Local::now().time()) else {
+            continue;
+        };
+        wanted.insert(desired.name.as_str());
+
+        match running
+            .iter()
+            .find(|session| session.name.as_ref() == Some(&desired.name))
+        {
+            Some(session) if session.managed_by.as_deref() != Some(managed_by.as_str()) => {
+                println!(
+                    "\"{}\" is already running (PID {}) but wasn't started by this policy, leaving it alone.",
+                    desired.name, session.pid
+                );
+            }
+            Some(session) if desired.matches(session) => {
+                println!("\"{}\" is up to date.", desired.name);
+            }
+            Some(session) => {
+                if stop(session, dry_run) {
+                    start(desired, timeout, &managed_by, dry_run);
+                }
+            }
+            None => start(desired, timeout, &managed_by, dry_run),
+        }
+    }
+
+    for session in &running {
+        let declared = session
+            .name
+            .as_deref()
+            .is_some_and(|name| wanted.contains(name));
+        if session.managed_by.as_deref() == Some(managed_by.as_str()) && !declared {
+            stop(session, dry_run);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DesiredSession, Event, Schedule, SleepType};
+    use chrono::{NaiveDate, NaiveTime, TimeDelta};
+
+    #[test]
+    fn test_simulate() {
+        let sessions = [
+            DesiredSession {
+                name: String::from("nightly"),
+                types: vec![SleepType::System],
+                timeout: None,
+                schedule: Some(Schedule {
+                    start: String::from("22:00"),
+                    end: String::from("06:00"),
+                }),
+                wake_on_lan: false,
+            },
+            DesiredSession {
+                name: String::from("short"),
+                types: vec![SleepType::Display],
+                timeout: Some(String::from("20h")),
+                schedule: None,
+                wake_on_lan: false,
+            },
+        ];
+        let at = |day, hour| {
+            NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap()
+        };
+        assert_eq!(
+            super::simulate(&sessions, at(1, 12), TimeDelta::hours(24)),
+            vec![
+                (at(1, 12), "short", Event::Start(Some(at(2, 8)))),
+                (at(1, 22), "nightly", Event::Start(Some(at(2, 6)))),
+                (at(2, 6), "nightly", Event::TimedOut),
+                (at(2, 8), "short", Event::TimedOut),
+                (at(2, 8), "short", Event::Start(Some(at(3, 4)))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_window_start() {
+        let at = |day, hour| {
+            NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap()
+        };
+        let start = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
+        assert_eq!(super::next_window_start(at(1, 12), start), at(1, 22));
+        assert_eq!(super::next_window_start(at(1, 22), start), at(2, 22));
+    }
+
+    #[test]
+    fn test_window_remaining() {
+        let time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+        let hour = 3_600_000;
+
+        assert_eq!(
+            super::window_remaining(time(10, 0), time(9, 0), time(17, 0)),
+            Some(7 * hour)
+        );
+        assert_eq!(
+            super::window_remaining(time(18, 0), time(9, 0), time(17, 0)),
+            None
+        );
+        // Overnight windows
+        assert_eq!(
+            super::window_remaining(time(23, 0), time(22, 0), time(6, 0)),
+            Some(7 * hour)
+        );
+        assert_eq!(
+            super::window_remaining(time(1, 0), time(22, 0), time(6, 0)),
+            Some(5 * hour)
+        );
+        assert_eq!(
+            super::window_remaining(time(12, 0), time(22, 0), time(6, 0)),
+            None
+        );
+    }
+}
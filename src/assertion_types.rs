@@ -0,0 +1,69 @@
+/// Assertion types IOKit currently understands, for --assert
+pub const ASSERTION_TYPES: [&str; 8] = [
+    "PreventUserIdleSystemSleep",
+    "PreventUserIdleDisplaySleep",
+    "PreventSystemSleep",
+    "PreventDiskIdle",
+    "NetworkClientActive",
+    "BackgroundTask",
+    "ApplePushServiceTask",
+    "InteractivePushServiceTask",
+];
+
+/// Older names and what they're called now (see the deprecated constants in IOPMLib.h)
+const ALIASES: [(&str, &str); 3] = [
+    ("NoIdleSleepAssertion", "PreventUserIdleSystemSleep"),
+    ("NoDisplaySleepAssertion", "PreventUserIdleDisplaySleep"),
+    ("DenySystemSleep", "PreventSystemSleep"),
+];
+
+/// The current name for an assertion type, accepting legacy names and the
+/// `kIOPMAssertionType` constant names (like "kIOPMAssertionTypeNoIdleSleep")
+pub fn canonical(name: &str) -> Option<&'static str> {
+    if let Some(constant) = name.strip_prefix("kIOPMAssertionType") {
+        // The constants for the legacy names drop the "Assertion" suffix
+        return canonical(constant).or_else(|| canonical(&format!("{constant}Assertion")));
+    }
+    if let Some(&current) = ASSERTION_TYPES.iter().find(|&&current| current == name) {
+        return Some(current);
+    }
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, current)| *current)
+}
+
+/// Like `canonical`, but with an error listing what's accepted
+pub fn try_canonical(name: &str) -> Result<&'static str, String> {
+    canonical(name).ok_or_else(|| {
+        format!(
+            "unknown assertion type \"{name}\" (expected one of {})",
+            ASSERTION_TYPES.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_canonical() {
+        assert_eq!(
+            super::canonical("PreventSystemSleep"),
+            Some("PreventSystemSleep")
+        );
+        assert_eq!(
+            super::canonical("NoIdleSleepAssertion"),
+            Some("PreventUserIdleSystemSleep")
+        );
+        assert_eq!(
+            super::canonical("kIOPMAssertionTypeNoDisplaySleep"),
+            Some("PreventUserIdleDisplaySleep")
+        );
+        assert_eq!(
+            super::canonical("kIOPMAssertionTypePreventDiskIdle"),
+            Some("PreventDiskIdle")
+        );
+        assert_eq!(super::canonical("preventsystemsleep"), None);
+        assert!(super::try_canonical("Nope").is_err());
+    }
+}
@@ -0,0 +1,486 @@
+use crate::journal;
+use crate::power_management::{self, IOKit};
+use caffeinate2::shared_settings::{Change, Shared};
+use std::collections::BTreeMap;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many times a power management call is tried when it keeps failing with a transient error
+const RETRY_ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubled after each one (so about 1.5 seconds in total)
+const RETRY_FIRST_DELAY: Duration = Duration::from_millis(100);
+
+/// Make a power management call, retrying with exponential backoff while it fails with
+/// transient errors (like while powerd restarts). Reports it if they never went away.
+fn with_retries<T>(what: &str, mut call: impl FnMut() -> Result<T, u32>) -> Result<T, u32> {
+    let mut delay = RETRY_FIRST_DELAY;
+    for attempt in 1.. {
+        match call() {
+            Err(code) if power_management::is_transient(code) && attempt < RETRY_ATTEMPTS => {
+                trace!("{what}: failed with {code:#X}, retrying in {delay:?}");
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(code) if power_management::is_transient(code) => {
+                eprintln!(
+                    "Warning: Couldn't {what} after {RETRY_ATTEMPTS} tries ({}).",
+                    power_management::describe_error(code)
+                );
+                return Err(code);
+            }
+            result => return result,
+        }
+    }
+    unreachable!()
+}
+
+/// The different ways of keeping the Mac awake
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// A regular power management assertion, like PreventUserIdleSystemSleep
+    Assertion(&'static str),
+    /// IOPMAssertionDeclareUserActivity
+    UserActivity,
+    /// The SleepDisabled system setting, which needs root
+    SleepDisabled,
+}
+
+impl Kind {
+    /// The closest thing to try when this is denied
+    fn fallback(self) -> Option<Kind> {
+        match self {
+            Kind::SleepDisabled => Some(Kind::Assertion("PreventSystemSleep")),
+            Kind::Assertion("PreventSystemSleep") => {
+                Some(Kind::Assertion("PreventUserIdleSystemSleep"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a sleep type (as `status` shows them, like "display") or an assertion type
+    /// names this
+    #[cfg(feature = "scripting")]
+    pub fn is_called(self, name: &str) -> bool {
+        match self {
+            Kind::Assertion(assertion_type) => {
+                assertion_type == name
+                    || matches!(
+                        (assertion_type, name),
+                        ("PreventUserIdleDisplaySleep", "display")
+                            | (
+                                "PreventUserIdleSystemSleep" | "PreventSystemSleep",
+                                "system"
+                            )
+                            | ("PreventDiskIdle", "disk")
+                    )
+            }
+            Kind::UserActivity => name == "user active",
+            Kind::SleepDisabled => name == "entire system",
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Kind::Assertion(assertion_type) => format!("create a {assertion_type} assertion"),
+            Kind::UserActivity => String::from("declare user activity"),
+            Kind::SleepDisabled => String::from("disable sleep entirely"),
+        }
+    }
+}
+
+/// One way of keeping the Mac awake, held only while all of its conditions are true
+/// (like "on AC power" or "backup drive mounted")
+pub struct Assertion {
+    iokit: IOKit,
+    kind: Kind,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    id: Option<u32>,
+    sleep_disabled: bool,
+    conditions: BTreeMap<&'static str, bool>,
+    /// Set once the session is over, so a late condition change can't bring it back
+    closed: bool,
+    /// What was used instead after the original kind was denied
+    fallback: Option<Kind>,
+    /// Set when nothing permitted was left to fall back to
+    unavailable: bool,
+    /// When it was last created, while it's held
+    held_since: Option<Instant>,
+    /// How long it was held before that
+    held_for: Duration,
+    /// When powerd should release it by itself (see --kernel-timeout)
+    kernel_deadline: Option<Instant>,
+    /// Set when SleepDisabled couldn't be turned back off, so the journal has to stay for repair
+    left_sleep_disabled: bool,
+}
+
+impl State {
+    fn is_held(&self) -> bool {
+        self.id.is_some() || self.sleep_disabled
+    }
+}
+
+impl Assertion {
+    pub fn new(kind: Kind) -> Assertion {
+        Assertion {
+            iokit: IOKit::new(),
+            kind,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // A panic elsewhere shouldn't stop us from releasing things
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Set a condition's initial value, without creating or releasing anything yet
+    pub fn add_condition(&self, condition: &'static str, value: bool) {
+        self.lock().conditions.insert(condition, value);
+    }
+
+    /// Update a condition, creating or releasing the assertion to match.
+    /// Returns whether it was created or released.
+    pub fn set_condition(&self, condition: &'static str, value: bool) -> bool {
+        let mut state = self.lock();
+        state.conditions.insert(condition, value);
+        self.apply(&mut state)
+    }
+
+    /// Create the assertion if all its conditions are true, release it otherwise
+    pub fn refresh(&self) -> bool {
+        let mut state = self.lock();
+        self.apply(&mut state)
+    }
+
+    fn apply(&self, state: &mut State) -> bool {
+        let wanted =
+            !state.closed && !state.unavailable && state.conditions.values().all(|value| *value);
+        if wanted == state.is_held() {
+            return false;
+        }
+        if wanted {
+            self.hold(state);
+        } else {
+            self.release(state);
+        }
+        if state.is_held() {
+            state.held_since.get_or_insert_with(Instant::now);
+        } else if let Some(since) = state.held_since.take() {
+            state.held_for += since.elapsed();
+        }
+        true
+    }
+
+    fn try_hold(&self, kind: Kind, state: &mut State) -> Result<(), u32> {
+        let what = kind.describe();
+        match kind {
+            Kind::Assertion(assertion_type) => {
+                let kernel_deadline = state.kernel_deadline;
+                state.id = Some(with_retries(&what, || match kernel_deadline {
+                    Some(deadline) => self.iokit.create_assertion_with_timeout(
+                        assertion_type,
+                        deadline.saturating_duration_since(Instant::now()),
+                    ),
+                    None => self.iokit.create_assertion(assertion_type, true),
+                })?);
+            }
+            Kind::UserActivity => {
+                state.id = Some(with_retries(&what, || {
+                    self.iokit.declare_user_activity(true)
+                })?)
+            }
+            Kind::SleepDisabled => {
+                // Recorded first, so a session that ends meanwhile leaves it on for us
+                let _lock = journal::lock();
+                journal::set_sleep_disabled(std::process::id() as i32, true);
+                with_retries(&what, || self.iokit.set_sleep_disabled(true))?;
+                state.sleep_disabled = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hold the assertion, or the closest thing that's permitted if it's denied
+    /// (like in a sandbox or a managed environment)
+    fn hold(&self, state: &mut State) {
+        let mut kind = state.fallback.unwrap_or(self.kind);
+        loop {
+            let Err(code) = self.try_hold(kind, state) else {
+                return;
+            };
+            if power_management::is_transient(code) {
+                // Not denied, just unavailable for now. The next refresh tries again.
+                return;
+            }
+            trace!("{:?} denied with code {:#X}", kind, code);
+            if kind == Kind::SleepDisabled
+                && code == power_management::IORETURN_NOT_PRIVILEGED
+                && !power_management::is_sandboxed()
+            {
+                eprintln!(
+                    "Error: Insufficient privileges to disable sleep. Try running with sudo."
+                );
+                process::exit(1);
+            }
+
+            let reason = power_management::describe_error(code);
+            match kind.fallback() {
+                Some(fallback) => {
+                    eprintln!(
+                        "Warning: Couldn't {} ({reason}). Trying to {} instead.",
+                        kind.describe(),
+                        fallback.describe()
+                    );
+                    state.fallback = Some(fallback);
+                    kind = fallback;
+                }
+                None => {
+                    eprintln!(
+                        "Warning: Couldn't {} ({reason}). Continuing without it.",
+                        kind.describe()
+                    );
+                    state.unavailable = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn release(&self, state: &mut State) {
+        if let Some(id) = state.id.take() {
+            if let Err(code) = with_retries(&format!("release assertion {id}"), || {
+                self.iokit.release_assertion(id)
+            }) {
+                // Transient failures were reported already
+                if !power_management::is_transient(code) {
+                    eprintln!(
+                        "Warning: Couldn't release assertion {id} ({}). It will be released when caffeinate2 exits.",
+                        power_management::describe_error(code)
+                    );
+                }
+            }
+        }
+        if state.sleep_disabled {
+            // Other sessions might still want sleep disabled
+            let _lock = journal::lock();
+            let pid = std::process::id() as i32;
+            let last_owner =
+                journal::owners().remove(Shared::SleepDisabled, pid) == Change::Restore;
+            state.left_sleep_disabled = false;
+            if !last_owner {
+                trace!("sleep stays disabled for other sessions");
+            } else if self.iokit.get_sleep_disabled() {
+                if let Err(code) =
+                    with_retries("re-enable sleep", || self.iokit.set_sleep_disabled(false))
+                {
+                    eprintln!(
+                        "Error: Couldn't re-enable sleep ({}). Run `sudo pmset -a disablesleep 0` to fix it.",
+                        power_management::describe_error(code)
+                    );
+                    state.left_sleep_disabled = true;
+                }
+            }
+            if !state.left_sleep_disabled {
+                journal::set_sleep_disabled(pid, false);
+            }
+            state.sleep_disabled = false;
+        }
+    }
+
+    /// Release the assertion for good. Returns false if SleepDisabled was left on.
+    pub fn close(&self) -> bool {
+        let mut state = self.lock();
+        state.closed = true;
+        self.apply(&mut state);
+        !state.left_sleep_disabled
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.lock().id
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.lock().is_held()
+    }
+
+    /// How long it's been held in total
+    fn held_for(&self) -> Duration {
+        let state = self.lock();
+        state.held_for
+            + state
+                .held_since
+                .map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Forget a held assertion without releasing it and create it again, because its ID is
+    /// no longer valid (like after powerd restarts). Returns whether it's held again.
+    fn recreate(&self) -> bool {
+        let mut state = self.lock();
+        if !state.is_held() {
+            return false;
+        }
+        state.id = None;
+        state.sleep_disabled = false;
+        self.apply(&mut state);
+        state.is_held()
+    }
+
+    /// Have powerd release the assertion by itself at `deadline`, moving the timeout of one
+    /// that's already held
+    fn set_kernel_deadline(&self, deadline: Instant) {
+        let mut state = self.lock();
+        state.kernel_deadline = Some(deadline);
+        let (Kind::Assertion(_), Some(id)) = (state.fallback.unwrap_or(self.kind), state.id) else {
+            return;
+        };
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if let Err(code) = with_retries("move the assertion's timeout", || {
+            self.iokit.set_assertion_timeout(id, timeout)
+        }) {
+            if !power_management::is_transient(code) {
+                eprintln!(
+                    "Warning: Couldn't move the assertion's timeout ({}).",
+                    power_management::describe_error(code)
+                );
+            }
+        }
+    }
+
+    /// Declare user activity again if that's what this is and it's held, so it doesn't time out
+    fn redeclare_user_activity(&self) {
+        let mut state = self.lock();
+        let (Kind::UserActivity, Some(id)) = (state.fallback.unwrap_or(self.kind), state.id) else {
+            return;
+        };
+        match with_retries("declare user activity", || {
+            self.iokit.redeclare_user_activity(true, id)
+        }) {
+            Ok(id) => state.id = Some(id),
+            Err(code) if !power_management::is_transient(code) => eprintln!(
+                "Warning: Couldn't declare user activity again ({}).",
+                power_management::describe_error(code)
+            ),
+            Err(_) => {}
+        }
+    }
+}
+
+/// All of a session's assertions
+#[derive(Clone, Default)]
+pub struct Assertions(Vec<Arc<Assertion>>);
+
+impl Assertions {
+    pub fn push(&mut self, assertion: Arc<Assertion>) {
+        self.0.push(assertion);
+    }
+
+    /// Set the initial value of a condition shared by every assertion
+    pub fn add_condition(&self, condition: &'static str, value: bool) {
+        for assertion in &self.0 {
+            assertion.add_condition(condition, value);
+        }
+    }
+
+    /// Update a condition shared by every assertion. Returns whether any changed.
+    pub fn set_condition(&self, condition: &'static str, value: bool) -> bool {
+        self.set_condition_by_kind(condition, |_| value)
+    }
+
+    /// Set the initial value of a condition that depends on what kind each assertion is
+    #[cfg(feature = "scripting")]
+    pub fn add_condition_by_kind(&self, condition: &'static str, value: impl Fn(Kind) -> bool) {
+        for assertion in &self.0 {
+            assertion.add_condition(condition, value(assertion.kind));
+        }
+    }
+
+    /// Update a condition that depends on what kind each assertion is. Returns whether any changed.
+    pub fn set_condition_by_kind(
+        &self,
+        condition: &'static str,
+        value: impl Fn(Kind) -> bool,
+    ) -> bool {
+        let mut changed = false;
+        for assertion in &self.0 {
+            changed |= assertion.set_condition(condition, value(assertion.kind));
+        }
+        changed
+    }
+
+    /// Create every assertion whose conditions are all true
+    pub fn refresh(&self) {
+        for assertion in &self.0 {
+            assertion.refresh();
+        }
+    }
+
+    /// Release them all for good. Returns false if SleepDisabled was left on.
+    pub fn close(&self) -> bool {
+        let mut restored = true;
+        for assertion in &self.0 {
+            restored &= assertion.close();
+        }
+        restored
+    }
+
+    /// Have powerd release the assertions by itself at `deadline`, even if we can't
+    pub fn set_kernel_deadline(&self, deadline: Instant) {
+        for assertion in &self.0 {
+            assertion.set_kernel_deadline(deadline);
+        }
+    }
+
+    /// Declare user activity again, restarting its timeout
+    pub fn redeclare_user_activity(&self) {
+        for assertion in &self.0 {
+            assertion.redeclare_user_activity();
+        }
+    }
+
+    /// Keep user activity declarations from timing out, for as long as the process runs
+    pub fn keep_user_active(&self, interval: std::time::Duration) {
+        let assertions = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            assertions.redeclare_user_activity();
+        });
+    }
+
+    /// Create the held assertions again (see `Assertion::recreate`). Returns how many are held again.
+    pub fn recreate(&self) -> usize {
+        self.0
+            .iter()
+            .filter(|assertion| assertion.recreate())
+            .count()
+    }
+
+    /// Whether anything is currently keeping the Mac awake
+    pub fn any_held(&self) -> bool {
+        self.0.iter().any(|assertion| assertion.is_held())
+    }
+
+    /// How long sleep was prevented, going by the assertion held the longest
+    pub fn held_for(&self) -> Duration {
+        self.0
+            .iter()
+            .map(|assertion| assertion.held_for())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// IDs of the assertions currently held
+    pub fn ids(&self) -> Vec<u32> {
+        self.0
+            .iter()
+            .filter_map(|assertion| assertion.id())
+            .collect()
+    }
+}
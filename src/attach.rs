@@ -0,0 +1,196 @@
+use crate::session::{self, Selector, Session};
+use crate::smc;
+use crate::status;
+use caffeinate2::i18n::format_duration;
+use std::fs::{self, File, Metadata};
+use std::io::{self, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// How much of the output that's already there to show when attaching
+const BACKLOG_LINES: usize = 20;
+
+/// How often (in redraws, a second apart) thermal pressure, temperatures and fan speeds are shown
+const READINGS_REDRAWS: usize = 60;
+
+/// Follows the file a session's command output goes to, like `tail -f`
+struct OutputFollower {
+    path: PathBuf,
+    file: File,
+    offset: u64,
+}
+
+impl OutputFollower {
+    /// Open it, starting with its last few lines
+    fn open(path: &str) -> Option<OutputFollower> {
+        let mut file = File::open(path).ok()?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).ok()?;
+        let start = contents
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, byte)| **byte == b'\n')
+            .nth(BACKLOG_LINES)
+            .map_or(0, |(index, _)| index + 1);
+        Some(OutputFollower {
+            path: PathBuf::from(path),
+            file,
+            offset: start as u64,
+        })
+    }
+
+    /// What's been added to the open file since last time
+    fn read_open_file(&mut self) -> Vec<u8> {
+        let size = self.file.metadata().map_or(0, |metadata| metadata.len());
+        if size < self.offset {
+            // It was truncated, so start over
+            self.offset = 0;
+        }
+        let mut new = Vec::new();
+        if self.file.seek(SeekFrom::Start(self.offset)).is_ok() {
+            let _ = self.file.read_to_end(&mut new);
+        }
+        self.offset += new.len() as u64;
+        new
+    }
+
+    /// Whatever was written since last time
+    fn read_new(&mut self) -> Vec<u8> {
+        let mut new = self.read_open_file();
+        // Rotating (with --log-max-size) renames the file and starts a new one at the path, so
+        // the rest of the old one is read first and then the new one is followed
+        let inode = |metadata: io::Result<Metadata>| metadata.ok().map(|metadata| metadata.ino());
+        let current = inode(fs::metadata(&self.path));
+        if current.is_some() && current != inode(self.file.metadata()) {
+            if let Ok(file) = File::open(&self.path) {
+                self.file = file;
+                self.offset = 0;
+                new.extend(self.read_open_file());
+            }
+        }
+        new
+    }
+}
+
+/// The session's status line, with how long is left if it has a timeout
+fn status_line(session: &Session) -> String {
+    let description = status::describe(session);
+    match session.until {
+        Some(until) => {
+            let remaining = (until - chrono::Local::now().timestamp()).max(0);
+            format!(
+                "{description} ({} left)",
+                format_duration(chrono::Duration::seconds(remaining))
+            )
+        }
+        None => description,
+    }
+}
+
+/// Follow a running session's status (and its command's output) until it ends. This only
+/// reads its files, so leaving with Ctrl+C doesn't affect it.
+pub fn attach(selector: &Selector) {
+    let session = Session::select_one(selector).unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        process::exit(1);
+    });
+    let pid = session.pid;
+    println!("Attached to session {pid}. Press Ctrl+C to detach (it keeps running).");
+
+    let mut output = session.output.as_deref().and_then(OutputFollower::open);
+    let print_output = |output: &mut Option<OutputFollower>| {
+        if let Some(new) = output.as_mut().map(OutputFollower::read_new) {
+            let _ = io::stdout().write_all(&new);
+        }
+    };
+    // Redraw one line in a terminal, otherwise only print changes
+    let terminal = io::stdout().is_terminal();
+    let mut last_line = String::new();
+    let smc = smc::Smc::open();
+    for redraw in 0.. {
+        let (Some(session), true) = (Session::load(pid), session::is_alive(pid)) else {
+            break;
+        };
+        if terminal {
+            // Output goes above the status line
+            print!("\r\x1b[K");
+        }
+        print_output(&mut output);
+        if redraw % READINGS_REDRAWS == 0 {
+            // Same as the session's own -v, for keeping an eye on the machine it's keeping awake
+            let readings = smc::readings(smc.as_ref()).describe();
+            if !readings.is_empty() {
+                println!("Health: {readings}.");
+            }
+        }
+        let line = status_line(&session);
+        if terminal {
+            print!("{line}");
+            let _ = io::stdout().flush();
+        } else if line != last_line {
+            println!("{line}");
+        }
+        last_line = line;
+        thread::sleep(Duration::from_secs(1));
+    }
+    if terminal {
+        print!("\r\x1b[K");
+    }
+    // The file is still open, so whatever it printed last can be shown even though it's gone
+    print_output(&mut output);
+    println!("Session {pid} ended.");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn test_output_follower() {
+        let path =
+            std::env::temp_dir().join(format!("caffeinate2-attach-{}.out", std::process::id()));
+        let lines: String = (1..=30).map(|line| format!("{line}\n")).collect();
+        fs::write(&path, lines).unwrap();
+
+        let mut follower = super::OutputFollower::open(path.to_str().unwrap()).unwrap();
+        let backlog = String::from_utf8(follower.read_new()).unwrap();
+        assert_eq!(backlog.lines().count(), super::BACKLOG_LINES);
+        assert!(backlog.starts_with("11\n"));
+        assert!(follower.read_new().is_empty());
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"31\n").unwrap();
+        assert_eq!(follower.read_new(), b"31\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_output_follower_rotation() {
+        let path = std::env::temp_dir().join(format!(
+            "caffeinate2-attach-rotate-{}.out",
+            std::process::id()
+        ));
+        let rotated = path.with_extension("out.1");
+        fs::write(&path, "1\n").unwrap();
+        let mut follower = super::OutputFollower::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(follower.read_new(), b"1\n");
+
+        // Like OutputLog::rotate, with a last line written just before
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"2\n").unwrap();
+        fs::rename(&path, &rotated).unwrap();
+        fs::write(&path, "3\n").unwrap();
+        assert_eq!(follower.read_new(), b"2\n3\n");
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"4\n").unwrap();
+        assert_eq!(follower.read_new(), b"4\n");
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+}
@@ -0,0 +1,33 @@
+use crate::assertions::Assertions;
+use crate::idle;
+use crate::low_power;
+use caffeinate2::idle_sleep::{self, BlockedSleeps};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often to sample the idle time. The shortest sleep timer is a minute.
+const INTERVAL: Duration = Duration::from_secs(30);
+
+/// Count how many times the Mac would have gone to idle sleep while `assertions` held it off
+pub fn watch(assertions: Assertions) -> Arc<Mutex<BlockedSleeps>> {
+    let blocked = Arc::new(Mutex::new(BlockedSleeps::default()));
+    let counter = blocked.clone();
+    thread::spawn(move || loop {
+        thread::sleep(INTERVAL);
+        if !assertions.any_held() {
+            continue;
+        }
+        let Some(idle) = idle::idle_time() else {
+            continue;
+        };
+        let idle = idle.as_secs() as i64;
+        // Read every time, since it's different on battery
+        let timer = low_power::pmset(&["-g"])
+            .ok()
+            .and_then(|output| idle_sleep::parse_sleep_timer(&output));
+        trace!("idle for {idle}s, sleep timer {timer:?}");
+        counter.lock().unwrap().observe(idle, timer);
+    });
+    blocked
+}
@@ -0,0 +1,162 @@
+//! Apple's `caffeinate` command line, so `caffeinate2 migrate` can start the same session.
+
+/// Assertion flags both take, with the same meaning
+const FLAGS: [char; 5] = ['d', 'i', 'm', 's', 'u'];
+
+/// caffeinate's timeout for -u without -t, in seconds
+const USER_ACTIVE_TIMEOUT: u64 = 5;
+
+/// What a caffeinate process was started to do
+#[derive(Debug, Default, PartialEq)]
+pub struct Intent {
+    /// Assertion flags, in the order given
+    pub flags: Vec<char>,
+    /// -t, in seconds
+    pub timeout: Option<u64>,
+    /// -w
+    pub waitfor: Option<i32>,
+    /// The utility it runs, if any
+    pub command: Option<Vec<String>>,
+}
+
+/// Parse caffeinate's arguments (without the program name) the way its getopt does
+pub fn parse(args: &[String]) -> Result<Intent, String> {
+    let mut intent = Intent::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        let Some(options) = arg.strip_prefix('-').filter(|options| !options.is_empty()) else {
+            // The utility and its arguments
+            let mut command = vec![arg.clone()];
+            command.extend(args.by_ref().cloned());
+            intent.command = Some(command);
+            return Ok(intent);
+        };
+        for (index, option) in options.char_indices() {
+            match option {
+                option if FLAGS.contains(&option) => intent.flags.push(option),
+                't' | 'w' => {
+                    // The value is either the rest of this argument or the next one
+                    let value = match &options[index + 1..] {
+                        "" => args
+                            .next()
+                            .ok_or_else(|| format!("-{option} needs a value"))?
+                            .as_str(),
+                        rest => rest,
+                    };
+                    let invalid = || format!("Invalid value for -{option}: {value}");
+                    if option == 't' {
+                        intent.timeout = Some(value.parse().map_err(|_| invalid())?);
+                    } else {
+                        intent.waitfor = Some(value.parse().map_err(|_| invalid())?);
+                    }
+                    break;
+                }
+                option => return Err(format!("Unknown option -{option}")),
+            }
+        }
+    }
+    let command: Vec<String> = args.cloned().collect();
+    if !command.is_empty() {
+        intent.command = Some(command);
+    }
+    Ok(intent)
+}
+
+impl Intent {
+    /// caffeinate2 arguments for the rest of the session, `elapsed` seconds after it started.
+    /// A wrapped command is already running, so it's waited for by `command_pid` instead.
+    /// None if it's already over.
+    pub fn caffeinate2_args(&self, elapsed: u64, command_pid: Option<i32>) -> Option<Vec<String>> {
+        let mut args: Vec<String> = self.flags.iter().map(|flag| format!("-{flag}")).collect();
+        match (&self.command, command_pid) {
+            (Some(_), Some(pid)) => args.extend([String::from("-w"), pid.to_string()]),
+            (Some(_), None) => return None,
+            (None, _) => {
+                let timeout = self.timeout.or_else(|| {
+                    (self.flags.contains(&'u') && self.waitfor.is_none())
+                        .then_some(USER_ACTIVE_TIMEOUT)
+                });
+                if let Some(timeout) = timeout {
+                    let remaining = timeout.checked_sub(elapsed).filter(|&left| left > 0)?;
+                    args.extend([String::from("-t"), format!("{remaining}s")]);
+                }
+                if let Some(pid) = self.waitfor {
+                    args.extend([String::from("-w"), pid.to_string()]);
+                }
+            }
+        }
+        Some(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Intent;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            super::parse(&args(&["-dims", "-t3600"])).unwrap(),
+            Intent {
+                flags: vec!['d', 'i', 'm', 's'],
+                timeout: Some(3600),
+                ..Intent::default()
+            }
+        );
+        assert_eq!(
+            super::parse(&args(&["-i", "-w", "123"])).unwrap(),
+            Intent {
+                flags: vec!['i'],
+                waitfor: Some(123),
+                ..Intent::default()
+            }
+        );
+        assert_eq!(
+            super::parse(&args(&["-s", "make", "-j8"])).unwrap(),
+            Intent {
+                flags: vec!['s'],
+                command: Some(args(&["make", "-j8"])),
+                ..Intent::default()
+            }
+        );
+        assert_eq!(
+            super::parse(&args(&["--", "-weird"])).unwrap().command,
+            Some(args(&["-weird"]))
+        );
+        assert!(super::parse(&args(&["-x"])).is_err());
+        assert!(super::parse(&args(&["-t"])).is_err());
+        assert!(super::parse(&args(&["-t", "soon"])).is_err());
+    }
+
+    #[test]
+    fn test_caffeinate2_args() {
+        let intent = super::parse(&args(&["-d", "-t", "600", "-w", "42"])).unwrap();
+        assert_eq!(
+            intent.caffeinate2_args(100, None),
+            Some(args(&["-d", "-t", "500s", "-w", "42"]))
+        );
+        assert_eq!(intent.caffeinate2_args(600, None), None);
+
+        let intent = super::parse(&args(&["-i", "sleep", "100"])).unwrap();
+        assert_eq!(
+            intent.caffeinate2_args(10, Some(77)),
+            Some(args(&["-i", "-w", "77"]))
+        );
+        assert_eq!(intent.caffeinate2_args(10, None), None);
+
+        // -u alone only lasts a few seconds
+        let intent = super::parse(&args(&["-u"])).unwrap();
+        assert_eq!(intent.caffeinate2_args(60, None), None);
+        assert_eq!(
+            super::parse(&[]).unwrap().caffeinate2_args(60, None),
+            Some(vec![])
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use block2::RcBlock;
+use objc2::rc::Retained;
+use objc2::runtime::{Bool, NSObjectProtocol};
+use objc2::sel;
+use objc2_event_kit::{EKAuthorizationStatus, EKEntityType, EKEventStore};
+use objc2_foundation::{NSDate, NSError};
+use std::sync::mpsc;
+
+// Calendar access needs a usage description, and a command line tool has no Info.plist
+// to put it in, so embed one where macOS looks for it
+#[used]
+#[link_section = "__TEXT,__info_plist"]
+static INFO_PLIST: [u8; 330] = *br#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0"><dict>
+<key>NSCalendarsFullAccessUsageDescription</key>
+<string>caffeinate2 --during-event keeps your Mac awake until the current event ends.</string>
+</dict></plist>
+"#;
+
+/// Ask for calendar access (showing the permission prompt the first time) and wait for the answer
+fn request_access(store: &EKEventStore) -> bool {
+    let status = unsafe { EKEventStore::authorizationStatusForEntityType(EKEntityType::Event) };
+    trace!(
+        "EKEventStore authorizationStatusForEntityType(Event) = {:?}",
+        status
+    );
+    if status == EKAuthorizationStatus::FullAccess {
+        return true;
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let completion = RcBlock::new(move |granted: Bool, _error: *mut NSError| {
+        let _ = sender.send(granted.as_bool());
+    });
+    // macOS 14 split calendar access into full and write-only, and the old request only gives the latter
+    if store.respondsToSelector(sel!(requestFullAccessToEventsWithCompletion:)) {
+        unsafe { store.requestFullAccessToEventsWithCompletion(RcBlock::as_ptr(&completion)) };
+    } else {
+        #[allow(deprecated)]
+        unsafe {
+            store.requestAccessToEntityType_completion(
+                EKEntityType::Event,
+                RcBlock::as_ptr(&completion),
+            )
+        };
+    }
+    let granted = receiver.recv().unwrap_or(false);
+    trace!("EKEventStore access granted: {}", granted);
+    granted
+}
+
+/// The title and end (as a Unix timestamp) of the calendar event happening right now.
+/// All-day events are skipped, and if events overlap, the one that ends last wins.
+pub fn current_event() -> Result<Option<(String, i64)>, String> {
+    let store: Retained<EKEventStore> = unsafe { EKEventStore::new() };
+    if !request_access(&store) {
+        return Err(String::from(
+            "No access to your calendars. Allow it in System Settings > Privacy & Security > Calendars.",
+        ));
+    }
+
+    let now = NSDate::now();
+    let soon = NSDate::dateWithTimeIntervalSinceNow(1.0);
+    let events = unsafe {
+        let predicate = store.predicateForEventsWithStartDate_endDate_calendars(&now, &soon, None);
+        store.eventsMatchingPredicate(&predicate)
+    };
+    trace!(
+        "EKEventStore eventsMatchingPredicate: {} event(s)",
+        events.count()
+    );
+
+    let current = events
+        .iter()
+        .filter(|event| !unsafe { event.isAllDay() })
+        .map(|event| unsafe {
+            (
+                event.title().to_string(),
+                event.endDate().timeIntervalSince1970() as i64,
+            )
+        })
+        .max_by_key(|(_, end)| *end);
+    Ok(current)
+}
@@ -0,0 +1,86 @@
+use crate::power_management;
+use crate::power_source;
+use crate::smc;
+use nix::unistd::Uid;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process;
+
+/// What this build and Mac support, for `--version --json`
+#[derive(Serialize, Debug)]
+pub struct Report {
+    version: &'static str,
+    /// Cargo features this build was made with
+    features: Vec<&'static str>,
+    macos_version: Option<String>,
+    arch: &'static str,
+    /// Private IOKit functions, by whether they could be looked up
+    iokit_symbols: BTreeMap<&'static str, bool>,
+    /// What features depend on, by whether it's there
+    backends: BTreeMap<&'static str, bool>,
+    root: bool,
+    sandboxed: bool,
+    /// Whether changes that need root (like -e and --disable-lpm) can be made
+    privileged: bool,
+}
+
+fn features() -> Vec<&'static str> {
+    [
+        (cfg!(feature = "objc"), "objc"),
+        (cfg!(feature = "scripting"), "scripting"),
+        (cfg!(feature = "calendar"), "calendar"),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, name)| name)
+    .collect()
+}
+
+fn macos_version() -> Option<String> {
+    let output = process::Command::new("/usr/bin/sw_vers")
+        .arg("-productVersion")
+        .output()
+        .ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+impl Report {
+    pub fn collect() -> Report {
+        let iokit_symbols = power_management::PRIVATE_SYMBOLS
+            .into_iter()
+            .map(|name| (name, power_management::has_private_symbol(name)))
+            .collect();
+        let backends = BTreeMap::from([
+            ("pmset", Path::new("/usr/bin/pmset").exists()),
+            ("ioreg", Path::new("/usr/sbin/ioreg").exists()),
+            ("smc", smc::Smc::open().is_some()),
+            ("thermal_pressure", smc::thermal_pressure().is_some()),
+            ("battery", power_source::battery_charge().is_some()),
+        ]);
+        let root = Uid::effective().is_root();
+        let sandboxed = power_management::is_sandboxed();
+        Report {
+            version: env!("CARGO_PKG_VERSION"),
+            features: features(),
+            macos_version: macos_version(),
+            arch: std::env::consts::ARCH,
+            iokit_symbols,
+            backends,
+            root,
+            sandboxed,
+            privileged: root && !sandboxed,
+        }
+    }
+}
+
+/// `--version`, or with `json`, the whole report
+pub fn print_version(json: bool) {
+    if json {
+        let report = Report::collect();
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        println!("caffeinate2 {}", env!("CARGO_PKG_VERSION"));
+    }
+}
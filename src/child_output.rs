@@ -0,0 +1,191 @@
+use crate::output_log::{OutputLog, Stream};
+use std::io::{self, Read, Write};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// What to do when a wrapped command stops printing anything (see --output-idle-timeout)
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OnIdle {
+    /// Only say it looks hung
+    Warn,
+    /// Allow sleep until it prints something again
+    Release,
+    /// Stop it (with SIGTERM), which ends the session
+    Kill,
+}
+
+/// How a wrapped command's output is shown, from --quiet-child and --prefix-child
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Don't pass its stdout on (it's still logged)
+    pub quiet: bool,
+    /// Put in front of every line it prints
+    pub prefix: Option<String>,
+}
+
+/// Passes a wrapped command's piped stdout and stderr on to ours (and the --log-output log),
+/// keeping track of when it last printed anything
+pub struct Forwarder {
+    /// Unix timestamp in milliseconds
+    last_output: Arc<AtomicI64>,
+    log: Option<Arc<Mutex<OutputLog>>>,
+    threads: Vec<JoinHandle<()>>,
+    /// Set once the command is done, so watching for idleness stops
+    done: Arc<AtomicBool>,
+}
+
+fn now_millis() -> i64 {
+    chrono::Local::now().timestamp_millis()
+}
+
+/// Write a chunk of output with `prefix` at the start of every line. `at_line_start` carries
+/// over between chunks, since lines can be split between them.
+fn write_prefixed(
+    to: &mut impl Write,
+    chunk: &[u8],
+    prefix: &[u8],
+    at_line_start: &mut bool,
+) -> io::Result<()> {
+    for line in chunk.split_inclusive(|&byte| byte == b'\n') {
+        if *at_line_start {
+            to.write_all(prefix)?;
+        }
+        to.write_all(line)?;
+        *at_line_start = line.ends_with(b"\n");
+    }
+    Ok(())
+}
+
+fn pump(
+    mut from: impl Read,
+    mut to: impl Write,
+    stream: Stream,
+    log: Option<Arc<Mutex<OutputLog>>>,
+    last_output: Arc<AtomicI64>,
+    prefix: Option<String>,
+) {
+    // Chunks rather than lines, so prompts and progress bars show up straight away
+    let mut buffer = [0; 8192];
+    let mut at_line_start = true;
+    loop {
+        match from.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                last_output.store(now_millis(), Ordering::SeqCst);
+                if let Some(log) = &log {
+                    log.lock().unwrap().write(stream, &buffer[..read]);
+                }
+                let written = match &prefix {
+                    Some(prefix) => write_prefixed(
+                        &mut to,
+                        &buffer[..read],
+                        prefix.as_bytes(),
+                        &mut at_line_start,
+                    ),
+                    None => to.write_all(&buffer[..read]),
+                };
+                if written.and_then(|_| to.flush()).is_err() {
+                    break;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+}
+
+impl Forwarder {
+    /// Start forwarding. The child has to have been spawned with piped stdout and stderr.
+    pub fn start(
+        child: &mut Child,
+        log: Option<Arc<Mutex<OutputLog>>>,
+        options: &Options,
+    ) -> Forwarder {
+        let last_output = Arc::new(AtomicI64::new(now_millis()));
+        let mut threads = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            let (log, last_output) = (log.clone(), last_output.clone());
+            let prefix = options.prefix.clone();
+            let to: Box<dyn Write + Send> = if options.quiet {
+                Box::new(io::sink())
+            } else {
+                Box::new(io::stdout())
+            };
+            threads.push(thread::spawn(move || {
+                pump(stdout, to, Stream::Stdout, log, last_output, prefix)
+            }));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let (log, last_output) = (log.clone(), last_output.clone());
+            let prefix = options.prefix.clone();
+            threads.push(thread::spawn(move || {
+                pump(
+                    stderr,
+                    io::stderr(),
+                    Stream::Stderr,
+                    log,
+                    last_output,
+                    prefix,
+                )
+            }));
+        }
+        Forwarder {
+            last_output,
+            log,
+            threads,
+            done: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Call `on_change(true)` once the command has printed nothing for `timeout`,
+    /// and `on_change(false)` when it starts printing again
+    pub fn watch_idle(&self, timeout: Duration, on_change: impl Fn(bool) + Send + 'static) {
+        let last_output = self.last_output.clone();
+        let done = self.done.clone();
+        let timeout_millis = timeout.as_millis() as i64;
+        thread::spawn(move || {
+            let mut idle = false;
+            while !done.load(Ordering::SeqCst) {
+                let silent_for = now_millis() - last_output.load(Ordering::SeqCst);
+                if (silent_for >= timeout_millis) != idle {
+                    idle = !idle;
+                    on_change(idle);
+                }
+                let until_timeout = (timeout_millis - silent_for).max(0) as u64;
+                // Check often enough to notice output resuming, but don't spin
+                thread::sleep(Duration::from_millis(until_timeout.clamp(100, 1000)));
+            }
+        });
+    }
+
+    /// Wait for the rest of the output to be passed on, after the command exits
+    pub fn join(self) {
+        self.done.store(true, Ordering::SeqCst);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        if let Some(log) = self.log {
+            log.lock().unwrap().finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_write_prefixed() {
+        let mut output = Vec::new();
+        let mut at_line_start = true;
+        for chunk in [&b"one\ntw"[..], b"o\n", b"\nthree"] {
+            super::write_prefixed(&mut output, chunk, b"[job] ", &mut at_line_start).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[job] one\n[job] two\n[job] \n[job] three"
+        );
+        assert!(!at_line_start);
+    }
+}
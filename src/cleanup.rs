@@ -0,0 +1,63 @@
+use crate::assertions::Assertions;
+use crate::journal;
+use crate::low_power;
+use crate::session::Session;
+use crate::standby_override;
+use caffeinate2::shared_settings::{Change, Shared};
+use caffeinate2::standby;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Everything that has to be undone when a session ends
+pub struct SessionState {
+    pub assertions: Assertions,
+    pub pid: i32,
+    /// Low Power Mode settings turned off by --disable-lpm
+    pub low_power: Vec<low_power::Setting>,
+    /// Standby settings changed by --no-standby or --maintain-wake-on-lan, with their original
+    /// values
+    pub standby: Vec<standby::Setting>,
+}
+
+/// The single owner of the session's state, shared between the main thread and
+/// the signal handler. Whichever ends the session first takes the state out,
+/// so assertions are released (and SleepDisabled reset) exactly once.
+#[derive(Clone)]
+pub struct Cleanup(Arc<Mutex<Option<SessionState>>>);
+
+impl Cleanup {
+    pub fn new(state: SessionState) -> Cleanup {
+        Cleanup(Arc::new(Mutex::new(Some(state))))
+    }
+
+    /// Release everything, returning how long sleep was prevented.
+    /// Does nothing (and returns None) if it already ran.
+    pub fn run(&self) -> Option<Duration> {
+        // A panic elsewhere shouldn't stop us from cleaning up
+        let mut guard = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(state) = guard.take() else {
+            trace!("cleanup already ran");
+            return None;
+        };
+        let sleep_restored = state.assertions.close();
+        let held_for = state.assertions.held_for();
+        // Another session that turned it off too might still need it off
+        let _lock = journal::lock();
+        let mut owners = journal::owners();
+        let low_power_restored = state.low_power.is_empty()
+            || owners.remove(Shared::LowPowerModeOff, state.pid) != Change::Restore
+            || low_power::restore(&state.low_power);
+        let standby_restored = state.standby.is_empty()
+            || owners.remove(Shared::StandbySettingsChanged, state.pid) != Change::Restore
+            || standby_override::restore(&state.standby);
+        // Otherwise the journal stays, so a later run can put back what's left
+        if sleep_restored && low_power_restored && standby_restored {
+            journal::clear(state.pid);
+        }
+        Session::unregister(state.pid);
+        Some(held_for)
+    }
+}
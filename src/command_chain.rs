@@ -0,0 +1,30 @@
+//! Several commands run one after another by one session, like `cmd1 ';;' cmd2`.
+
+/// What separates commands in the command line
+pub const SEPARATOR: &str = ";;";
+
+/// Split the command arguments at each `;;` into shell command strings.
+/// Empty commands (like from a trailing `;;`) are dropped.
+pub fn split(args: &[String]) -> Vec<String> {
+    args.split(|arg| arg == SEPARATOR)
+        .filter(|command| !command.is_empty())
+        .map(|command| command.join(" "))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_split() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            super::split(&args(&["make", "build", ";;", "make", "test"])),
+            vec!["make build", "make test"]
+        );
+        assert_eq!(super::split(&args(&["sleep 5"])), vec!["sleep 5"]);
+        assert_eq!(
+            super::split(&args(&[";;", "a", ";;", ";;", "b", ";;"])),
+            vec!["a", "b"]
+        );
+    }
+}
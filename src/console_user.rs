@@ -0,0 +1,177 @@
+use core_foundation::array::{CFArray, CFArrayRef};
+use core_foundation::base::{CFType, CFTypeRef, TCFType};
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::runloop::{
+    kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopSource, CFRunLoopSourceRef,
+};
+use core_foundation::string::{CFString, CFStringRef};
+use std::os::raw::c_void;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// The dynamic store key for the console user, what SCDynamicStoreKeyCreateConsoleUser returns
+const CONSOLE_USER_KEY: &str = "State:/Users/ConsoleUser";
+
+type SCDynamicStoreCallBack =
+    extern "C" fn(store: *const c_void, changed_keys: CFArrayRef, info: *mut c_void);
+
+/// SCDynamicStoreContext from SystemConfiguration/SCDynamicStore.h
+#[repr(C)]
+struct SCDynamicStoreContext {
+    version: isize,
+    info: *mut c_void,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+}
+
+#[link(name = "SystemConfiguration", kind = "framework")]
+extern "C" {
+    fn SCDynamicStoreCopyConsoleUser(
+        store: *const c_void,
+        uid: *mut u32,
+        gid: *mut u32,
+    ) -> CFStringRef;
+    fn SCDynamicStoreCreate(
+        allocator: *const c_void,
+        name: CFStringRef,
+        callout: Option<SCDynamicStoreCallBack>,
+        context: *mut SCDynamicStoreContext,
+    ) -> *const c_void;
+    fn SCDynamicStoreCopyValue(store: *const c_void, key: CFStringRef) -> CFTypeRef;
+    fn SCDynamicStoreSetNotificationKeys(
+        store: *const c_void,
+        keys: CFArrayRef,
+        patterns: CFArrayRef,
+    ) -> u8;
+    fn SCDynamicStoreCreateRunLoopSource(
+        allocator: *const c_void,
+        store: *const c_void,
+        order: isize,
+    ) -> CFRunLoopSourceRef;
+}
+
+/// The user at the console (with fast user switching, whoever's session is on screen).
+/// None at the login window, or if nobody is logged in.
+pub fn current() -> Option<(String, u32)> {
+    let mut uid = 0;
+    let mut gid = 0;
+    let name = unsafe { SCDynamicStoreCopyConsoleUser(std::ptr::null(), &mut uid, &mut gid) };
+    if name.is_null() {
+        trace!("SCDynamicStoreCopyConsoleUser() = null");
+        return None;
+    }
+    let name = unsafe { CFString::wrap_under_create_rule(name) }.to_string();
+    trace!("SCDynamicStoreCopyConsoleUser() = {name} ({uid})");
+    (name != "loginwindow").then_some((name, uid))
+}
+
+fn field(dictionary: &CFDictionary, key: &'static str) -> Option<CFType> {
+    let key = CFString::from_static_string(key);
+    dictionary
+        .find(key.as_CFTypeRef())
+        .map(|value| unsafe { CFType::wrap_under_get_rule(*value) })
+}
+
+/// UIDs of everyone logged in to a GUI session, switched out ones included.
+/// None if that can't be found out.
+pub fn logged_in() -> Option<Vec<u32>> {
+    let name = CFString::new("caffeinate2");
+    let key = CFString::new(CONSOLE_USER_KEY);
+    let value = unsafe {
+        let store = SCDynamicStoreCreate(
+            std::ptr::null(),
+            name.as_concrete_TypeRef(),
+            None,
+            std::ptr::null_mut(),
+        );
+        if store.is_null() {
+            trace!("SCDynamicStoreCreate() = null");
+            return None;
+        }
+        let store = CFType::wrap_under_create_rule(store);
+        SCDynamicStoreCopyValue(store.as_CFTypeRef(), key.as_concrete_TypeRef())
+    };
+    if value.is_null() {
+        trace!("SCDynamicStoreCopyValue({CONSOLE_USER_KEY}) = null");
+        return Some(Vec::new());
+    }
+    let info: CFDictionary = unsafe { CFType::wrap_under_create_rule(value) }.downcast()?;
+    let sessions: CFArray = field(&info, "SessionInfo")?.downcast()?;
+    let uids: Vec<u32> = sessions
+        .iter()
+        .filter_map(|session| {
+            let session: CFDictionary =
+                unsafe { CFType::wrap_under_get_rule(*session) }.downcast()?;
+            let uid = field(&session, "kCGSSessionUserIDKey")?.downcast::<CFNumber>()?;
+            u32::try_from(uid.to_i64()?).ok()
+        })
+        .collect();
+    trace!("logged in: {uids:?}");
+    Some(uids)
+}
+
+/// Block until this user has logged out of their GUI session
+pub fn wait_for_logout(uid: u32) {
+    let (changed, changes) = mpsc::channel();
+    watch(move |_| {
+        let _ = changed.send(());
+    });
+    // Logging out changes the console user, but check now and then in case that's missed
+    while logged_in().is_none_or(|uids| uids.contains(&uid)) {
+        let _ = changes.recv_timeout(Duration::from_secs(60));
+    }
+}
+
+type Callback = Box<dyn Fn(Option<u32>) + Send>;
+
+extern "C" fn console_user_changed(
+    _store: *const c_void,
+    _changed_keys: CFArrayRef,
+    info: *mut c_void,
+) {
+    let callback = unsafe { &*(info as *const Callback) };
+    callback(current().map(|(_, uid)| uid));
+}
+
+/// Call `callback` with the console user's UID whenever someone else switches in.
+/// It runs on its own thread.
+pub fn watch(callback: impl Fn(Option<u32>) + Send + 'static) {
+    let callback: Callback = Box::new(callback);
+    thread::spawn(move || {
+        // Lives as long as the run loop, which is forever
+        let mut context = SCDynamicStoreContext {
+            version: 0,
+            info: Box::into_raw(Box::new(callback)) as *mut c_void,
+            retain: std::ptr::null(),
+            release: std::ptr::null(),
+            copy_description: std::ptr::null(),
+        };
+        unsafe {
+            let name = CFString::new("caffeinate2");
+            let store = SCDynamicStoreCreate(
+                std::ptr::null(),
+                name.as_concrete_TypeRef(),
+                Some(console_user_changed),
+                &mut context,
+            );
+            if store.is_null() {
+                trace!("SCDynamicStoreCreate() = null, not watching the console user");
+                return;
+            }
+            let keys = CFArray::from_CFTypes(&[CFString::new(CONSOLE_USER_KEY)]);
+            let set = SCDynamicStoreSetNotificationKeys(
+                store,
+                keys.as_concrete_TypeRef(),
+                std::ptr::null(),
+            );
+            trace!("SCDynamicStoreSetNotificationKeys({CONSOLE_USER_KEY}) = {set}");
+            let source = SCDynamicStoreCreateRunLoopSource(std::ptr::null(), store, 0);
+            let source = CFRunLoopSource::wrap_under_create_rule(source);
+            CFRunLoop::get_current().add_source(&source, kCFRunLoopDefaultMode);
+        }
+        CFRunLoop::run_current();
+    });
+}
@@ -0,0 +1,381 @@
+use crate::apply::SleepType;
+use crate::journal;
+use crate::lockdown::Lockdown;
+use crate::other_assertions;
+use crate::persist;
+use crate::session::{self, Selector, Session};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::os::unix::process::CommandExt;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// Ask a session to stop. It releases its assertions on SIGTERM.
+pub fn send_stop(pid: i32) -> nix::Result<()> {
+    let result = signal::kill(Pid::from_raw(pid), Signal::SIGTERM);
+    trace!("kill({pid}, SIGTERM) = {result:?}");
+    if result.is_ok() {
+        // SIGTERM alone looks like a shutdown, so it would come back at the next login
+        persist::remove_for_pid(pid);
+    }
+    result
+}
+
+/// Stop a session and wait (up to a few seconds) for it to finish cleaning up
+pub fn stop_and_wait(pid: i32) -> Result<(), String> {
+    send_stop(pid).map_err(|err| err.to_string())?;
+    for _ in 0..50 {
+        if !session::is_alive(pid) {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(String::from("it didn't exit in time"))
+}
+
+/// Make a session last until at least `until` (a Unix timestamp).
+/// The new deadline goes in its session file, then SIGUSR1 tells it to re-read it.
+pub fn extend_session(session: &Session, until: i64) -> Result<(), String> {
+    let Some(current) = session.until else {
+        return Err(String::from("it has no timeout"));
+    };
+    if until <= current {
+        return Ok(());
+    }
+
+    let mut session = session.clone();
+    session.until = Some(until);
+    session.register().map_err(|err| err.to_string())?;
+    let result = signal::kill(Pid::from_raw(session.pid), Signal::SIGUSR1);
+    trace!("kill({}, SIGUSR1) = {:?}", session.pid, result);
+    result.map_err(|err| err.to_string())
+}
+
+/// Start a new caffeinate2 with the given arguments, detached from this terminal.
+/// Returns the PID of the new session.
+pub fn spawn_background(args: &[String]) -> std::io::Result<u32> {
+    let child = process::Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        // Own process group, so closing the terminal doesn't take it down
+        .process_group(0)
+        .spawn()?;
+    Ok(child.id())
+}
+
+/// Stop the selected sessions
+pub fn stop(selector: &Selector) {
+    let sessions = Session::select(selector);
+
+    if sessions.is_empty() {
+        eprintln!("Error: No {} running.", selector.describe());
+        process::exit(1);
+    }
+
+    let mut failed = false;
+    for session in sessions {
+        match send_stop(session.pid) {
+            Ok(()) => match session.describe_tags() {
+                Some(tags) => println!("Stopped session {} ({tags}).", session.pid),
+                None => println!("Stopped session {}.", session.pid),
+            },
+            Err(err) => {
+                eprintln!("Error: Couldn't stop session {}: {}", session.pid, err);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Make the selected sessions last at least `duration` from now
+pub fn extend(selector: &Selector, duration: String) {
+    let sessions = Session::select(selector);
+
+    if sessions.is_empty() {
+        eprintln!("Error: No {} running.", selector.describe());
+        process::exit(1);
+    }
+
+    let until =
+        chrono::Local::now().timestamp() + caffeinate2::duration_parser::parse_duration(duration);
+    let lockdown = Lockdown::load();
+    let mut failed = false;
+    for session in sessions {
+        let allowed = lockdown.as_ref().map_or(Ok(()), |lockdown| {
+            lockdown.check_duration((until - session.started) * 1000)
+        });
+        match allowed.and_then(|()| extend_session(&session, until)) {
+            Ok(()) => println!("Extended session {}.", session.pid),
+            Err(err) => {
+                eprintln!("Error: Couldn't extend session {}: {}", session.pid, err);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Take over the selected session: start it again in this process (which stops the old one
+/// once it's running), so it can be managed from this terminal
+pub fn adopt(selector: &Selector) {
+    let session = Session::select_one(selector).unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        process::exit(1);
+    });
+    if session.command.is_some() {
+        // Starting it again would run the command a second time
+        eprintln!(
+            "Error: Session {} is running a command, which can't be handed over.",
+            session.pid
+        );
+        process::exit(1);
+    }
+    if session.args.is_empty() {
+        eprintln!(
+            "Error: Session {} didn't record how it was started, so it can't be adopted.",
+            session.pid
+        );
+        process::exit(1);
+    }
+
+    let exe = std::env::current_exe().unwrap_or_else(|err| {
+        eprintln!("Error: Couldn't find caffeinate2: {err}");
+        process::exit(1);
+    });
+    // Only returns if it failed
+    let err = process::Command::new(exe)
+        .args(&session.args)
+        .args(["--adopt", &session.pid.to_string()])
+        .exec();
+    eprintln!("Error: Couldn't adopt session {}: {err}", session.pid);
+    process::exit(1);
+}
+
+/// Check a background session against the administrator's policy before it's started, since
+/// it can't report errors itself. `millis` is its timeout, if it has one.
+pub fn check_lockdown(types: &[SleepType], millis: Option<i64>) -> Result<(), String> {
+    let Some(lockdown) = Lockdown::load() else {
+        return Ok(());
+    };
+    lockdown.check_types(types)?;
+    match millis {
+        Some(millis) => lockdown.check_duration(millis),
+        None => Ok(()),
+    }
+}
+
+/// Stop your sessions (with the given name) if any are running, otherwise start one in the background
+pub fn toggle(duration: Option<String>, name: Option<String>, json: bool) {
+    let selector = Selector {
+        pid: None,
+        name: name.clone(),
+        mine: true,
+    };
+    let sessions = Session::select(&selector);
+
+    if sessions.is_empty() {
+        let mut args = Vec::new();
+        if let Some(name) = &name {
+            args.extend([String::from("--name"), name.clone()]);
+        }
+        // Validate here, since the background process can't report errors. It prevents
+        // system sleep, like any session that isn't given types.
+        let millis = duration
+            .as_ref()
+            .map(|duration| caffeinate2::duration_parser::parse_duration_ms(duration.clone()));
+        if let Err(err) = check_lockdown(&[SleepType::System], millis) {
+            eprintln!("Error: {err}");
+            process::exit(1);
+        }
+        if let Some(duration) = &duration {
+            args.extend([String::from("-t"), duration.clone()]);
+        }
+
+        let pid = spawn_background(&args).unwrap_or_else(|err| {
+            eprintln!("Error: Couldn't start background session: {err}");
+            process::exit(1);
+        });
+
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({ "active": true, "pid": pid, "name": name, "timeout": duration })
+            );
+        } else {
+            match &duration {
+                Some(duration) => println!("Preventing sleep for {duration} (PID {pid})."),
+                None => println!("Preventing sleep until toggled off (PID {pid})."),
+            }
+        }
+        return;
+    }
+
+    let mut stopped = Vec::new();
+    let mut failed = Vec::new();
+    for session in sessions {
+        match send_stop(session.pid) {
+            Ok(()) => stopped.push(session.pid),
+            Err(err) => failed.push((session.pid, err)),
+        }
+    }
+
+    if json {
+        let failed_pids: Vec<i32> = failed.iter().map(|(pid, _)| *pid).collect();
+        println!(
+            "{}",
+            serde_json::json!({ "active": !failed.is_empty(), "stopped": stopped, "failed": failed_pids })
+        );
+    } else {
+        if !stopped.is_empty() {
+            let pids: Vec<String> = stopped.iter().map(|pid| pid.to_string()).collect();
+            println!("Sleep allowed again (stopped PID {}).", pids.join(", "));
+        }
+        for (pid, err) in &failed {
+            eprintln!("Error: Couldn't stop session {pid}: {err}");
+        }
+    }
+
+    if !failed.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// Prevent one kind of sleep for `duration` from a background session and return right away.
+/// Its assertion is created with a timeout, so powerd releases it on time by itself.
+pub fn assert_once(sleep_type: SleepType, duration: String, name: Option<String>) {
+    // Validate here, since the background process can't report errors
+    let millis = caffeinate2::duration_parser::parse_duration_ms(duration.clone());
+    if millis <= 0 {
+        eprintln!("Error: --for has to be longer than zero.");
+        process::exit(1);
+    }
+    if let Err(err) = check_lockdown(&[sleep_type], Some(millis)) {
+        eprintln!("Error: {err}");
+        process::exit(1);
+    }
+
+    let mut args = vec![
+        String::from(sleep_type.flag()),
+        String::from("-t"),
+        duration.clone(),
+        String::from("--kernel-timeout"),
+    ];
+    if let Some(name) = &name {
+        args.extend([String::from("--name"), name.clone()]);
+    }
+    let pid = spawn_background(&args).unwrap_or_else(|err| {
+        eprintln!("Error: Couldn't start background session: {err}");
+        process::exit(1);
+    });
+    println!(
+        "Preventing {} sleep for {duration} (ID {pid}). `caffeinate2 release {pid}` ends it early.",
+        sleep_type.session_name()
+    );
+}
+
+/// Your sessions a `release` ID stands for: a PID, a --name, or the ID of an assertion one holds
+fn sessions_for_id(id: String) -> Result<Vec<Session>, String> {
+    let mine = |pid, name| Selector {
+        pid,
+        name,
+        mine: true,
+    };
+    let Ok(number) = id.parse::<u32>() else {
+        let selector = mine(None, Some(id));
+        let sessions = Session::select(&selector);
+        if sessions.is_empty() {
+            return Err(format!("No {} running.", selector.describe()));
+        }
+        return Ok(sessions);
+    };
+
+    let sessions = Session::select(&mine(Some(number as i32), None));
+    if !sessions.is_empty() {
+        return Ok(sessions);
+    }
+    if let Some(assertion) = other_assertions::list(&[])
+        .into_iter()
+        .find(|assertion| assertion.id == Some(number))
+    {
+        let sessions = Session::select(&mine(Some(assertion.pid), None));
+        if sessions.is_empty() {
+            return Err(format!(
+                "Assertion {number} isn't one of your caffeinate2 sessions' ({}).",
+                assertion.describe()
+            ));
+        }
+        return Ok(sessions);
+    }
+    if let Some(pid) = journal::holder_of(number) {
+        if !session::is_alive(pid) {
+            // powerd drops a process's assertions when it exits, but not the settings it changed
+            println!("Session {pid} already ended, so assertion {number} was released with it.");
+            journal::repair();
+            return Ok(Vec::new());
+        }
+    }
+    Err(format!(
+        "No caffeinate2 session or assertion with ID {number} of yours running."
+    ))
+}
+
+/// Stop your own sessions (one by ID, or all of them), then check with powerd that their
+/// assertions are gone
+pub fn release(id: Option<String>, all_mine: bool) {
+    let sessions = match id {
+        Some(id) => sessions_for_id(id).unwrap_or_else(|err| {
+            eprintln!("Error: {err}");
+            process::exit(1);
+        }),
+        None => Session::select(&Selector {
+            pid: None,
+            name: None,
+            mine: true,
+        }),
+    };
+    if all_mine {
+        // Put back what crashed sessions changed too
+        journal::repair();
+        if sessions.is_empty() {
+            println!("No caffeinate2 sessions of yours running.");
+        }
+    }
+
+    let mut failed = false;
+    for session in sessions {
+        if let Err(err) = stop_and_wait(session.pid) {
+            eprintln!("Error: Couldn't release session {}: {err}", session.pid);
+            failed = true;
+            continue;
+        }
+        let left = other_assertions::held_by(session.pid);
+        if left.is_empty() {
+            match session.describe_tags() {
+                Some(tags) => println!("Released session {} ({tags}).", session.pid),
+                None => println!("Released session {}.", session.pid),
+            }
+        } else {
+            let left: Vec<String> = left.iter().map(|assertion| assertion.describe()).collect();
+            eprintln!(
+                "Error: Session {} exited, but powerd still lists {}.",
+                session.pid,
+                left.join("; ")
+            );
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
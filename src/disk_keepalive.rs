@@ -0,0 +1,48 @@
+use crate::volume_watch;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+// Well under the shortest spin-down timer macOS offers (1 minute)
+const INTERVAL: Duration = Duration::from_secs(30);
+const KEEPALIVE_FILE: &str = ".caffeinate2-keepalive";
+
+/// Keep a single drive from idling by writing to it regularly. PreventDiskIdle covers every
+/// drive, and neither IOKit nor Disk Arbitration has a public per-device version of it, so
+/// this write-based fallback is all there is. It leaves every other drive free to spin down.
+pub fn start(volume: &Path, verbose: bool) {
+    // Writing to a folder that isn't a mount point would keep whatever drive it's on awake,
+    // like the boot drive for a /Volumes folder left behind by an unmounted one
+    if volume != Path::new("/") && !volume_watch::is_mounted(volume) {
+        eprintln!(
+            "Error: {} isn't a mounted volume. Pass where it's mounted, like /Volumes/Backup.",
+            volume.display()
+        );
+        process::exit(1);
+    }
+
+    let file = volume.join(KEEPALIVE_FILE);
+    thread::spawn(move || loop {
+        if let Err(err) = touch(&file) {
+            // Read-only volumes can't be written to, but reading still counts as activity
+            trace!("couldn't write {}: {}", file.display(), err);
+            if let Err(err) = fs::read_dir(file.parent().unwrap()).map(|entries| entries.count()) {
+                if verbose {
+                    eprintln!("Couldn't keep {} awake: {}", file.display(), err);
+                }
+            }
+        }
+        thread::sleep(INTERVAL);
+    });
+}
+
+fn touch(file: &Path) -> std::io::Result<()> {
+    let mut handle = fs::File::create(file)?;
+    handle.write_all(chrono::Local::now().to_rfc3339().as_bytes())?;
+    // Make sure it actually reaches the disk instead of sitting in a cache
+    handle.sync_all()?;
+    fs::remove_file(file)
+}
@@ -0,0 +1,207 @@
+use once_cell::sync::Lazy;
+use std::process;
+
+/// Longest duration we accept, so deadline math can never overflow (100 years)
+const MAX_SECONDS: i64 = 100 * 365 * 86400;
+
+// Compiled once, since regex compilation is a noticeable part of startup time
+static DURATION_RE: Lazy<regex::Regex> =
+    Lazy::new(|| regex::Regex::new(r"(\d+(?:\.\d+)?)\s*(ms|s|m|h|d)").unwrap());
+static NUMBER_RE: Lazy<regex::Regex> = Lazy::new(|| regex::Regex::new(r"^\d+(?:\.\d+)?$").unwrap());
+
+/// Convert a number like "1.25" of some unit into milliseconds, without going through floats.
+/// Digits past the millisecond are dropped.
+fn to_millis(number: &str, unit_millis: i64) -> Option<i64> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let millis = whole.parse::<i64>().ok()?.checked_mul(unit_millis)?;
+    // Nine digits is already well past a millisecond of a day, and keeps this from overflowing
+    let fraction = &fraction[..fraction.len().min(9)];
+    if fraction.is_empty() {
+        return Some(millis);
+    }
+    let numerator = fraction.parse::<i64>().ok()?;
+    millis.checked_add(numerator * unit_millis / 10i64.pow(fraction.len() as u32))
+}
+
+/// Parse a duration like "1 day 2 hours 3mins 4s", "1.25s" or "500ms", or a plain
+/// (possibly fractional) number of seconds, into milliseconds
+pub fn try_parse_duration_ms(duration: &str) -> Result<i64, String> {
+    // Use regex to split the duration into a bunch of number and unit pairs
+    let mut total_millis: i64 = 0;
+    let mut matched = false;
+    for captures in DURATION_RE.captures_iter(duration) {
+        matched = true;
+        let unit_millis = match &captures[2] {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            "d" => 86_400_000,
+            _ => unreachable!("regex only matches known units"),
+        };
+
+        total_millis = to_millis(&captures[1], unit_millis)
+            .and_then(|millis| total_millis.checked_add(millis))
+            .ok_or_else(|| String::from("Timeout is too long!"))?;
+    }
+
+    // If no units were specified, assume seconds
+    if !matched {
+        let number = duration.trim();
+        if !NUMBER_RE.is_match(number) {
+            return Err(String::from("Timeout isn't a valid duration or number!"));
+        }
+        total_millis =
+            to_millis(number, 1000).ok_or_else(|| String::from("Timeout is too long!"))?;
+    }
+
+    if total_millis > MAX_SECONDS * 1000 {
+        return Err(String::from("Timeout is too long!"));
+    }
+
+    Ok(total_millis)
+}
+
+/// Like `try_parse_duration_ms`, but in whole seconds (rounded down)
+pub fn try_parse_duration(duration: &str) -> Result<i64, String> {
+    try_parse_duration_ms(duration).map(|millis| millis / 1000)
+}
+
+/// Parse a duration, exiting with an error message if it's invalid
+pub fn parse_duration(duration: String) -> i64 {
+    try_parse_duration(&duration).unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        process::exit(1)
+    })
+}
+
+/// Parse a duration in milliseconds, exiting with an error message if it's invalid
+pub fn parse_duration_ms(duration: String) -> i64 {
+    try_parse_duration_ms(&duration).unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        process::exit(1)
+    })
+}
+
+/// Compact duration like "3h 20m", for messages printed while running
+pub fn format_short_duration(duration: chrono::Duration) -> String {
+    let days = duration.num_days();
+    let hours = duration.num_hours() % 24;
+    let minutes = duration.num_minutes() % 60;
+    let seconds = duration.num_seconds() % 60;
+
+    let mut parts = Vec::new();
+    if days > 0 {
+        parts.push(format!("{days}d"));
+    }
+    if hours > 0 {
+        parts.push(format!("{hours}h"));
+    }
+    if minutes > 0 {
+        parts.push(format!("{minutes}m"));
+    }
+    // Seconds are just noise once we're past the first minute
+    if parts.is_empty() {
+        parts.push(format!("{seconds}s"));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_duration() {
+        let duration = "1d2h3m4s".to_string();
+        let result = super::parse_duration(duration);
+        assert_eq!(result, 93784);
+
+        let duration = "1day 2hrs3m".to_string();
+        let result = super::parse_duration(duration);
+        assert_eq!(result, 93780);
+
+        let duration = "3 minutes 17 hours 2 seconds".to_string();
+        let result = super::parse_duration(duration);
+        assert_eq!(result, 61382);
+
+        let duration = "45323".to_string();
+        let result = super::parse_duration(duration);
+        assert_eq!(result, 45323);
+    }
+
+    #[test]
+    fn test_parse_duration_errors() {
+        assert!(super::try_parse_duration("soon").is_err());
+        assert!(super::try_parse_duration("-5").is_err());
+        assert!(super::try_parse_duration("99999999999999999999d").is_err());
+        assert!(super::try_parse_duration("9999999999999999d").is_err());
+        assert_eq!(super::try_parse_duration("0s"), Ok(0));
+    }
+
+    #[test]
+    fn test_parse_sub_second_durations() {
+        assert_eq!(super::try_parse_duration_ms("500ms"), Ok(500));
+        assert_eq!(super::try_parse_duration_ms("1.25s"), Ok(1250));
+        assert_eq!(super::try_parse_duration_ms("0.5m"), Ok(30_000));
+        assert_eq!(super::try_parse_duration_ms("2.5"), Ok(2500));
+        assert_eq!(super::try_parse_duration_ms("1s 250ms"), Ok(1250));
+        assert_eq!(super::try_parse_duration_ms("0.0001s"), Ok(0));
+        // Minutes are still minutes
+        assert_eq!(super::try_parse_duration_ms("3 mins"), Ok(180_000));
+        assert_eq!(super::try_parse_duration("1500ms"), Ok(1));
+        assert!(super::try_parse_duration_ms("1.").is_err());
+    }
+
+    #[test]
+    fn test_format_short_duration() {
+        let duration = chrono::Duration::try_seconds(12_000).unwrap();
+        assert_eq!(super::format_short_duration(duration), "3h 20m");
+
+        let duration = chrono::Duration::try_seconds(93_784).unwrap();
+        assert_eq!(super::format_short_duration(duration), "1d 2h 3m");
+
+        let duration = chrono::Duration::try_seconds(42).unwrap();
+        assert_eq!(super::format_short_duration(duration), "42s");
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(input in "\\PC*") {
+            let _ = super::try_parse_duration(&input);
+        }
+
+        #[test]
+        fn parse_never_panics_on_big_numbers(input in "([0-9]{1,25} ?[smhd] ?){1,6}") {
+            if let Ok(seconds) = super::try_parse_duration(&input) {
+                prop_assert!((0..=super::MAX_SECONDS).contains(&seconds));
+                prop_assert!(chrono::Duration::try_seconds(seconds).is_some());
+            }
+        }
+
+        #[test]
+        fn parse_units_round_trip(d in 0i64..1000, h in 0i64..24, m in 0i64..60, s in 0i64..60) {
+            let input = format!("{d}d {h}h {m}m {s}s");
+            prop_assert_eq!(super::try_parse_duration(&input), Ok(d * 86400 + h * 3600 + m * 60 + s));
+        }
+
+        #[test]
+        fn parse_millis_round_trip(seconds in 0i64..100_000, millis in 0i64..1000) {
+            prop_assert_eq!(super::try_parse_duration_ms(&format!("{seconds}.{millis:03}s")), Ok(seconds * 1000 + millis));
+            prop_assert_eq!(super::try_parse_duration_ms(&format!("{millis}ms")), Ok(millis));
+        }
+
+        #[test]
+        fn parse_plain_seconds_round_trip(seconds in 0..=super::MAX_SECONDS) {
+            prop_assert_eq!(super::try_parse_duration(&seconds.to_string()), Ok(seconds));
+        }
+
+        #[test]
+        fn short_format_round_trips(minutes in 1i64..(1000 * 1440)) {
+            // Whole minutes survive formatting, since only seconds get dropped
+            let duration = chrono::Duration::try_minutes(minutes).unwrap();
+            let formatted = super::format_short_duration(duration);
+            prop_assert_eq!(super::try_parse_duration(&formatted), Ok(minutes * 60));
+        }
+    }
+}
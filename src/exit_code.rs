@@ -0,0 +1,42 @@
+//! Turning the ways a session can end into the exit code caffeinate2 passes on.
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// Exit code for being stopped by a signal, following the shell convention (130 for Ctrl+C)
+pub fn from_signal(signal: i32) -> i32 {
+    128 + signal
+}
+
+/// Exit code of a process that finished, using the shell convention if it was killed by a signal
+pub fn from_status(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => from_signal(signal),
+        (None, None) => 1,
+    }
+}
+
+/// Exit code from a raw wait(2) status, like the one kevent reports for NOTE_EXITSTATUS
+pub fn from_wait_status(status: i32) -> i32 {
+    from_status(ExitStatus::from_raw(status))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_from_signal() {
+        assert_eq!(super::from_signal(2), 130);
+        assert_eq!(super::from_signal(15), 143);
+    }
+
+    #[test]
+    fn test_from_wait_status() {
+        // Exited normally with code 3
+        assert_eq!(super::from_wait_status(3 << 8), 3);
+        assert_eq!(super::from_wait_status(0), 0);
+        // Killed by SIGINT and SIGKILL
+        assert_eq!(super::from_wait_status(2), 130);
+        assert_eq!(super::from_wait_status(9), 137);
+    }
+}
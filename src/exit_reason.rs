@@ -0,0 +1,85 @@
+//! Why a session ended, for scripts that need more than the exit code (see --exit-reason).
+
+use serde::Serialize;
+
+/// Why a session ended
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ExitReason {
+    /// The --timeout ran out
+    Timeout,
+    /// The --waitfor process exited
+    PidExit { pid: i32, code: i32 },
+    /// The wrapped command (or the first of several to fail) exited
+    CommandExit { code: i32 },
+    /// The --waitfor-job launchd job was unloaded
+    JobUnloaded { label: String, code: i32 },
+    /// An --until-* or --while-http condition was met
+    Condition { message: String },
+    /// We were stopped by a signal, like Ctrl+C or `ctl stop`
+    Signal { signal: i32 },
+    /// Something went wrong, like the --waitfor PID not existing
+    Error { kind: String },
+}
+
+/// How --exit-reason reports it
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A sentence
+    Text,
+    /// A JSON object with a "reason" field and the exit code
+    Json,
+}
+
+impl ExitReason {
+    pub fn describe(&self) -> String {
+        match self {
+            ExitReason::Timeout => String::from("the timeout ran out"),
+            ExitReason::PidExit { pid, code } => format!("PID {pid} exited with code {code}"),
+            ExitReason::CommandExit { code } => format!("the command exited with code {code}"),
+            ExitReason::JobUnloaded { label, code } => {
+                format!("{label} was unloaded (last exit status {code})")
+            }
+            ExitReason::Condition { message } => message.clone(),
+            ExitReason::Signal { signal } => format!("stopped by signal {signal}"),
+            ExitReason::Error { kind } => format!("error ({kind})"),
+        }
+    }
+
+    /// The report for --exit-reason, ending with the exit code we're about to exit with
+    pub fn report(&self, format: Format, exit_code: i32) -> String {
+        match format {
+            Format::Text => format!(
+                "Session ended: {} (exit code {exit_code}).",
+                self.describe()
+            ),
+            Format::Json => {
+                let mut json = serde_json::to_value(self).unwrap();
+                json["exit_code"] = exit_code.into();
+                json.to_string()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExitReason, Format};
+
+    #[test]
+    fn test_report() {
+        let reason = ExitReason::PidExit { pid: 42, code: 3 };
+        assert_eq!(
+            reason.report(Format::Json, 3),
+            r#"{"code":3,"exit_code":3,"pid":42,"reason":"pid_exit"}"#
+        );
+        assert_eq!(
+            reason.report(Format::Text, 3),
+            "Session ended: PID 42 exited with code 3 (exit code 3)."
+        );
+        assert_eq!(
+            ExitReason::Timeout.report(Format::Json, 0),
+            r#"{"exit_code":0,"reason":"timeout"}"#
+        );
+    }
+}
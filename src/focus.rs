@@ -0,0 +1,55 @@
+use crate::trigger_file;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Where macOS keeps Focus state. Reading it needs Full Disk Access.
+fn db_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_default();
+    PathBuf::from(home).join("Library/DoNotDisturb/DB")
+}
+
+fn read_json(file: &str) -> io::Result<Value> {
+    let contents = fs::read(db_dir().join(file))?;
+    serde_json::from_slice(&contents).map_err(io::Error::from)
+}
+
+/// Names of the Focus modes that are currently on (usually zero or one)
+pub fn active_modes() -> io::Result<Vec<String>> {
+    let assertions = read_json("Assertions.json")?;
+    let configurations = read_json("ModeConfigurations.json")?;
+    let modes = &configurations["data"][0]["modeConfigurations"];
+
+    let active = assertions["data"][0]["storeAssertionRecords"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|record| record["assertionDetails"]["assertionDetailsModeIdentifier"].as_str())
+        .map(|identifier| {
+            // Fall back to the identifier for modes we can't find a name for
+            modes[identifier]["mode"]["name"]
+                .as_str()
+                .unwrap_or(identifier)
+                .to_string()
+        })
+        .collect();
+    trace!("active Focus modes: {:?}", active);
+    Ok(active)
+}
+
+pub fn is_active(name: &str) -> io::Result<bool> {
+    Ok(active_modes()?
+        .iter()
+        .any(|mode| mode.eq_ignore_ascii_case(name)))
+}
+
+/// Call `callback` with whether the Focus mode is on, whenever Focus state changes
+pub fn watch(name: String, callback: impl Fn(bool) + Send + 'static) {
+    trigger_file::watch_dir(&db_dir(), move || {
+        // The files are briefly missing while they're being replaced, so skip those events
+        if let Ok(active) = is_active(&name) {
+            callback(active);
+        }
+    });
+}
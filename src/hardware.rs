@@ -0,0 +1,80 @@
+use crate::power_source;
+use caffeinate2::platform;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::process;
+use std::sync::OnceLock;
+
+extern "C" {
+    fn sysctlbyname(
+        name: *const c_char,
+        value: *mut c_void,
+        size: *mut usize,
+        new_value: *mut c_void,
+        new_size: usize,
+    ) -> c_int;
+}
+
+/// What kind of Mac this is
+#[derive(Debug, Clone, Copy)]
+pub struct Hardware {
+    /// Even when running under Rosetta
+    pub apple_silicon: bool,
+    pub battery: bool,
+}
+
+fn ioreg(class: &str) -> Option<String> {
+    let output = process::Command::new("/usr/sbin/ioreg")
+        .args(["-r", "-c", class, "-d", "1"])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    trace!("ioreg -c {class}: {}", output.status);
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn is_apple_silicon() -> bool {
+    let name = CString::new("hw.optional.arm64").unwrap();
+    let mut value: c_int = 0;
+    let mut size = std::mem::size_of::<c_int>();
+    let result = unsafe {
+        sysctlbyname(
+            name.as_ptr(),
+            &mut value as *mut c_int as *mut c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    trace!("sysctlbyname(hw.optional.arm64) = {result}, value {value}");
+    result == 0 && value == 1
+}
+
+impl Hardware {
+    pub fn detect() -> Hardware {
+        Hardware {
+            apple_silicon: is_apple_silicon(),
+            battery: power_source::battery_charge().is_some(),
+        }
+    }
+
+    /// Whether it's a laptop. This runs ioreg, so it's only looked up when something asks.
+    pub fn lid(&self) -> bool {
+        static LID: OnceLock<bool> = OnceLock::new();
+        *LID.get_or_init(|| {
+            ioreg("IOPMrootDomain").is_some_and(|output| platform::has_lid(&output))
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        platform::describe(self.apple_silicon, self.lid())
+    }
+}
+
+/// Whether any connected drive spins. None if that can't be told.
+pub fn has_rotational_disk() -> Option<bool> {
+    platform::has_rotational_disk(&ioreg("IOBlockStorageDevice")?)
+}
@@ -0,0 +1,177 @@
+//! Message catalog for human-readable output, using Fluent (https://projectfluent.org).
+//! Catalogs live in `locales/<language>/caffeinate2.ftl` and are compiled in.
+
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::FluentResource;
+use once_cell::sync::OnceCell;
+use unic_langid::LanguageIdentifier;
+
+pub use fluent_bundle::{FluentArgs, FluentValue};
+
+/// Built-in catalogs. The first one is the fallback.
+const LOCALES: &[(&str, &str)] = &[("en-US", include_str!("../locales/en-US/caffeinate2.ftl"))];
+
+static BUNDLE: OnceCell<FluentBundle<FluentResource>> = OnceCell::new();
+
+/// Turn a POSIX locale ("de_DE.UTF-8") or language tag ("de-DE") into a language identifier
+pub(crate) fn parse_locale(locale: &str) -> Option<LanguageIdentifier> {
+    let tag = locale.split(['.', '@']).next()?.replace('_', "-");
+    tag.parse().ok()
+}
+
+/// Pick the best built-in catalog: an exact match, then the same language, then the fallback
+fn negotiate(requested: Option<&str>) -> usize {
+    let Some(requested) = requested.and_then(parse_locale) else {
+        return 0;
+    };
+    let available: Vec<LanguageIdentifier> = LOCALES
+        .iter()
+        .map(|(tag, _)| tag.parse().unwrap())
+        .collect();
+    available
+        .iter()
+        .position(|locale| *locale == requested)
+        .or_else(|| {
+            available
+                .iter()
+                .position(|locale| locale.language == requested.language)
+        })
+        .unwrap_or(0)
+}
+
+fn build(requested: Option<&str>) -> FluentBundle<FluentResource> {
+    let (tag, source) = LOCALES[negotiate(requested)];
+    let resource = FluentResource::try_new(source.to_string())
+        .unwrap_or_else(|(_, errors)| panic!("Invalid {tag} catalog: {errors:?}"));
+    let mut bundle = FluentBundle::new_concurrent(vec![tag.parse().unwrap()]);
+    // Unicode isolation marks only show up as garbage in terminals
+    bundle.set_use_isolating(false);
+    bundle.add_resource(resource).unwrap();
+    bundle
+}
+
+/// The language asked for by the environment, in the order gettext uses
+fn env_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Choose the language, from `--lang` if given, otherwise the environment.
+/// Only the first call has any effect.
+pub fn init(lang: Option<&str>) {
+    let requested = lang.map(String::from).or_else(env_locale);
+    BUNDLE.get_or_init(|| build(requested.as_deref()));
+}
+
+/// Look up a message. Use the `tr!` macro rather than calling this directly.
+pub fn message(id: &str, args: Option<&FluentArgs>) -> String {
+    let bundle = BUNDLE.get_or_init(|| build(env_locale().as_deref()));
+    let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+        return id.to_string();
+    };
+    let mut errors = Vec::new();
+    bundle
+        .format_pattern(pattern, args, &mut errors)
+        .into_owned()
+}
+
+/// Look up a message in the catalog, like `tr!("resuming", time = "at 5:00:00 PM")`
+#[macro_export]
+macro_rules! tr {
+    ($id:expr) => {
+        $crate::i18n::message($id, None)
+    };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = $crate::i18n::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::i18n::message($id, Some(&args))
+    }};
+}
+
+/// Spell out a duration, like "1 hour 5 seconds". Seconds are shown unless the duration is
+/// under a second, other units only if nonzero.
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let mut parts = Vec::new();
+    let days = duration.num_days();
+    let hours = duration.num_hours() % 24;
+    let minutes = duration.num_minutes() % 60;
+    let seconds = duration.num_seconds() % 60;
+    let millis = duration.num_milliseconds() % 1000;
+    if days > 0 {
+        parts.push(tr!("days", count = days));
+    }
+    if hours > 0 {
+        parts.push(tr!("hours", count = hours));
+    }
+    if minutes > 0 {
+        parts.push(tr!("minutes", count = minutes));
+    }
+    if seconds > 0 || millis == 0 || !parts.is_empty() {
+        parts.push(tr!("seconds", count = seconds));
+    }
+    if millis > 0 {
+        parts.push(tr!("milliseconds", count = millis));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(super::negotiate(None), 0);
+        assert_eq!(super::negotiate(Some("en_US.UTF-8")), 0);
+        assert_eq!(super::negotiate(Some("en-GB")), 0);
+        // Unknown languages and junk fall back to English
+        assert_eq!(super::negotiate(Some("xx_XX")), 0);
+        assert_eq!(super::negotiate(Some("C")), 0);
+    }
+
+    #[test]
+    fn test_catalogs() {
+        // Every catalog parses (build panics otherwise) and has the banner
+        for (tag, _) in super::LOCALES {
+            let bundle = super::build(Some(tag));
+            assert!(bundle.has_message("preventing-sleep"), "{tag}");
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        let format = |seconds| super::format_duration(chrono::Duration::seconds(seconds));
+        assert_eq!(format(0), "0 seconds");
+        assert_eq!(format(1), "1 second");
+        assert_eq!(format(61), "1 minute 1 second");
+        assert_eq!(format(3600), "1 hour 0 seconds");
+        let format_ms = |millis| super::format_duration(chrono::Duration::milliseconds(millis));
+        assert_eq!(format_ms(500), "500 milliseconds");
+        assert_eq!(format_ms(1250), "1 second 250 milliseconds");
+        assert_eq!(
+            format(2 * 86400 + 3 * 3600 + 4 * 60 + 5),
+            "2 days 3 hours 4 minutes 5 seconds"
+        );
+        assert_eq!(
+            crate::tr!("pid-finished", pid = 12, time = "at 1:00:00 PM", code = 0),
+            "PID 12 finished at 1:00:00 PM with exit code 0"
+        );
+        assert_eq!(
+            crate::tr!(
+                "heartbeat",
+                holding = crate::tr!("still-holding", types = "system"),
+                time = crate::tr!("time-remaining", duration = "2h13m")
+            ),
+            "Still holding: system, 2h13m remaining."
+        );
+        assert_eq!(
+            crate::tr!(
+                "round-done",
+                round = 1,
+                rounds = 4,
+                duration = "5 minutes 0 seconds"
+            ),
+            "Round 1 of 4 done, taking a 5 minutes 0 seconds break."
+        );
+    }
+}
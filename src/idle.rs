@@ -0,0 +1,47 @@
+use caffeinate2::idle_sleep;
+use std::process;
+use std::time::Duration;
+
+/// kCGEventSourceStateHIDSystemState, the events the HID system has seen from every user
+const HID_SYSTEM_STATE: i32 = 1;
+/// kCGAnyInputEventType
+const ANY_INPUT_EVENT: u32 = !0;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventSourceSecondsSinceLastEventType(state: i32, event_type: u32) -> f64;
+}
+
+/// HIDIdleTime from IOHIDSystem, for when CoreGraphics can't tell (like without a window server)
+fn hid_idle_time() -> Option<Duration> {
+    let output = process::Command::new("/usr/sbin/ioreg")
+        .args(["-c", "IOHIDSystem", "-d", "4"])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    let seconds = idle_sleep::parse_idle_time(&String::from_utf8_lossy(&output.stdout))?;
+    Some(Duration::from_secs(seconds.max(0) as u64))
+}
+
+/// How long it's been since anyone used a keyboard, mouse or trackpad
+pub fn idle_time() -> Option<Duration> {
+    let seconds =
+        unsafe { CGEventSourceSecondsSinceLastEventType(HID_SYSTEM_STATE, ANY_INPUT_EVENT) };
+    trace!("CGEventSourceSecondsSinceLastEventType(HID system, any) = {seconds}");
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        hid_idle_time()
+    }
+}
+
+/// `caffeinate2 idle`: the idle time in whole seconds, for scripts
+pub fn print_idle_time() {
+    match idle_time() {
+        Some(idle) => println!("{}", idle.as_secs()),
+        None => {
+            eprintln!("Error: Couldn't read the idle time.");
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Working out when the Mac would have gone to idle sleep if nothing had prevented it: once the
+//! user has been idle for as long as the system sleep timer in Energy Saver/Battery settings.
+
+/// HIDIdleTime (in nanoseconds) from `ioreg -c IOHIDSystem`, in seconds
+pub fn parse_idle_time(output: &str) -> Option<i64> {
+    output.lines().find_map(|line| {
+        let (_, nanoseconds) = line.split_once("\"HIDIdleTime\" = ")?;
+        Some(nanoseconds.trim().parse::<i64>().ok()? / 1_000_000_000)
+    })
+}
+
+/// The system sleep timer in seconds from `pmset -g`, like
+/// ` sleep                1 (sleep prevented by caffeinate2)`. None if it never sleeps.
+pub fn parse_sleep_timer(output: &str) -> Option<i64> {
+    output.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        if words.next()? != "sleep" {
+            return None;
+        }
+        let minutes: i64 = words.next()?.parse().ok()?;
+        (minutes > 0).then_some(minutes * 60)
+    })
+}
+
+/// Counts how many times idle sleep would have happened. That's once for every stretch of
+/// idleness that reaches the sleep timer, since sleeping (or the user coming back) ends it.
+#[derive(Debug, Default)]
+pub struct BlockedSleeps {
+    count: u32,
+    /// Whether the current stretch was counted already
+    counted: bool,
+    last_idle: i64,
+}
+
+impl BlockedSleeps {
+    /// Take a sample of the idle time and the sleep timer (None if it's off), both in seconds
+    pub fn observe(&mut self, idle: i64, timer: Option<i64>) {
+        // Idle time only goes down when the user does something
+        if idle < self.last_idle {
+            self.counted = false;
+        }
+        self.last_idle = idle;
+        if let (false, Some(timer)) = (self.counted, timer) {
+            if idle >= timer {
+                self.count += 1;
+                self.counted = true;
+            }
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse() {
+        let ioreg = "    |   \"HIDIdleTime\" = 125000000000\n";
+        assert_eq!(super::parse_idle_time(ioreg), Some(125));
+
+        let pmset = "System-wide power settings:\nCurrently in use:\n standby              1\n displaysleep         10\n sleep                1 (sleep prevented by caffeinate2)\n";
+        assert_eq!(super::parse_sleep_timer(pmset), Some(60));
+        assert_eq!(super::parse_sleep_timer(" sleep                0\n"), None);
+        assert_eq!(super::parse_sleep_timer(" displaysleep 10\n"), None);
+    }
+
+    #[test]
+    fn test_blocked_sleeps() {
+        let mut blocked = super::BlockedSleeps::default();
+        for idle in [30, 60, 90, 120] {
+            blocked.observe(idle, Some(60));
+        }
+        // Still the same idle stretch
+        assert_eq!(blocked.count(), 1);
+
+        // The user came back and left again
+        for idle in [5, 35, 65] {
+            blocked.observe(idle, Some(60));
+        }
+        assert_eq!(blocked.count(), 2);
+
+        // Sleep turned off
+        for idle in [0, 100] {
+            blocked.observe(idle, None);
+        }
+        assert_eq!(blocked.count(), 2);
+    }
+}
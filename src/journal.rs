@@ -0,0 +1,278 @@
+use crate::low_power;
+use crate::power_management::{self, IOKit};
+use crate::proc_info;
+use crate::session;
+use crate::standby_override;
+use caffeinate2::shared_settings::{Change, Owners, Shared};
+use caffeinate2::standby;
+use nix::fcntl::{Flock, FlockArg};
+use nix::unistd::Uid;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+/// SleepDisabled and Low Power Mode survive reboots, so what changed them has to as well
+const JOURNAL_DIR: &str = "/Library/Application Support/caffeinate2/journal";
+
+/// The system settings a session changed, written before changing them so they can be put back
+/// if it never gets to clean up (like when it's killed with SIGKILL or the Mac loses power)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Journal {
+    pub pid: i32,
+    /// When the process started (Unix seconds), so one that reuses its PID after a reboot isn't
+    /// mistaken for it
+    #[serde(default)]
+    pub started: Option<u64>,
+    /// Sleep types it prevents, as `status` shows them
+    pub types: Vec<String>,
+    /// Assertions it held. The system releases these when it exits, so they're just for reference.
+    pub assertion_ids: Vec<u32>,
+    /// Whether it turns SleepDisabled on
+    pub sleep_disabled: bool,
+    /// Low Power Mode settings it turned off
+    pub low_power: Vec<low_power::Setting>,
+    /// Standby settings it changed, with their original values
+    #[serde(default)]
+    pub standby: Vec<standby::Setting>,
+}
+
+fn start_time(pid: i32) -> Option<u64> {
+    proc_info::bsd_info(pid).map(|info| info.start_tvsec)
+}
+
+fn path(pid: i32) -> PathBuf {
+    PathBuf::from(JOURNAL_DIR).join(format!("{pid}.json"))
+}
+
+fn list() -> Vec<Journal> {
+    let Ok(entries) = fs::read_dir(JOURNAL_DIR) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| fs::read(entry.ok()?.path()).ok())
+        .filter_map(|contents| serde_json::from_slice(&contents).ok())
+        .collect()
+}
+
+impl Journal {
+    pub fn new(pid: i32, types: Vec<String>, sleep_disabled: bool) -> Journal {
+        Journal {
+            pid,
+            started: start_time(pid),
+            types,
+            assertion_ids: Vec::new(),
+            sleep_disabled,
+            low_power: Vec::new(),
+            standby: Vec::new(),
+        }
+    }
+
+    /// Whether the session that wrote it is still running. Journals from before the start time
+    /// was recorded only go by the PID.
+    fn is_running(&self) -> bool {
+        session::is_alive(self.pid)
+            && self
+                .started
+                .is_none_or(|started| start_time(self.pid).is_none_or(|now| now == started))
+    }
+
+    fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(JOURNAL_DIR)?;
+        session::write_atomically(&path(self.pid), &serde_json::to_vec(self)?)
+    }
+
+    /// Record the current state. Call this before making the changes it describes.
+    pub fn write(&self) {
+        // Not while a session that's ending decides whether it's the last owner of something
+        let _lock = lock();
+        let result = self.save();
+        trace!("writing journal {:?}: {result:?}", self);
+        if let Err(err) = result {
+            // Without root the changes are denied anyway
+            if Uid::effective().is_root() {
+                eprintln!(
+                    "Warning: Couldn't write {} ({err}). If caffeinate2 is killed, its changes won't be undone.",
+                    path(self.pid).display()
+                );
+            }
+        }
+    }
+}
+
+/// Held while a session works out whether it's the last owner of a shared setting, acts on it
+/// and updates its journal, so two sessions ending at once can't each leave it to the other.
+/// None without root, which can't change the settings anyway.
+pub fn lock() -> Option<Flock<File>> {
+    let result = fs::create_dir_all(JOURNAL_DIR)
+        .and_then(|()| File::open(JOURNAL_DIR))
+        .and_then(|dir| {
+            Flock::lock(dir, FlockArg::LockExclusive).map_err(|(_, errno)| errno.into())
+        });
+    trace!("locking {JOURNAL_DIR}: {result:?}");
+    result.ok()
+}
+
+/// Record whether a session has SleepDisabled on, once it's turned it on or given it up.
+/// Call this with the lock held.
+pub fn set_sleep_disabled(pid: i32, sleep_disabled: bool) {
+    let Some(mut journal) = fs::read(path(pid))
+        .ok()
+        .and_then(|contents| serde_json::from_slice::<Journal>(&contents).ok())
+    else {
+        return;
+    };
+    journal.sleep_disabled = sleep_disabled;
+    let result = journal.save();
+    trace!("updating journal {:?}: {result:?}", journal);
+}
+
+/// Who owns the shared settings, going by the journals of sessions that are still running.
+/// Hold the lock from here until the change is made and the journal updated.
+pub fn owners() -> Owners {
+    owners_of(&running())
+}
+
+fn running() -> Vec<Journal> {
+    list().into_iter().filter(Journal::is_running).collect()
+}
+
+fn owners_of(journals: &[Journal]) -> Owners {
+    let mut owners = Owners::default();
+    for journal in journals {
+        if journal.sleep_disabled {
+            owners.add(Shared::SleepDisabled, journal.pid);
+        }
+        if !journal.low_power.is_empty() {
+            owners.add(Shared::LowPowerModeOff, journal.pid);
+        }
+        if !journal.standby.is_empty() {
+            owners.add(Shared::StandbySettingsChanged, journal.pid);
+        }
+    }
+    owners
+}
+
+/// The Low Power Mode settings other running sessions turned off. A session that turns it off
+/// after them takes these on, so whichever ends last turns it back on.
+pub fn low_power_of_others(pid: i32) -> Vec<low_power::Setting> {
+    let mut settings: Vec<low_power::Setting> = Vec::new();
+    for journal in running().into_iter().filter(|journal| journal.pid != pid) {
+        for setting in journal.low_power {
+            if !settings.contains(&setting) {
+                settings.push(setting);
+            }
+        }
+    }
+    settings
+}
+
+/// The original standby settings other running sessions changed. These are what has to be put
+/// back, not what the settings are now.
+pub fn standby_of_others(pid: i32) -> Vec<standby::Setting> {
+    let mut settings: Vec<standby::Setting> = Vec::new();
+    for journal in running().into_iter().filter(|journal| journal.pid != pid) {
+        for setting in journal.standby {
+            if !settings
+                .iter()
+                .any(|other| other.source == setting.source && other.key == setting.key)
+            {
+                settings.push(setting);
+            }
+        }
+    }
+    settings
+}
+
+/// The session that held an assertion, if it was recorded in a journal
+pub fn holder_of(assertion_id: u32) -> Option<i32> {
+    list()
+        .into_iter()
+        .find(|journal| journal.assertion_ids.contains(&assertion_id))
+        .map(|journal| journal.pid)
+}
+
+/// Forget the journal after the session put everything back or left it to other sessions
+pub fn clear(pid: i32) {
+    let _ = fs::remove_file(path(pid));
+}
+
+/// Put back what sessions that didn't clean up left changed
+pub fn repair() {
+    let _lock = lock();
+    let own_pid = process::id() as i32;
+    let (running, ended): (Vec<Journal>, Vec<Journal>) = list()
+        .into_iter()
+        .filter(|journal| journal.pid != own_pid)
+        .partition(Journal::is_running);
+
+    let mut owners = owners_of(&running);
+    for journal in ended {
+        trace!("repairing after {:?}", journal);
+        if !Uid::effective().is_root() {
+            eprintln!(
+                "Warning: Session {} ended without putting back the sleep settings it changed. Run caffeinate2 with sudo once to undo them.",
+                journal.pid
+            );
+            continue;
+        }
+        match repair_one(&journal, &mut owners) {
+            Ok(true) => clear(journal.pid),
+            // Left for a later run
+            Ok(false) => {}
+            Err(err) => eprintln!("Error: {err}"),
+        }
+    }
+}
+
+/// Returns whether everything was put back
+fn repair_one(journal: &Journal, owners: &mut Owners) -> Result<bool, String> {
+    // A running session still depends on these, and will put them back itself
+    let sleep_change = journal
+        .sleep_disabled
+        .then(|| owners.remove(Shared::SleepDisabled, journal.pid));
+    let low_power_change = (!journal.low_power.is_empty())
+        .then(|| owners.remove(Shared::LowPowerModeOff, journal.pid));
+    let standby_change = (!journal.standby.is_empty())
+        .then(|| owners.remove(Shared::StandbySettingsChanged, journal.pid));
+    if [sleep_change, low_power_change, standby_change].contains(&Some(Change::Keep)) {
+        return Ok(false);
+    }
+
+    if sleep_change.is_some() {
+        let iokit = IOKit::new();
+        if iokit.get_sleep_disabled() {
+            iokit.set_sleep_disabled(false).map_err(|code| {
+                format!(
+                    "Couldn't re-enable sleep, which session {} left disabled ({}). Run `sudo pmset -a disablesleep 0` to fix it.",
+                    journal.pid,
+                    power_management::describe_error(code)
+                )
+            })?;
+            println!(
+                "Re-enabled sleep, which session {} left disabled.",
+                journal.pid
+            );
+        }
+    }
+    if low_power_change.is_some() {
+        if !low_power::restore(&journal.low_power) {
+            return Ok(false);
+        }
+        println!(
+            "Turned Low Power Mode back on, which session {} left off.",
+            journal.pid
+        );
+    }
+    if standby_change.is_some() {
+        if !standby_override::restore(&journal.standby) {
+            return Ok(false);
+        }
+        println!(
+            "Put back the standby settings, which session {} left changed.",
+            journal.pid
+        );
+    }
+    Ok(true)
+}
@@ -0,0 +1,271 @@
+use crate::apply::SleepType;
+use crate::assertions::{Assertion, Assertions, Kind};
+use crate::cleanup::{Cleanup, SessionState};
+use crate::lockdown::Lockdown;
+use crate::powerd_watch;
+use crate::session::{self, Session};
+use crate::sleep_log;
+use crate::wake_watch;
+use caffeinate2::duration_parser::try_parse_duration_ms;
+use caffeinate2::exit_code;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde::Serialize;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often user activity is declared. Short enough that the screensaver never starts
+/// and the display is back to full brightness within seconds of waking.
+const TICK: Duration = Duration::from_secs(5);
+
+/// How long a status client gets to send its request and take the response
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// The most of a status request that's read, headers included. A GET is much smaller.
+const MAX_REQUEST_BYTES: u64 = 8192;
+
+/// What the status endpoint reports
+#[derive(Serialize, Debug, Default)]
+struct KioskStatus {
+    pid: i32,
+    /// Unix timestamp of when kiosk mode started
+    started: i64,
+    /// The presentation command, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    command_running: bool,
+    /// How many times the command has been started again after exiting
+    restarts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_exit_code: Option<i32>,
+    /// Unix timestamp of the last time the Mac woke up, if it slept anyway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_wake: Option<i64>,
+    assertions: Vec<u32>,
+}
+
+type SharedStatus = Arc<Mutex<KioskStatus>>;
+
+fn build_assertions() -> Assertions {
+    let mut assertions = Assertions::default();
+    for kind in [
+        Kind::Assertion("PreventUserIdleDisplaySleep"),
+        Kind::Assertion("PreventUserIdleSystemSleep"),
+        // Resets the idle timer, so the display doesn't dim and the screensaver doesn't start
+        Kind::UserActivity,
+    ] {
+        assertions.push(Arc::new(Assertion::new(kind)));
+    }
+    assertions
+}
+
+/// Declare user activity every tick, and straight away after the Mac wakes up (if it slept anyway)
+fn keep_awake(assertions: Assertions, status: SharedStatus) {
+    assertions.keep_user_active(TICK);
+    wake_watch::watch(move |slept| {
+        println!("Woke up from sleep, re-asserting.");
+        sleep_log::report_sleep(slept);
+        assertions.refresh();
+        assertions.redeclare_user_activity();
+        let mut status = status.lock().unwrap();
+        status.last_wake = Some(chrono::Local::now().timestamp());
+        status.assertions = assertions.ids();
+    });
+}
+
+fn respond(mut stream: TcpStream, status: &SharedStatus) -> std::io::Result<()> {
+    // A client that sends nothing (or never stops sending) mustn't tie up the connection
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_BYTES));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, we don't need any of them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (code, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/" | "/status")) => {
+            ("200 OK", serde_json::to_string(&*status.lock().unwrap())?)
+        }
+        (Some("GET"), _) => ("404 Not Found", String::from(r#"{"error":"not found"}"#)),
+        _ => (
+            "405 Method Not Allowed",
+            String::from(r#"{"error":"only GET is supported"}"#),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Answer `GET /status` with the kiosk's state as JSON, for monitoring
+fn serve_status(address: &str, status: SharedStatus) {
+    let listener = TcpListener::bind(address).unwrap_or_else(|err| {
+        eprintln!("Error: Couldn't listen on {address}: {err}");
+        process::exit(1);
+    });
+    println!("Serving status on http://{address}/status");
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // On its own thread, so a slow client doesn't keep monitoring from getting through
+            let status = Arc::clone(&status);
+            thread::spawn(move || {
+                if let Err(err) = respond(stream, &status) {
+                    trace!("status request failed: {err}");
+                }
+            });
+        }
+    });
+}
+
+/// Keep a signage Mac awake and its display at full brightness with no screensaver,
+/// optionally running a presentation command and starting it again whenever it exits
+pub fn run(command: Vec<String>, restart_delay: &str, http: Option<&str>, name: Option<String>) {
+    let restart_delay = try_parse_duration_ms(restart_delay).unwrap_or_else(|err| {
+        eprintln!("Error: Invalid --restart-delay: {err}");
+        process::exit(1);
+    });
+
+    if let Some(lockdown) = Lockdown::load() {
+        let result = lockdown
+            .check_types(&[SleepType::Display, SleepType::System, SleepType::UserActive])
+            .and_then(|_| match lockdown.max_duration_ms().unwrap() {
+                Some(_) => Err(String::from(
+                    "Your administrator limits how long sessions last, so kiosk mode isn't available.",
+                )),
+                None => Ok(()),
+            });
+        if let Err(err) = result {
+            eprintln!("Error: {err}");
+            process::exit(1);
+        }
+    }
+
+    let pid = process::id() as i32;
+    let command_str = (!command.is_empty()).then(|| command.join(" "));
+    let status = Arc::new(Mutex::new(KioskStatus {
+        pid,
+        started: chrono::Local::now().timestamp(),
+        command: command_str.clone(),
+        ..Default::default()
+    }));
+
+    let assertions = build_assertions();
+    assertions.refresh();
+    status.lock().unwrap().assertions = assertions.ids();
+    keep_awake(assertions.clone(), status.clone());
+    powerd_watch::watch(assertions.clone());
+    if let Some(address) = http {
+        serve_status(address, status.clone());
+    }
+
+    let session = Session {
+        version: session::VERSION,
+        pid,
+        name: Some(name.unwrap_or_else(|| String::from("kiosk"))),
+        started: chrono::Local::now().timestamp(),
+        until: None,
+        types: vec![
+            String::from("display (no dim)"),
+            String::from("system"),
+            String::from("user active"),
+        ],
+        command: command_str.clone(),
+        waitfor: None,
+        managed_by: None,
+        progress: None,
+        user: session::user_name(session::owner_uid()),
+        uid: Some(session::owner_uid()),
+        output: None,
+        args: Vec::new(),
+        tags: Default::default(),
+    };
+    if let Err(err) = session.register() {
+        trace!("couldn't register session: {err}");
+    }
+
+    let cleanup = Cleanup::new(SessionState {
+        assertions,
+        pid,
+        low_power: Vec::new(),
+        standby: Vec::new(),
+    });
+    let stopping = Arc::new(AtomicBool::new(false));
+    let child_pid = Arc::new(AtomicI32::new(0));
+
+    let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap();
+    let signal_cleanup = cleanup.clone();
+    let signal_stopping = stopping.clone();
+    let signal_child_pid = child_pid.clone();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            signal_stopping.store(true, Ordering::SeqCst);
+            let child = signal_child_pid.load(Ordering::SeqCst);
+            if child != 0 {
+                // The main thread cleans up once the command exits
+                let _ = signal::kill(Pid::from_raw(child), Signal::try_from(signal).ok());
+                continue;
+            }
+            signal_cleanup.run();
+            process::exit(exit_code::from_signal(signal));
+        }
+    });
+
+    let Some(command_str) = command_str else {
+        println!("Kiosk mode on until Ctrl+C pressed.");
+        thread::park();
+        return;
+    };
+
+    println!("Kiosk mode on, running: {command_str}");
+    let exit_code = loop {
+        let mut child = process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&command_str)
+            .spawn()
+            .unwrap_or_else(|err| {
+                eprintln!("Error: Couldn't start command: {err}");
+                cleanup.run();
+                process::exit(1);
+            });
+        child_pid.store(child.id() as i32, Ordering::SeqCst);
+        status.lock().unwrap().command_running = true;
+
+        let code = exit_code::from_status(child.wait().unwrap());
+        child_pid.store(0, Ordering::SeqCst);
+        {
+            let mut status = status.lock().unwrap();
+            status.command_running = false;
+            status.last_exit_code = Some(code);
+        }
+        if stopping.load(Ordering::SeqCst) {
+            break code;
+        }
+
+        println!(
+            "Command exited with code {code}, restarting in {}.",
+            caffeinate2::i18n::format_duration(chrono::Duration::milliseconds(restart_delay))
+        );
+        thread::sleep(Duration::from_millis(restart_delay as u64));
+        if stopping.load(Ordering::SeqCst) {
+            break code;
+        }
+        status.lock().unwrap().restarts += 1;
+    };
+    cleanup.run();
+    process::exit(exit_code);
+}
@@ -0,0 +1,116 @@
+use nix::sys::event;
+use std::io;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// How often to check on a job that's loaded but not running (like between respawns)
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A launchd job as `launchctl list` shows it
+#[derive(Debug, PartialEq)]
+pub struct Job {
+    pub pid: Option<i32>,
+    /// Exit status of its last run
+    pub last_status: i32,
+}
+
+/// Find a job in `launchctl list` output, which has a PID, last exit status and label on each line
+fn parse_list(output: &str, label: &str) -> Option<Job> {
+    output.lines().skip(1).find_map(|line| {
+        let mut fields = line.split('\t');
+        let (pid, status, job_label) = (fields.next()?, fields.next()?, fields.next()?);
+        (job_label == label).then(|| Job {
+            pid: pid.parse().ok(),
+            last_status: status.parse().unwrap_or(0),
+        })
+    })
+}
+
+/// Look up a job in our launchd domain (the system domain when running as root).
+/// None if it isn't loaded.
+pub fn lookup(label: &str) -> io::Result<Option<Job>> {
+    let output = process::Command::new("/bin/launchctl")
+        .arg("list")
+        .stderr(process::Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "launchctl exited with {}",
+            output.status
+        )));
+    }
+    let job = parse_list(&String::from_utf8_lossy(&output.stdout), label);
+    trace!("launchctl list {label}: {job:?}");
+    Ok(job)
+}
+
+/// Block until a process exits (or is already gone)
+fn wait_for_exit(pid: i32) {
+    let kq = event::Kqueue::new().unwrap();
+    let kev = event::KEvent::new(
+        pid as usize,
+        event::EventFilter::EVFILT_PROC,
+        event::EventFlag::EV_ADD | event::EventFlag::EV_ENABLE | event::EventFlag::EV_ONESHOT,
+        event::FilterFlag::NOTE_EXIT,
+        0,
+        0,
+    );
+    let mut eventlist = [kev];
+    let result = kq.kevent(&[kev], &mut eventlist, None);
+    trace!("kevent for job PID {pid}: {result:?}");
+}
+
+/// Wait while the job is loaded, following it to its new PID whenever it respawns.
+/// Returns the exit status of its last run.
+pub fn wait_while_loaded(label: &str, verbose: bool) -> i32 {
+    let mut last_pid = None;
+    let mut last_status = 0;
+    loop {
+        let job = match lookup(label) {
+            Ok(Some(job)) => job,
+            Ok(None) => return last_status,
+            Err(err) => {
+                eprintln!("Error: Couldn't ask launchd about {label}: {err}");
+                process::exit(1);
+            }
+        };
+        last_status = job.last_status;
+        match job.pid {
+            Some(pid) => {
+                if verbose && last_pid.is_some_and(|last_pid| last_pid != pid) {
+                    println!("{label} respawned as PID {pid}.");
+                }
+                last_pid = Some(pid);
+                wait_for_exit(pid);
+                // Give launchd a moment to notice before asking it again
+                thread::sleep(Duration::from_millis(100));
+            }
+            // Loaded but not running, like between respawns
+            None => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_list() {
+        let output = "PID\tStatus\tLabel\n123\t0\tcom.example.backup\n-\t78\tcom.example.sync\n";
+        assert_eq!(
+            super::parse_list(output, "com.example.backup"),
+            Some(super::Job {
+                pid: Some(123),
+                last_status: 0
+            })
+        );
+        assert_eq!(
+            super::parse_list(output, "com.example.sync"),
+            Some(super::Job {
+                pid: None,
+                last_status: 78
+            })
+        );
+        assert_eq!(super::parse_list(output, "com.example"), None);
+    }
+}
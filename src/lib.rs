@@ -0,0 +1,24 @@
+//! Platform-independent pieces of caffeinate2, shared by the binaries and benchmarks.
+
+pub mod assertion_types;
+pub mod caffeinate_args;
+pub mod command_chain;
+pub mod duration_parser;
+pub mod exit_code;
+pub mod exit_reason;
+pub mod i18n;
+pub mod idle_sleep;
+pub mod overlap;
+pub mod platform;
+#[cfg(feature = "scripting")]
+pub mod policy_script;
+pub mod power_log;
+pub mod preflight;
+pub mod prompt_format;
+pub mod sandbox;
+pub mod session_simulation;
+pub mod shared_settings;
+pub mod standby;
+pub mod thermal;
+pub mod time_format;
+pub mod timer;
@@ -0,0 +1,129 @@
+use crate::apply::SleepType;
+use caffeinate2::duration_parser::try_parse_duration_ms;
+use caffeinate2::i18n::format_duration;
+use nix::unistd::{Gid, Group, Uid, User};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::process;
+
+/// Written by IT, so it's somewhere only admins can change
+const POLICY_PATH: &str = "/Library/Application Support/caffeinate2/policy.toml";
+
+/// GID of the macOS "admin" group
+const ADMIN_GID: u32 = 80;
+
+/// Limits an administrator has put on caffeinate2
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Lockdown {
+    /// Longest a session can last (same format as --timeout)
+    max_duration: Option<String>,
+    /// Don't allow --entirely
+    #[serde(default)]
+    forbid_entirely: bool,
+    /// Only these sleep types can be prevented
+    allowed_types: Option<Vec<SleepType>>,
+    /// Also apply the limits to admins (they're exempt by default)
+    #[serde(default)]
+    apply_to_admins: bool,
+}
+
+fn is_admin() -> bool {
+    let uid = Uid::current();
+    if uid.is_root() {
+        return true;
+    }
+    let Ok(Some(user)) = User::from_uid(uid) else {
+        return false;
+    };
+    user.gid == Gid::from_raw(ADMIN_GID)
+        || Group::from_gid(Gid::from_raw(ADMIN_GID))
+            .ok()
+            .flatten()
+            .is_some_and(|group| group.mem.contains(&user.name))
+}
+
+impl Lockdown {
+    /// The limits that apply to the current user, if there are any.
+    /// An unreadable policy is an error rather than no policy, so it can't be bypassed by breaking it.
+    pub fn load() -> Option<Lockdown> {
+        let contents = match fs::read_to_string(POLICY_PATH) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                eprintln!("Error: Couldn't read admin policy {POLICY_PATH}: {err}");
+                process::exit(1);
+            }
+        };
+        let lockdown: Lockdown = toml::from_str(&contents)
+            .map_err(|err| err.to_string())
+            .and_then(|lockdown: Lockdown| lockdown.max_duration_ms().map(|_| lockdown))
+            .unwrap_or_else(|err| {
+                eprintln!("Error: Invalid admin policy {POLICY_PATH}: {err}");
+                process::exit(1);
+            });
+        trace!("admin policy: {:?}", lockdown);
+
+        if is_admin() && !lockdown.apply_to_admins {
+            trace!("admin policy doesn't apply to admins");
+            return None;
+        }
+        Some(lockdown)
+    }
+
+    /// The maximum session length in milliseconds, if there is one
+    pub fn max_duration_ms(&self) -> Result<Option<i64>, String> {
+        self.max_duration
+            .as_deref()
+            .map(try_parse_duration_ms)
+            .transpose()
+    }
+
+    pub fn check_types(&self, types: &[SleepType]) -> Result<(), String> {
+        if self.forbid_entirely && types.contains(&SleepType::Entirely) {
+            return Err(String::from(
+                "Your administrator doesn't allow preventing sleep entirely.",
+            ));
+        }
+        if let Some(allowed) = &self.allowed_types {
+            if let Some(denied) = types
+                .iter()
+                .find(|sleep_type| !allowed.contains(sleep_type))
+            {
+                return Err(format!(
+                    "Your administrator doesn't allow preventing {} sleep.",
+                    denied.session_name()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check --assert types. Ones that don't amount to an allowed sleep type are refused.
+    pub fn check_assertions(&self, assertion_types: &[&str]) -> Result<(), String> {
+        let Some(allowed) = &self.allowed_types else {
+            return Ok(());
+        };
+        match assertion_types.iter().find(|assertion_type| {
+            !SleepType::for_assertion(assertion_type)
+                .is_some_and(|sleep_type| allowed.contains(&sleep_type))
+        }) {
+            Some(denied) => Err(format!(
+                "Your administrator doesn't allow {denied} assertions."
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Check a session length, in milliseconds
+    pub fn check_duration(&self, millis: i64) -> Result<(), String> {
+        match self.max_duration_ms().unwrap() {
+            Some(max) if millis > max => Err(format!(
+                "Your administrator limits sessions to {}.",
+                format_duration(chrono::Duration::milliseconds(max))
+            )),
+            _ => Ok(()),
+        }
+    }
+}
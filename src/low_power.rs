@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// How often --respect-lpm checks whether Low Power Mode was turned on or off
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A power source's Low Power Mode setting, as `pmset -g custom` shows it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Setting {
+    /// pmset's flag for the power source, like "-b" for battery
+    source: String,
+    /// "lowpowermode", or "powermode" on Macs that also have High Power Mode
+    key: String,
+    value: u32,
+}
+
+impl Setting {
+    fn is_low_power(&self) -> bool {
+        // powermode is 0 (automatic), 1 (low power) or 2 (high power)
+        self.value == 1
+    }
+}
+
+/// A "lowpowermode 1" or "powermode 1" line
+fn parse_line(line: &str) -> Option<(String, u32)> {
+    let mut fields = line.split_whitespace();
+    let (key, value) = (fields.next()?, fields.next()?);
+    if !matches!(key, "lowpowermode" | "powermode") {
+        return None;
+    }
+    Some((key.to_string(), value.parse().ok()?))
+}
+
+/// Low Power Mode settings per power source, from `pmset -g custom`
+fn parse_custom(output: &str) -> Vec<Setting> {
+    let mut settings = Vec::new();
+    let mut source = None;
+    for line in output.lines() {
+        match line.trim_end() {
+            "Battery Power:" => source = Some("-b"),
+            "AC Power:" => source = Some("-c"),
+            "UPS Power:" => source = Some("-u"),
+            line => {
+                if let (Some(source), Some((key, value))) = (source, parse_line(line)) {
+                    settings.push(Setting {
+                        source: source.to_string(),
+                        key,
+                        value,
+                    });
+                }
+            }
+        }
+    }
+    settings
+}
+
+pub fn pmset(args: &[&str]) -> io::Result<String> {
+    let output = process::Command::new("/usr/bin/pmset")
+        .args(args)
+        .output()?;
+    trace!("pmset {args:?}: {}", output.status);
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether Low Power Mode is on for the current power source. None if this Mac doesn't have it.
+pub fn is_enabled() -> Option<bool> {
+    let output = pmset(&["-g"]).ok()?;
+    output
+        .lines()
+        .find_map(parse_line)
+        .map(|(_, value)| value == 1)
+}
+
+/// The power sources Low Power Mode is on for, which is what `disable` changes
+pub fn enabled() -> io::Result<Vec<Setting>> {
+    Ok(parse_custom(&pmset(&["-g", "custom"])?)
+        .into_iter()
+        .filter(Setting::is_low_power)
+        .collect())
+}
+
+/// Turn Low Power Mode off for these power sources (which needs root)
+pub fn disable(settings: &[Setting]) -> io::Result<()> {
+    for setting in settings {
+        pmset(&[&setting.source, &setting.key, "0"])?;
+    }
+    Ok(())
+}
+
+/// Put back what `disable` changed. Returns whether all of it was.
+pub fn restore(settings: &[Setting]) -> bool {
+    let mut restored = true;
+    for setting in settings {
+        if let Err(err) = pmset(&[&setting.source, &setting.key, &setting.value.to_string()]) {
+            eprintln!(
+                "Error: Couldn't turn Low Power Mode back on ({err}). Run `sudo pmset {} {} {}` to fix it.",
+                setting.source, setting.key, setting.value
+            );
+            restored = false;
+        }
+    }
+    restored
+}
+
+/// Call `callback` with the new state whenever Low Power Mode is turned on or off
+pub fn watch(mut enabled: bool, callback: impl Fn(bool) + Send + 'static) {
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+        if let Some(now) = is_enabled().filter(|now| *now != enabled) {
+            enabled = now;
+            callback(enabled);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Setting;
+
+    #[test]
+    fn test_parse_custom() {
+        let output = "Battery Power:\n lowpowermode         1\n standby              1\nAC Power:\n lowpowermode         0\n sleep                1\n";
+        assert_eq!(
+            super::parse_custom(output),
+            vec![
+                Setting {
+                    source: String::from("-b"),
+                    key: String::from("lowpowermode"),
+                    value: 1
+                },
+                Setting {
+                    source: String::from("-c"),
+                    key: String::from("lowpowermode"),
+                    value: 0
+                },
+            ]
+        );
+        assert_eq!(
+            super::parse_line(" powermode            2"),
+            Some((String::from("powermode"), 2))
+        );
+        assert_eq!(super::parse_line(" sleep                1"), None);
+    }
+}
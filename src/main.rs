@@ -1,225 +1,2305 @@
 #![cfg(target_os = "macos")]
 
+#[macro_use]
+mod trace;
+
+mod alarm;
+mod apply;
+mod assertions;
+mod attach;
+mod blocked_sleeps;
+#[cfg(feature = "calendar")]
+mod calendar;
+mod capabilities;
+mod child_output;
+mod cleanup;
+mod console_user;
+mod ctl;
+mod disk_keepalive;
+mod focus;
+mod hardware;
+mod idle;
+mod journal;
+mod kiosk;
+mod launchd_job;
+mod lockdown;
+mod low_power;
+mod migrate;
+mod notify;
+#[cfg(feature = "objc")]
+mod objc_bridge;
+mod other_assertions;
+mod output_log;
+mod persist;
+mod plugin;
 mod power_management;
+mod power_off;
+mod power_source;
+mod powerd_watch;
+mod preflight_check;
+mod proc_info;
+mod progress;
+mod prompt;
+mod screen_lock;
+#[cfg(feature = "scripting")]
+mod script_policy;
+mod serve;
+mod session;
+mod sleep_log;
+mod smc;
+mod standby_override;
+mod start_history;
+mod status;
+mod task_policy;
+mod trigger_file;
+mod until;
+mod volume_watch;
+mod wake_on_lan;
+mod wake_watch;
+mod why;
 
-use clap::Parser;
+use apply::SleepType;
+use assertions::{Assertion, Assertions, Kind};
+use caffeinate2::assertion_types;
+use caffeinate2::command_chain;
+use caffeinate2::duration_parser::{
+    format_short_duration, parse_duration, parse_duration_ms, try_parse_duration_ms,
+};
+use caffeinate2::exit_code;
+use caffeinate2::exit_reason::{self, ExitReason};
+use caffeinate2::i18n::{self, format_duration};
+use caffeinate2::sandbox;
+use caffeinate2::session_simulation::{self, Plan, Release};
+use caffeinate2::standby;
+use caffeinate2::thermal;
+use caffeinate2::time_format::{self, TimeFormat};
+use caffeinate2::timer;
+use caffeinate2::tr;
+use chrono::TimeZone;
+use clap::{Parser, Subcommand};
+use cleanup::{Cleanup, SessionState};
+use hardware::Hardware;
+use journal::Journal;
 use nix::{sys::event, unistd};
-use signal_hook::{consts::SIGINT, iterator::Signals};
+use power_source::BatteryWarning;
+use session::{Selector, Session};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
+use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process;
+use std::sync::atomic::{AtomicI32, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+const DATE_FMT: &str = "%B %-d";
+
+/// How often --user-active is declared again. The shortest display sleep timer is a minute.
+const USER_ACTIVITY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often -v prints thermal pressure, temperatures and fan speeds
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
-fn set_assertions(iokit: &power_management::IOKit, args: &Args, state: bool) -> Vec<u32> {
+/// Runs a command under a sandbox profile (see --sandbox)
+const SANDBOX_EXEC: &str = "/usr/bin/sandbox-exec";
+
+/// How often to print heartbeat lines when stdout isn't a terminal and --heartbeat-log isn't given
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The assertions this session needs. Nothing is created until they're refreshed.
+fn build_assertions(args: &Args, hardware: &Hardware) -> Assertions {
+    let mut assertions = Assertions::default();
     if args.dry_run {
         // Don't actually sleep
-        return Vec::new();
+        return assertions;
     }
 
     if args.entirely {
         // Prevents the system from sleeping entirely.
-        iokit.set_sleep_disabled(true).unwrap_or_else(|_| {
-            eprintln!("Error: Insufficient privileges to disable sleep. Try running with sudo.");
+        assertions.push(Arc::new(Assertion::new(Kind::SleepDisabled)));
+    }
+    if args.display || args.no_dim {
+        // Prevents the display from sleeping automatically.
+        assertions.push(Arc::new(Assertion::new(Kind::Assertion(
+            "PreventUserIdleDisplaySleep",
+        ))));
+    }
+    if args.disk == Some(None) {
+        // Prevents the disk from stopping when idle.
+        assertions.push(Arc::new(Assertion::new(Kind::Assertion("PreventDiskIdle"))));
+    }
+    if args.system {
+        // Prevents the system from sleeping automatically.
+        assertions.push(Arc::new(Assertion::new(Kind::Assertion(
+            "PreventUserIdleSystemSleep",
+        ))));
+    }
+    if args.system_on_ac {
+        // Prevents the system from sleeping, but only while on AC power.
+        let assertion = Arc::new(Assertion::new(Kind::Assertion("PreventSystemSleep")));
+        assertion.add_condition("on AC", power_source::on_ac_power());
+
+        // Without a battery, it never switches
+        if hardware.battery {
+            let watched = assertion.clone();
+            let verbose = args.verbose > 0;
+            power_source::watch(move |on_ac| {
+                if watched.set_condition("on AC", on_ac) && verbose {
+                    if on_ac {
+                        println!("Switched to AC power, preventing system sleep.");
+                    } else {
+                        println!("Switched to battery power, allowing system sleep.");
+                    }
+                }
+            });
+        }
+        assertions.push(assertion);
+    }
+    if args.user_active || args.no_dim {
+        // Declares the user is active. Repeating this resets the idle timer, which keeps the display from dimming.
+        assertions.push(Arc::new(Assertion::new(Kind::UserActivity)));
+    }
+    if args.network_client {
+        // Keeps the system awake for network clients of services running on it.
+        assertions.push(Arc::new(Assertion::new(Kind::Assertion(
+            "NetworkClientActive",
+        ))));
+    }
+    for assertion_type in assertion_types(args) {
+        assertions.push(Arc::new(Assertion::new(Kind::Assertion(assertion_type))));
+    }
+
+    assertions
+}
+
+/// The --assert types, by their current names. These were checked in main().
+fn assertion_types(args: &Args) -> Vec<&'static str> {
+    args.assert
+        .iter()
+        .map(|name| assertion_types::canonical(name).unwrap())
+        .collect()
+}
+
+/// The --until-* (or --while-http) condition that ends the session, if there is one
+fn until_condition(args: &Args) -> Option<until::Condition> {
+    let http = |url: &String, while_matching| until::Condition::Http {
+        url: url.clone(),
+        expect: until::HttpExpectation {
+            status: args.expect_status,
+            body: args.expect_body.clone(),
+        },
+        while_matching,
+    };
+    if let Some(host) = &args.until_reachable {
+        Some(until::Condition::Reachable(host.clone()))
+    } else if let Some(host) = &args.until_unreachable {
+        Some(until::Condition::Unreachable(host.clone()))
+    } else if let Some(url) = &args.until_http {
+        Some(http(url, false))
+    } else {
+        args.while_http.as_ref().map(|url| http(url, true))
+    }
+}
+
+/// Print what `--dry-run --simulate` expects to happen over `span`, assuming the battery keeps
+/// draining like it does now and --until-* conditions are never met
+fn print_simulation(
+    args: &Args,
+    hardware: &Hardware,
+    span: chrono::Duration,
+    break_duration: chrono::Duration,
+    interval: Duration,
+) {
+    use session_simulation::Event;
+
+    let battery = hardware.battery.then(|| {
+        let charge = power_source::battery_charge().unwrap_or(100) as f64;
+        session_simulation::Battery {
+            charge,
+            on_ac: power_source::on_ac_power(),
+            // Without an estimate from macOS, about 10% an hour is typical for light use
+            drain_per_hour: power_source::time_to_empty()
+                .map_or(10.0, |minutes| charge * 60.0 / minutes as f64),
+        }
+    });
+    let plan = Plan {
+        span,
+        timeout: args
+            .timeout
+            .clone()
+            .map(|timeout| chrono::Duration::milliseconds(parse_duration_ms(timeout))),
+        rounds: args.repeat.unwrap_or(1),
+        break_duration,
+        battery,
+        only_on_ac: args.system_on_ac,
+        monitor: until_condition(args).map(|condition| {
+            (
+                condition.banner(),
+                chrono::Duration::from_std(interval).unwrap(),
+            )
+        }),
+    };
+
+    let from = chrono::Local::now();
+    println!("Simulating the next {}:", format_duration(span));
+    if let Some(battery) = battery {
+        if battery.on_ac {
+            println!("On AC power with the battery at {:.0}%.", battery.charge);
+        } else {
+            println!(
+                "On battery at {:.0}%, draining about {:.0}% an hour.",
+                battery.charge, battery.drain_per_hour
+            );
+        }
+    }
+    let events = session_simulation::simulate(&plan);
+    for (elapsed, event) in &events {
+        let time = (from + *elapsed).format("%a %H:%M");
+        match event {
+            Event::Monitoring { what, every } => println!(
+                "{time}  Wait {what}, checking every {} (assumed to never happen).",
+                format_duration(*every)
+            ),
+            Event::Round { round, of } => println!("{time}  Round {round} of {of}."),
+            Event::Held => println!("{time}  Prevent sleep."),
+            Event::Released(Release::Break { round }) => {
+                println!("{time}  Allow sleep for the break after round {round}.")
+            }
+            Event::Released(Release::OnBattery) => {
+                println!("{time}  Allow sleep, since --system-on-ac doesn't hold on battery.")
+            }
+            Event::Released(Release::BatteryCritical) => {
+                println!("{time}  Allow sleep, since the battery is almost empty.")
+            }
+            Event::BatteryEarly(charge) => {
+                println!("{time}  First low battery warning, at about {charge:.0}%.")
+            }
+            Event::BatteryFinal(charge) => {
+                println!("{time}  Final low battery warning, at about {charge:.0}%.")
+            }
+            Event::TimedOut => println!("{time}  The session ends."),
+            Event::BatteryEmpty => println!("{time}  The battery runs out and the Mac sleeps."),
+        }
+    }
+    if !events
+        .iter()
+        .any(|(_, event)| matches!(event, Event::TimedOut | Event::BatteryEmpty))
+    {
+        println!("Still going after {}.", format_duration(span));
+    }
+}
+
+/// Names of the sleep types prevented, as shown by `status`
+fn sleep_types(args: &Args) -> Vec<String> {
+    let disk = match &args.disk {
+        Some(Some(volume)) => format!("disk ({})", volume.display()),
+        _ => String::from("disk"),
+    };
+    let types = [
+        (args.display && !args.no_dim, "display"),
+        (args.no_dim, "display (no dim)"),
+        (args.disk.is_some(), &disk),
+        (args.system, "system"),
+        (args.system_on_ac, "system on AC"),
+        (args.entirely, "entire system"),
+        (args.user_active, "user active"),
+        (args.network_client, "network client"),
+    ];
+    types
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| name.to_string())
+        .chain(assertion_types(args).into_iter().map(String::from))
+        .collect()
+}
+
+/// The sleep types the arguments ask for, in the terms admin policies use
+fn requested_types(args: &Args) -> Vec<SleepType> {
+    let types = [
+        (args.display || args.no_dim, SleepType::Display),
+        (args.disk.is_some(), SleepType::Disk),
+        (args.system, SleepType::System),
+        (args.system_on_ac, SleepType::SystemOnAc),
+        (args.entirely, SleepType::Entirely),
+        (args.user_active || args.no_dim, SleepType::UserActive),
+        (args.network_client, SleepType::NetworkClient),
+    ];
+    types
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, sleep_type)| *sleep_type)
+        .collect()
+}
+
+/// Clap args
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, disable_version_flag = true)]
+struct Args {
+    /// Verbose mode, for caffeinate2's own messages (see --quiet-child and --prefix-child for the
+    /// wrapped command's). Pass -vvv to also trace every IOKit call and kevent.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Language for messages, like "en-US". Defaults to LANG.
+    #[arg(long, global = true)]
+    lang: Option<String>,
+
+    /// How to show times: 12h, 24h, or a strftime pattern like "%H:%M".
+    /// Defaults to the usual clock for your locale.
+    #[arg(long, global = true, value_name = "FORMAT")]
+    time_format: Option<TimeFormat>,
+
+    /// Label for this session, so `status` and `ctl` can target it by name
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Metadata like team=ios or ticket=OPS-12, shown by `status` and `ctl stop` so it's clear
+    /// on a shared Mac who's keeping it awake and why. Can be repeated.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = session::parse_tag)]
+    tag: Vec<(String, String)>,
+
+    /// If a session with the same --name is already running, exit immediately instead
+    #[arg(long, requires = "name")]
+    unique: bool,
+
+    /// With --unique, make the running session last at least as long as --timeout asks for
+    #[arg(long, requires = "unique")]
+    extend_if_running: bool,
+
+    /// When caffeinate2 is being started over and over (like by a script in a loop), extend a
+    /// running session that prevents the same sleep types instead of starting another one
+    #[arg(long, requires = "DURATION", conflicts_with_all = ["command", "PID"])]
+    debounce: bool,
+
+    /// Stop any running session with the same --name or command, then take over from it
+    #[arg(long, conflicts_with = "unique")]
+    replace: bool,
+
+    /// Keep preventing sleep from a background process and return immediately.
+    /// Follow it with `caffeinate2 attach` and stop it with `caffeinate2 ctl stop`.
+    #[arg(long)]
+    detach: bool,
+
+    /// Set by --detach: this is the background process, and nobody sees its output
+    #[arg(long, hide = true)]
+    detached: bool,
+
+    /// Start this session again after a reboot or logout, until its timeout runs out.
+    /// Installs a LaunchAgent that runs `caffeinate2 rearm` at login.
+    #[arg(long, requires = "DURATION", conflicts_with_all = ["command", "PID"])]
+    persist: bool,
+
+    /// Set by `rearm`: which recorded --persist session this is
+    #[arg(long, hide = true)]
+    persisted_id: Option<String>,
+
+    /// Set by `ctl adopt`: the session to take over once ours is running
+    #[arg(long, hide = true, value_name = "PID")]
+    adopt: Option<i32>,
+
+    /// Set by `assert`: have powerd release the assertions at the timeout by itself
+    #[arg(long, hide = true, requires = "DURATION")]
+    kernel_timeout: bool,
+
+    /// Set by `apply`: the policy file this session belongs to
+    #[arg(long, hide = true, requires = "name")]
+    managed_by: Option<String>,
+
+    /// Dry run. Don't actually prevent sleep.
+    /// Useful for testing.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// With --dry-run, print how the session would go over this long (like "8h"): --repeat
+    /// rounds and breaks, the battery running down and --system-on-ac letting go on battery
+    #[arg(long, value_name = "DURATION", requires = "dry_run")]
+    simulate: Option<String>,
+
+    /// Drop root privileges in command.
+    /// You need root to disable sleep entirely,
+    /// but some programs don't want to run as root.
+    #[arg(long)]
+    drop_root: bool,
+
+    /// Disable display sleep
+    #[arg(short, long)]
+    display: bool,
+
+    /// Keep the display at full brightness, instead of only keeping it from sleeping
+    /// (it still dims before it would have slept). For dashboards and signage.
+    #[arg(long)]
+    no_dim: bool,
+
+    /// Disable disk idle sleep. Pass a volume (--disk=/Volumes/Backup)
+    /// to only keep that drive from spinning down.
+    #[arg(short = 'm', long, name = "VOLUME", num_args = 0..=1, require_equals = true)]
+    disk: Option<Option<PathBuf>>,
+
+    /// Disable idle system sleep. [DEFAULT]
+    #[arg(short = 'i', long)]
+    system: bool,
+
+    /// Disable system sleep while not on battery
+    #[arg(short, long)]
+    system_on_ac: bool,
+
+    /// Disable system sleep entirely (ignores lid closing)
+    #[arg(short, long)]
+    entirely: bool,
+
+    /// Allow sleep while the screen is locked, and prevent it again once it's unlocked
+    #[arg(long, conflicts_with = "hold_through_lock")]
+    release_on_lock: bool,
+
+    /// Run this shell command when macOS warns that the battery is low, with the warning
+    /// ("early" or "final") in CAFFEINATE2_BATTERY_WARNING. At the final warning, sleep is
+    /// allowed whether or not this is given, until the battery recovers.
+    #[arg(long, value_name = "HOOK")]
+    on_low_battery: Option<String>,
+
+    /// Keep preventing sleep while the screen is locked (the default)
+    #[arg(long)]
+    hold_through_lock: bool,
+
+    /// With fast user switching, allow sleep while another user is using the Mac,
+    /// and prevent it again once the session's user switches back
+    #[arg(long)]
+    pause_when_switched_out: bool,
+
+    /// Wake the display if it's asleep when the session starts
+    #[arg(long)]
+    wake_display: bool,
+
+    /// Turn Low Power Mode off for the session (needs root), and back on once it ends
+    #[arg(long, conflicts_with = "respect_lpm")]
+    disable_lpm: bool,
+
+    /// Allow sleep while Low Power Mode is on, instead of working against it
+    #[arg(long)]
+    respect_lpm: bool,
+
+    /// Raise the standby delay to cover the session (needs root), and put it back once it
+    /// ends, so a Mac that sleeps anyway doesn't hibernate and drop its connections
+    #[arg(long)]
+    no_standby: bool,
+
+    /// Make sure the Mac can be woken over the network (Wake for network access) during the
+    /// session, turning it on until the session ends if it's off (which needs root)
+    #[arg(long)]
+    maintain_wake_on_lan: bool,
+
+    /// Allow sleep once the CPU has been at least this hot (in °C) for --max-wake-temp-for,
+    /// and prevent it again once it's cooled down. A safety net for closed-lid sessions.
+    #[arg(long, value_name = "CELSIUS")]
+    max_wake_temp: Option<f64>,
+
+    /// How long the CPU has to stay over --max-wake-temp (or back under it) to count
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "1m",
+        requires = "max_wake_temp"
+    )]
+    max_wake_temp_for: String,
+
+    /// Declare the user is active.
+    /// If the display is off, this option turns it on and prevents it from going into idle sleep.
+    #[arg(short, long)]
+    user_active: bool,
+
+    /// With --user-active, only declare it once at startup (like caffeinate2 used to),
+    /// so the display can sleep again after the usual idle time
+    #[arg(long, requires = "user_active")]
+    user_active_once: bool,
+
+    /// Keep the Mac awake for network clients (NetworkClientActive), for servers running on it
+    /// like file sharing or a dev server. Unlike --system, it doesn't count as someone using it.
+    #[arg(long)]
+    network_client: bool,
+
+    /// Also hold a power management assertion of this type, like PreventSystemSleep.
+    /// Old IOKit names (like NoIdleSleepAssertion) are accepted too.
+    #[arg(long, value_name = "TYPE")]
+    assert: Vec<String>,
+
+    /// Wait for X seconds.
+    /// Also supports time units (like "1 day 2 hours 3mins 4s").
+    #[arg(short, long, name = "DURATION")]
+    timeout: Option<String>,
+
+    /// With a command and --timeout, keep preventing sleep until the timeout runs out even if
+    /// the command finishes first. Without it, the session ends when the command does.
+    #[arg(long, requires_all = ["DURATION", "command"])]
+    hold_full_timeout: bool,
+
+    /// Go through the timeout this many times, taking a --break between them (like a pomodoro timer).
+    /// Sleep is allowed during the breaks.
+    #[arg(long, value_name = "N", requires = "DURATION", conflicts_with_all = ["command", "PID"])]
+    repeat: Option<u32>,
+
+    /// How long the breaks between --repeat rounds are
+    #[arg(
+        long = "break",
+        value_name = "DURATION",
+        default_value = "5m",
+        requires = "repeat"
+    )]
+    break_duration: String,
+
+    /// Refuse to start a session that's likely to be cut short (low battery, a scheduled
+    /// shutdown, little space for --log-output), instead of only warning. Timeouts of an hour
+    /// or more and commands with --log-output are checked.
+    #[arg(long)]
+    strict_preflight: bool,
+
+    /// Prevent sleep until the calendar event that's happening now ends
+    #[cfg(feature = "calendar")]
+    #[arg(long, conflicts_with = "DURATION")]
+    during_event: bool,
+
+    /// Builds without the calendar feature only accept --during-event to say it's missing
+    #[cfg(not(feature = "calendar"))]
+    #[arg(long, hide = true)]
+    during_event: bool,
+
+    /// Wait for program with PID X to complete and pass its exit code.
+    #[arg(short, long, name = "PID")]
+    waitfor: Option<i32>,
+
+    /// Wait while this launchd job (by label) is loaded, following it when it respawns,
+    /// and pass on its last exit status
+    #[arg(long, value_name = "LABEL", conflicts_with_all = ["command", "PID", "DURATION"])]
+    waitfor_job: Option<String>,
+
+    /// Never stand in the way of the user: release everything (SleepDisabled included) and end
+    /// as soon as the Mac is shut down, restarted or put to sleep
+    #[arg(long, conflicts_with = "command")]
+    until_shutdown_blocked: bool,
+
+    /// Keep preventing sleep until you log out, like for a LaunchAgent that keeps the Mac awake
+    /// while you're logged in. Switching users with fast user switching doesn't count.
+    #[arg(long, conflicts_with_all = ["command", "PID", "DURATION", "waitfor_job"])]
+    until_logout: bool,
+
+    /// Wait until this host answers pings
+    #[arg(long, value_name = "HOST", conflicts_with_all = ["command", "PID", "DURATION", "waitfor_job"])]
+    until_reachable: Option<String>,
+
+    /// Wait until this host stops answering pings, like a NAS that's being copied from going offline
+    #[arg(long, value_name = "HOST", conflicts_with_all = ["command", "PID", "DURATION", "waitfor_job", "until_reachable"])]
+    until_unreachable: Option<String>,
+
+    /// Poll this URL and wait until it gives the expected response (see --expect-status and --expect-body)
+    #[arg(long, value_name = "URL", conflicts_with_all = ["command", "PID", "DURATION", "waitfor_job", "until_reachable", "until_unreachable"])]
+    until_http: Option<String>,
+
+    /// Poll this URL and wait while it gives the expected response, ending once it stops
+    #[arg(long, value_name = "URL", conflicts_with_all = ["command", "PID", "DURATION", "waitfor_job", "until_reachable", "until_unreachable", "until_http"])]
+    while_http: Option<String>,
+
+    /// HTTP status the response needs for --until-http and --while-http
+    #[arg(long, value_name = "CODE", default_value_t = 200)]
+    expect_status: u16,
+
+    /// Text the response body needs to contain for --until-http and --while-http
+    #[arg(long, value_name = "TEXT")]
+    expect_body: Option<String>,
+
+    /// How often to check --until-*, --while-http and --while-plugin conditions
+    #[arg(long, value_name = "DURATION", default_value = "10s")]
+    interval: String,
+
+    /// Estimate how the --waitfor PID is getting on (how far into its file rsync, cp, tar or
+    /// ditto is, and how fast it's reading) and show it in `status`
+    #[arg(long, requires = "PID")]
+    progress: bool,
+
+    /// When running until Ctrl+C, print a reminder every DURATION
+    /// that sleep is still being prevented.
+    #[arg(long, name = "INTERVAL")]
+    remind: Option<String>,
+
+    /// Also show reminders as macOS notifications
+    #[arg(long)]
+    notify: bool,
+
+    /// Print a line like "Still holding: system, 2h13m remaining." every INTERVAL, so a CI log
+    /// doesn't look hung. On every 5 minutes by default when stdout isn't a terminal.
+    #[arg(long, value_name = "INTERVAL")]
+    heartbeat_log: Option<String>,
+
+    /// Don't print heartbeat lines, even when stdout isn't a terminal
+    #[arg(long, conflicts_with = "heartbeat_log")]
+    no_heartbeat_log: bool,
+
+    /// Only prevent sleep while this volume (a name under /Volumes, or a path) is mounted
+    #[arg(long, value_name = "VOLUME")]
+    while_mounted: Option<String>,
+
+    /// Only prevent sleep while this file exists, so other tools can
+    /// toggle the session by creating and removing it
+    #[arg(long, name = "PATH")]
+    trigger_file: Option<PathBuf>,
+
+    /// Only prevent sleep while this Focus mode (like "Work") is on
+    #[arg(long, value_name = "FOCUS")]
+    while_focus: Option<String>,
+
+    /// Let a rhai script decide what to keep awake, run every --interval (see the README)
+    #[cfg(feature = "scripting")]
+    #[arg(long, value_name = "FILE")]
+    policy_script: Option<PathBuf>,
+
+    /// Builds without the scripting feature only accept --policy-script to say it's missing
+    #[cfg(not(feature = "scripting"))]
+    #[arg(long, hide = true, value_name = "FILE")]
+    policy_script: Option<PathBuf>,
+
+    /// Only prevent sleep while this condition plugin (an executable in
+    /// ~/.config/caffeinate2/conditions) says to. Can be repeated.
+    #[arg(long, value_name = "NAME")]
+    while_plugin: Vec<String>,
+
+    /// If the wrapped command prints nothing for this long, consider it hung (see --on-output-idle).
+    /// Its output is passed through a pipe instead of going straight to the terminal.
+    #[arg(long, value_name = "DURATION", requires = "command")]
+    output_idle_timeout: Option<String>,
+
+    /// What to do when --output-idle-timeout runs out
+    #[arg(
+        long,
+        value_enum,
+        default_value = "warn",
+        requires = "output_idle_timeout"
+    )]
+    on_output_idle: child_output::OnIdle,
+
+    /// Don't show the wrapped command's stdout (its stderr still shows, and --log-output still gets both)
+    #[arg(long, requires = "command")]
+    quiet_child: bool,
+
+    /// Put this in front of every line the wrapped command prints, like "[job] ".
+    /// {n} is replaced by the command's number, to tell --parallel commands apart.
+    #[arg(long, value_name = "PREFIX", requires = "command")]
+    prefix_child: Option<String>,
+
+    /// Also write the wrapped command's output to this file, one timestamped line at a time.
+    /// Like --output-idle-timeout, its output goes through a pipe.
+    #[arg(long, value_name = "PATH", requires = "command")]
+    log_output: Option<PathBuf>,
+
+    /// Rotate the --log-output file once it reaches this size (like "10M"), keeping 5 old ones
+    #[arg(long, value_name = "SIZE", requires = "log_output")]
+    log_max_size: Option<String>,
+
+    /// Run this command after the others, like another `;;`-separated command. Can be repeated.
+    #[arg(long, value_name = "COMMAND", requires = "command")]
+    then: Vec<String>,
+
+    /// With several commands, don't run the rest once one fails
+    #[arg(long, requires = "command")]
+    stop_on_error: bool,
+
+    /// Run the wrapped command with this nice value, from -20 to 20 (below 0 needs root)
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "command",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-20..=20)
+    )]
+    nice: Option<i32>,
+
+    /// Run the wrapped command at this quality of service, so a long job at background or
+    /// utility leaves the Mac responsive
+    #[arg(long, value_enum, requires = "command")]
+    qos: Option<task_policy::Qos>,
+
+    /// Run the wrapped command as a background task, like `taskpolicy -b`: the lowest CPU
+    /// priority and throttled disk and network I/O, while sleep is still prevented
+    #[arg(long, requires = "command", conflicts_with = "qos")]
+    background_task: bool,
+
+    /// Run the wrapped command in a sandbox: a preset (no-network, read-only-home, or strict
+    /// for both) or a sandbox-exec profile file, for scripts you don't fully trust
+    #[arg(long, value_name = "PROFILE", requires = "command")]
+    sandbox: Option<String>,
+
+    /// When the session ends by itself (not when it's stopped), play a sound (sound or
+    /// sound:NAME), flash the screen (flash) or say something (say:TEXT). Can be repeated.
+    #[arg(long, value_name = "ALARM")]
+    alarm: Vec<alarm::Alarm>,
+
+    /// When the session ends, say why on stderr, as a sentence or as JSON (--exit-reason=json),
+    /// so scripts can tell a timeout from the command failing
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    exit_reason: Option<exit_reason::Format>,
+
+    /// Don't print how long sleep was prevented when the session ends,
+    /// or how long the command took and what it used once it finishes
+    #[arg(long)]
+    no_summary: bool,
+
+    /// With several commands, run them all at once instead of one after another
+    #[arg(long, requires = "command", conflicts_with = "stop_on_error")]
+    parallel: bool,
+
+    /// Wait for given command to complete (takes priority above timeout and pid).
+    /// Separate several commands with ';;' to run them one after another.
+    #[arg()]
+    command: Option<Vec<String>>,
+
+    /// Print version
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// With --version, print a JSON report of what this build and Mac support (features,
+    /// IOKit symbols, macOS version, whether root is available), for install scripts and bug reports
+    #[arg(long, requires = "version")]
+    json: bool,
+
+    #[command(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Show running caffeinate2 sessions
+    Status {
+        /// Print in xbar/SwiftBar plugin format
+        #[arg(long)]
+        xbar: bool,
+
+        /// Print a single line for scripts and shell prompts,
+        /// like "active system,display until=2024-06-01T18:00 pid=123" or "inactive"
+        #[arg(long, conflicts_with = "xbar")]
+        short: bool,
+
+        #[command(flatten)]
+        selector: Selector,
+    },
+    /// Print a short segment for PS1 or starship while sessions are running, and nothing otherwise
+    Prompt {
+        /// Template with {icon}, {remaining}, {types}, {count} and {names} placeholders
+        #[arg(long, default_value = prompt::DEFAULT_FORMAT)]
+        format: String,
+
+        #[command(flatten)]
+        selector: Selector,
+    },
+    /// Print how many seconds it's been since the keyboard, mouse or trackpad was last used
+    Idle,
+    /// Prevent one kind of sleep for a while from the background and return right away.
+    /// powerd releases the assertion by itself when the time is up.
+    Assert {
+        /// What to keep awake
+        #[arg(long = "type", value_enum, default_value = "system")]
+        sleep_type: SleepType,
+
+        /// How long (same format as --timeout)
+        #[arg(long = "for", value_name = "DURATION")]
+        duration: String,
+
+        /// Label for the session, which `release` also accepts
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// End your sessions early, like ones started by `assert`, and check that powerd
+    /// released their assertions
+    Release {
+        /// PID (as `assert` printed it), name, or the ID of an assertion the session holds
+        #[arg(required_unless_present = "all_mine")]
+        id: Option<String>,
+
+        /// All of your sessions, and settings left changed by crashed ones
+        #[arg(long, conflicts_with = "id")]
+        all_mine: bool,
+    },
+    /// Start a background session if none of yours is running, otherwise stop yours
+    Toggle {
+        /// How long the new session should last (same format as --timeout)
+        duration: Option<String>,
+
+        /// Only consider (and start) sessions with this name
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Start and stop sessions to match a policy file (see the README for the format)
+    Apply {
+        policy: PathBuf,
+
+        /// Only print what would change
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Print when sessions would start and stop over this long (like "24h"),
+        /// as if `apply` ran every minute, without changing anything
+        #[arg(long, value_name = "DURATION", conflicts_with = "dry_run")]
+        simulate: Option<String>,
+    },
+    /// Answer ctl requests (one JSON object per line) on stdin, e.g. over `ssh host caffeinate2 serve --stdio`
+    Serve {
+        /// Talk over stdin/stdout (currently the only transport)
+        #[arg(long, required = true)]
+        stdio: bool,
+    },
+    /// Keep a signage Mac awake with the display at full brightness and no screensaver,
+    /// running a presentation command (if given) and restarting it whenever it exits
+    Kiosk {
+        /// How long to wait before restarting the command
+        #[arg(long, value_name = "DURATION", default_value = "5s")]
+        restart_delay: String,
+
+        /// Address to serve the status endpoint (GET /status) on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8642")]
+        http: String,
+
+        /// Don't serve the status endpoint
+        #[arg(long)]
+        no_http: bool,
+
+        /// Label for the session (defaults to "kiosk")
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Presentation command to keep running
+        #[arg(trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Explain recent sleeps from the power management log: why each one happened,
+    /// which assertions changed just before, and what woke the Mac up
+    WhyDidItSleep {
+        /// How many sleeps to show
+        #[arg(long, value_name = "N", default_value_t = 5)]
+        last: usize,
+
+        /// Include going back to sleep after maintenance wakes
+        #[arg(long)]
+        all: bool,
+    },
+    /// Follow a running session's status (and time left) in this terminal, without affecting it
+    Attach {
+        #[command(flatten)]
+        selector: Selector,
+    },
+    /// Start --persist sessions that should still be running (run at login by the LaunchAgent)
+    Rearm,
+    /// Replace running Apple caffeinate processes with caffeinate2 sessions that do the same
+    Migrate {
+        /// Stop the original caffeinate processes once their replacements are running
+        #[arg(long)]
+        kill: bool,
+
+        /// Only print what would be started
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Control running caffeinate2 sessions
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    /// Stop running sessions, releasing their assertions
+    Stop {
+        #[command(flatten)]
+        selector: Selector,
+    },
+    /// Print running sessions in a machine-readable format
+    Status {
+        #[arg(long, value_enum, default_value = "json")]
+        format: status::Format,
+
+        #[command(flatten)]
+        selector: Selector,
+    },
+    /// Make running sessions with a timeout last at least DURATION from now
+    Extend {
+        /// Same format as --timeout
+        duration: String,
+
+        #[command(flatten)]
+        selector: Selector,
+    },
+    /// Take over a running session in this terminal. Its assertions are re-created
+    /// by this process, then the old one exits.
+    Adopt {
+        #[command(flatten)]
+        selector: Selector,
+    },
+}
+
+/// Whether the wrapped commands got `signal` already: Ctrl+C goes to the terminal's whole
+/// foreground process group, which they're in while we are. Many tools take a second SIGINT
+/// to mean "quit right now", so it mustn't be sent twice.
+fn reached_children(signal: i32) -> bool {
+    signal == SIGINT
+        && unistd::tcgetpgrp(std::io::stdin()).is_ok_and(|pgrp| pgrp == unistd::getpgrp())
+}
+
+/// Exit for a flag whose Cargo feature this build was made without
+#[cfg(any(not(feature = "calendar"), not(feature = "scripting")))]
+fn missing_feature(flag: &str, feature: &str) -> ! {
+    eprintln!(
+        "Error: This build doesn't include the `{feature}` feature, which {flag} needs. Reinstall with `cargo install caffeinate2 --features {feature}`."
+    );
+    process::exit(1);
+}
+
+/// Start the --on-low-battery hook, without waiting for it
+fn run_low_battery_hook(hook: &str, warning: BatteryWarning) {
+    let child = process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(hook)
+        .env("CAFFEINATE2_BATTERY_WARNING", warning.name())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            // Reap it when it's done
+            thread::spawn(move || child.wait());
+        }
+        Err(err) => eprintln!("Warning: Couldn't run the --on-low-battery hook: {err}"),
+    }
+}
+
+/// Format a Unix timestamp for messages, including the date if it isn't today
+fn format_time(timestamp: i64) -> String {
+    let time = chrono::Local.timestamp_opt(timestamp, 0).unwrap();
+    format_datetime(
+        &time,
+        time.date_naive() != chrono::Local::now().date_naive(),
+    )
+}
+
+/// "at 5:00:00 PM", or "on March 2 at 5:00:00 PM" if `with_date`
+fn format_datetime(time: &chrono::DateTime<chrono::Local>, with_date: bool) -> String {
+    let time_str = time_format::current().time(time);
+    if with_date {
+        tr!(
+            "on-date-at-time",
+            date = time.format(DATE_FMT).to_string(),
+            time = time_str
+        )
+    } else {
+        tr!("at-time", time = time_str)
+    }
+}
+
+/// What a wrapped command used, like `time -l` would report it
+fn usage_summary(command: &str, wall_time: Duration, usage: &proc_info::ChildUsage) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut max_rss = usage.max_rss as f64;
+    let mut unit = 0;
+    while max_rss >= 1000.0 && unit < UNITS.len() - 1 {
+        max_rss /= 1000.0;
+        unit += 1;
+    }
+    format!(
+        "`{command}` took {} (user {:.2}s, system {:.2}s, max memory {max_rss:.0} {}).",
+        format_duration(chrono::Duration::from_std(wall_time).unwrap()),
+        usage.user_time.as_secs_f64(),
+        usage.system_time.as_secs_f64(),
+        UNITS[unit]
+    )
+}
+
+/// What a session did, like "Prevented display, system sleep for 2h 13m.", and how many
+/// times the Mac would have gone to idle sleep otherwise (if that was counted)
+fn session_summary(types: &[String], held_for: Duration, blocked_sleeps: Option<u32>) -> String {
+    let mut summary = format!(
+        "Prevented {} sleep for {}.",
+        types.join(", "),
+        format_short_duration(chrono::Duration::from_std(held_for).unwrap())
+    );
+    match blocked_sleeps {
+        Some(0) => summary += " The Mac wouldn't have gone to idle sleep in that time anyway.",
+        Some(1) => summary += " Without it, the Mac would have gone to idle sleep once.",
+        Some(count) => {
+            summary += &format!(" Without it, the Mac would have gone to idle sleep {count} times.")
+        }
+        None => {}
+    }
+    summary
+}
+
+/// Our arguments, split where the wrapped command's own arguments start (at `--`), since
+/// anything after that is the command's even if it looks like one of our flags
+fn split_args() -> (Vec<String>, Vec<String>) {
+    let mut ours: Vec<String> = std::env::args().skip(1).collect();
+    let command = match ours.iter().position(|arg| arg == "--") {
+        Some(separator) => ours.split_off(separator),
+        None => Vec::new(),
+    };
+    (ours, command)
+}
+
+/// Our arguments minus the one-off flags, so a session started with them again
+/// (by `rearm` or `ctl adopt`) looks like the original
+fn reusable_args() -> Vec<String> {
+    let mut args = Vec::new();
+    let (ours, command) = split_args();
+    let mut original = ours.into_iter();
+    while let Some(arg) = original.next() {
+        if arg == "--persisted-id" || arg == "--adopt" {
+            original.next();
+        } else if arg != "--detach"
+            && arg != "--detached"
+            && !arg.starts_with("--persisted-id=")
+            && !arg.starts_with("--adopt=")
+        {
+            args.push(arg);
+        }
+    }
+    args.extend(command);
+    args
+}
+
+/// Record a --persist session so `rearm` can bring it back until `until` (Unix milliseconds)
+fn persist_session(id: &str, until: i64, pid: i32) {
+    let args = reusable_args();
+    let persisted = persist::PersistedSession { args, until, pid };
+    if let Err(err) = persisted.save(id) {
+        eprintln!("Error: Couldn't record session for --persist: {err}");
+        return;
+    }
+    match persist::install_launch_agent() {
+        Ok(Some(path)) => println!("Installed {} to re-arm sessions at login.", path.display()),
+        Ok(None) => {}
+        Err(err) => eprintln!("Error: Couldn't install LaunchAgent: {err}"),
+    }
+}
+
+fn main() {
+    let mut args = Args::parse();
+    // Meant to run on every shell prompt, so it skips setting up anything it doesn't need
+    if args.version {
+        capabilities::print_version(args.json);
+        return;
+    }
+    if let Some(Action::Idle) = &args.action {
+        idle::print_idle_time();
+        return;
+    }
+    if let Some(Action::Status {
+        short: true,
+        selector,
+        ..
+    }) = &args.action
+    {
+        status::print_short(selector);
+        return;
+    }
+    if let Some(Action::Prompt { format, selector }) = &args.action {
+        prompt::print(selector, format);
+        return;
+    }
+    i18n::init(args.lang.as_deref());
+    time_format::init(args.time_format.clone());
+
+    if let Some(action) = args.action.take() {
+        match action {
+            Action::Status {
+                xbar: true,
+                selector,
+                ..
+            } => status::print_xbar(&selector),
+            Action::Status {
+                xbar: false,
+                selector,
+                ..
+            } => status::print_status(&selector),
+            Action::Prompt { .. } | Action::Idle => unreachable!("handled before setting up"),
+            Action::Toggle {
+                duration,
+                name,
+                json,
+            } => ctl::toggle(duration, name, json),
+            Action::Assert {
+                sleep_type,
+                duration,
+                name,
+            } => ctl::assert_once(sleep_type, duration, name),
+            Action::Release { id, all_mine } => ctl::release(id, all_mine),
+            Action::Apply {
+                policy,
+                dry_run,
+                simulate,
+            } => apply::apply(&policy, dry_run, simulate.as_deref()),
+            Action::Serve { stdio: _ } => serve::serve_stdio(),
+            Action::Kiosk {
+                restart_delay,
+                http,
+                no_http,
+                name,
+                command,
+            } => {
+                trace::set_verbosity(args.verbose);
+                kiosk::run(
+                    command,
+                    &restart_delay,
+                    (!no_http).then_some(http.as_str()),
+                    name,
+                )
+            }
+            Action::WhyDidItSleep { last, all } => why::why_did_it_sleep(last, all),
+            Action::Attach { selector } => attach::attach(&selector),
+            Action::Rearm => persist::rearm(),
+            Action::Migrate { kill, dry_run } => migrate::migrate(kill, dry_run),
+            Action::Ctl {
+                command: CtlCommand::Stop { selector },
+            } => ctl::stop(&selector),
+            Action::Ctl {
+                command: CtlCommand::Status { format, selector },
+            } => status::print_structured(&selector, format),
+            Action::Ctl {
+                command: CtlCommand::Extend { duration, selector },
+            } => ctl::extend(&selector, duration),
+            Action::Ctl {
+                command: CtlCommand::Adopt { selector },
+            } => ctl::adopt(&selector),
+        }
+        return;
+    }
+
+    #[cfg(not(feature = "calendar"))]
+    if args.during_event {
+        missing_feature("--during-event", "calendar");
+    }
+    #[cfg(not(feature = "scripting"))]
+    if args.policy_script.is_some() {
+        missing_feature("--policy-script", "scripting");
+    }
+
+    let default_types = !(args.display
+        || args.no_dim
+        || args.disk.is_some()
+        || args.system
+        || args.system_on_ac
+        || args.entirely
+        || args.user_active
+        || args.network_client
+        || !args.assert.is_empty());
+    if default_types {
+        // Default to system sleep if no other options are specified
+        args.system = true;
+    }
+
+    if !cfg!(target_os = "macos") {
+        panic!("This program only works on macOS.");
+    }
+
+    for name in &args.assert {
+        match assertion_types::try_canonical(name) {
+            Ok(current) if current != name => {
+                eprintln!("Warning: \"{name}\" is a deprecated name, using {current} instead.")
+            }
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("Error: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    trace::set_verbosity(args.verbose);
+    if args.verbose > 0 {
+        println!("DEBUG {:#?}", &args);
+    }
+
+    let hardware = Hardware::detect();
+    if args.verbose > 0 {
+        println!("Running on an {}.", hardware.describe());
+        if args.entirely && !hardware.lid() {
+            println!("This Mac has no lid to close, so -e only adds blocking sleep from the Apple menu to what -i does.");
+        }
+        if args.system_on_ac && !hardware.battery {
+            println!("This Mac has no battery, so --system-on-ac always prevents sleep.");
+        }
+    }
+    if default_types && !hardware.battery && !hardware.lid() {
+        // What people usually want kept awake at a desk is the display
+        println!("Tip: On a desktop Mac, -d keeps the display on and the screensaver away. Pass -i to only keep the system awake, without this tip.");
+    }
+    if args.disk == Some(None) && hardware::has_rotational_disk() == Some(false) {
+        eprintln!("Warning: This Mac only has solid-state storage, which doesn't spin down, so --disk has nothing to do.");
+    }
+
+    // Print types of sleep prevented
+    let mut types = Vec::new();
+    if args.no_dim {
+        types.push(tr!("sleep-type-display-no-dim"));
+    } else if args.display {
+        types.push(tr!("sleep-type-display"));
+    }
+    match &args.disk {
+        Some(Some(volume)) => types.push(tr!(
+            "sleep-type-disk-volume",
+            volume = volume.display().to_string()
+        )),
+        Some(None) => types.push(tr!("sleep-type-disk")),
+        None => {}
+    }
+    if args.system {
+        types.push(tr!("sleep-type-system"));
+    }
+    if args.system_on_ac {
+        types.push(tr!("sleep-type-system-on-ac"));
+    }
+    if args.entirely {
+        types.push(tr!("sleep-type-entirely"));
+    }
+    if args.user_active {
+        types.push(tr!("sleep-type-user-active"));
+    }
+    if args.network_client {
+        types.push(tr!("sleep-type-network-client"));
+    }
+    types.extend(assertion_types(&args).into_iter().map(String::from));
+    let sleep_str = tr!("preventing-sleep", types = types.join(" "));
+
+    #[cfg(feature = "calendar")]
+    if args.during_event {
+        match calendar::current_event() {
+            Ok(Some((title, end))) => {
+                println!(
+                    "Keeping awake for \"{title}\", which ends {}.",
+                    format_time(end)
+                );
+                let remaining = end - chrono::Local::now().timestamp();
+                args.timeout = Some(remaining.max(0).to_string());
+            }
+            Ok(None) => {
+                eprintln!("Error: There's no calendar event happening right now.");
+                process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Error: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    // Started again with the same arguments after it crashed or was killed
+    let mut resumed = false;
+    if args.persist && args.persisted_id.is_none() && args.adopt.is_none() {
+        args.persisted_id = persist::find_interrupted(&reusable_args());
+        resumed = args.persisted_id.is_some();
+    }
+
+    if let Some(id) = &args.persisted_id {
+        // Only what's left of the original timeout
+        let Some(persisted) = persist::PersistedSession::load(id) else {
+            eprintln!("Error: No persisted session {id}.");
+            process::exit(1);
+        };
+        let remaining = persisted.until - chrono::Local::now().timestamp_millis();
+        if remaining <= 0 {
+            persist::remove(id);
+            process::exit(0);
+        }
+        if resumed {
+            println!(
+                "Resuming the interrupted session, with {} left.",
+                format_duration(chrono::Duration::milliseconds(remaining))
+            );
+        }
+        args.timeout = Some(format!("{remaining}ms"));
+    }
+
+    if let Some(old) = args.adopt {
+        let Some(adopted) = Session::load(old) else {
+            eprintln!("Error: No caffeinate2 session with PID {old}.");
+            process::exit(1);
+        };
+        if let Some(until) = adopted.until {
+            // Only what's left of its timeout
+            let remaining = until * 1000 - chrono::Local::now().timestamp_millis();
+            if remaining <= 0 {
+                println!("Session {old} is about to end anyway.");
+                process::exit(0);
+            }
+            args.timeout = Some(format!("{remaining}ms"));
+        }
+        // It's the session these would find, and it's about to go away
+        args.unique = false;
+        args.replace = false;
+    }
+
+    if let Some(lockdown) = lockdown::Lockdown::load() {
+        let enforce = |result: Result<(), String>| {
+            if let Err(err) = result {
+                eprintln!("Error: {err}");
+                process::exit(1);
+            }
+        };
+        enforce(lockdown.check_types(&requested_types(&args)));
+        enforce(lockdown.check_assertions(&assertion_types(&args)));
+        if let Some(max) = lockdown.max_duration_ms().unwrap() {
+            if args.command.is_some() {
+                // A command can run for as long as it likes, and --timeout doesn't apply to it
+                enforce(Err(String::from(
+                    "Your administrator limits how long sessions last, so commands can't be wrapped.",
+                )));
+            }
+            // These wait on something else instead of a timeout, so the cap can't apply to them
+            if let Some(flag) = [
+                (args.waitfor.is_some(), "--waitfor"),
+                (args.waitfor_job.is_some(), "--waitfor-job"),
+                (args.until_logout, "--until-logout"),
+                (args.until_reachable.is_some(), "--until-reachable"),
+                (args.until_unreachable.is_some(), "--until-unreachable"),
+                (args.until_http.is_some(), "--until-http"),
+                (args.while_http.is_some(), "--while-http"),
+            ]
+            .into_iter()
+            .find_map(|(set, flag)| set.then_some(flag))
+            {
+                enforce(Err(format!(
+                    "Your administrator limits how long sessions last, so {flag} can't be used."
+                )));
+            }
+            match &args.timeout {
+                Some(timeout) => {
+                    // --repeat goes through the timeout again after every break
+                    let rounds = i64::from(args.repeat.unwrap_or(1));
+                    let breaks = match args.repeat {
+                        Some(_) => parse_duration_ms(args.break_duration.clone())
+                            .saturating_mul(rounds - 1),
+                        None => 0,
+                    };
+                    let total = parse_duration_ms(timeout.clone())
+                        .saturating_mul(rounds)
+                        .saturating_add(breaks);
+                    enforce(lockdown.check_duration(total))
+                }
+                None => {
+                    println!(
+                        "Your administrator limits sessions to {}.",
+                        format_duration(chrono::Duration::milliseconds(max))
+                    );
+                    args.timeout = Some(format!("{max}ms"));
+                }
+            }
+        }
+    }
+
+    let timeout_duration = args
+        .timeout
+        .clone()
+        .map(|timeout| chrono::Duration::try_milliseconds(parse_duration_ms(timeout)).unwrap());
+
+    let heartbeat_interval = match (&args.heartbeat_log, args.no_heartbeat_log) {
+        (_, true) => None,
+        (Some(interval), false) => {
+            let seconds = parse_duration(interval.clone());
+            if seconds <= 0 {
+                eprintln!("Error: Heartbeat interval must be positive!");
+                process::exit(1);
+            }
+            Some(Duration::from_secs(seconds as u64))
+        }
+        // A CI log that goes quiet for hours looks like a hang
+        (None, false) => {
+            (!std::io::stdout().is_terminal() && !args.detached).then_some(HEARTBEAT_INTERVAL)
+        }
+    };
+
+    let remind_interval = args.remind.clone().map(|interval| {
+        let seconds = parse_duration(interval);
+        if seconds <= 0 {
+            eprintln!("Error: Reminder interval must be positive!");
+            process::exit(1);
+        }
+        chrono::Duration::try_seconds(seconds).unwrap()
+    });
+
+    if args.until_logout
+        && !console_user::logged_in().is_none_or(|uids| uids.contains(&session::owner_uid()))
+    {
+        eprintln!(
+            "Error: You aren't logged in to a GUI session, so there's no logout to wait for."
+        );
+        process::exit(1);
+    }
+
+    if let Some(label) = &args.waitfor_job {
+        match launchd_job::lookup(label) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                eprintln!("Error: No launchd job {label} is loaded.");
+                process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Error: Couldn't ask launchd about {label}: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let break_duration = match try_parse_duration_ms(&args.break_duration) {
+        Ok(millis) if millis > 0 => chrono::Duration::milliseconds(millis),
+        Ok(_) => {
+            eprintln!("Error: --break must be positive!");
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Error: Invalid --break: {err}");
+            process::exit(1);
+        }
+    };
+
+    let interval = match try_parse_duration_ms(&args.interval) {
+        Ok(millis) if millis > 0 => Duration::from_millis(millis as u64),
+        Ok(_) => {
+            eprintln!("Error: --interval must be positive!");
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Error: Invalid --interval: {err}");
+            process::exit(1);
+        }
+    };
+
+    if let Some(span) = &args.simulate {
+        let span = match try_parse_duration_ms(span) {
+            Ok(millis) if millis > 0 => chrono::Duration::milliseconds(millis),
+            Ok(_) => {
+                eprintln!("Error: --simulate must be positive!");
+                process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Error: Invalid --simulate: {err}");
+                process::exit(1);
+            }
+        };
+        print_simulation(&args, &hardware, span, break_duration, interval);
+        process::exit(0);
+    }
+
+    let output_idle_timeout =
+        args.output_idle_timeout
+            .as_ref()
+            .map(|timeout| match try_parse_duration_ms(timeout) {
+                Ok(millis) if millis > 0 => Duration::from_millis(millis as u64),
+                Ok(_) => {
+                    eprintln!("Error: --output-idle-timeout must be positive!");
+                    process::exit(1);
+                }
+                Err(err) => {
+                    eprintln!("Error: Invalid --output-idle-timeout: {err}");
+                    process::exit(1);
+                }
+            });
+
+    if args.detached && args.command.is_some() && args.log_output.is_none() {
+        // Keep the command's output for `attach`, since it has nowhere else to go.
+        // Not being able to isn't worth failing over.
+        if session::create_dir().is_ok() {
+            args.log_output = Some(Session::output_path(process::id() as i32));
+        }
+    }
+    let output_log = args.log_output.as_ref().map(|path| {
+        let max_size = args.log_max_size.as_ref().map(|size| {
+            output_log::parse_size(size).unwrap_or_else(|err| {
+                eprintln!("Error: Invalid --log-max-size: {err}");
+                process::exit(1);
+            })
+        });
+        output_log::OutputLog::open(path, max_size).unwrap_or_else(|err| {
+            eprintln!("Error: Couldn't open {}: {err}", path.display());
             process::exit(1);
+        })
+    });
+
+    let commands: Vec<String> = args
+        .command
+        .as_deref()
+        .map(command_chain::split)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(args.then.iter().cloned())
+        .collect();
+    let command_str =
+        (!commands.is_empty()).then(|| commands.join(&format!(" {} ", command_chain::SEPARATOR)));
+
+    if args.replace {
+        let replaced = Session::list().into_iter().filter(|session| {
+            (args.name.is_some() && session.name == args.name)
+                || (command_str.is_some() && session.command == command_str)
         });
+        for session in replaced {
+            println!("Replacing session {}.", session.pid);
+            if let Err(err) = ctl::stop_and_wait(session.pid) {
+                eprintln!("Error: Couldn't stop session {}: {}", session.pid, err);
+                process::exit(1);
+            }
+        }
     }
 
-    let mut assertions = Vec::new();
-    if args.display {
-        // Prevents the display from dimming automatically.
-        assertions.push(iokit.create_assertion("PreventUserIdleDisplaySleep", state));
+    let recent_starts = start_history::record();
+    if recent_starts > start_history::BURST {
+        if let (true, Some(duration)) = (args.debounce, timeout_duration) {
+            let types = sleep_types(&args);
+            // Only our own, since someone else's could end (or be stopped) whenever they like
+            let selector = Selector {
+                pid: None,
+                name: None,
+                mine: true,
+            };
+            let equivalent = Session::select(&selector).into_iter().find(|session| {
+                session.types == types
+                    && session.name == args.name
+                    && session.until.is_some()
+                    && session.command.is_none()
+                    && session.waitfor.is_none()
+            });
+            if let Some(existing) = equivalent {
+                let until = (chrono::Local::now() + duration).timestamp();
+                if let Err(err) = ctl::extend_session(&existing, until) {
+                    eprintln!("Error: Couldn't extend session {}: {}", existing.pid, err);
+                    process::exit(1);
+                }
+                println!(
+                    "Reusing session {} (now lasting at least until {}).",
+                    existing.pid,
+                    format_time(until)
+                );
+                process::exit(0);
+            }
+        }
+        // Only the first time it goes over, so a loop doesn't get a warning every time
+        if recent_starts == start_history::BURST + 1 && !args.debounce {
+            eprintln!(
+                "Warning: caffeinate2 was started {recent_starts} times in the last minute. If a script starts it in a loop, give it a --name and use --unique, keep one session running with --detach, or add --debounce."
+            );
+        }
+    }
+
+    if args.unique {
+        let selector = Selector {
+            pid: None,
+            name: args.name.clone(),
+            mine: false,
+        };
+        if let Some(existing) = Session::select(&selector).first() {
+            println!(
+                "Session \"{}\" is already running (PID {}).",
+                args.name.as_ref().unwrap(),
+                existing.pid
+            );
+            if let (true, Some(duration)) = (args.extend_if_running, timeout_duration) {
+                let until = (chrono::Local::now() + duration).timestamp();
+                if let Err(err) = ctl::extend_session(existing, until) {
+                    eprintln!("Error: Couldn't extend session {}: {}", existing.pid, err);
+                    process::exit(1);
+                }
+                println!("Extended it to last at least until {}.", format_time(until));
+            }
+            process::exit(0);
+        }
+    }
+
+    // How long a command takes isn't known, but its output needs room either way
+    let long_timeout = timeout_duration
+        .filter(|duration| args.command.is_none() && *duration >= preflight_check::MIN_DURATION);
+    if long_timeout.is_some() || args.log_output.is_some() {
+        let problems = preflight_check::problems(long_timeout, args.log_output.as_deref());
+        for problem in &problems {
+            if args.strict_preflight {
+                eprintln!("Error: {problem}");
+            } else {
+                eprintln!("Warning: {problem}");
+            }
+        }
+        if args.strict_preflight && !problems.is_empty() {
+            process::exit(1);
+        }
+    }
+
+    if args.detach {
+        // Assertions are released when the process that made them exits, so something has to
+        // stay behind to hold them (and run the command, if there is one). Start ourselves again
+        // in the background.
+        let (ours, command) = split_args();
+        let background_args: Vec<String> = ours
+            .into_iter()
+            .map(|arg| {
+                if arg == "--detach" {
+                    String::from("--detached")
+                } else {
+                    arg
+                }
+            })
+            .chain(command)
+            .collect();
+        match ctl::spawn_background(&background_args) {
+            Ok(pid) => {
+                println!("Preventing sleep in the background (PID {pid}).");
+                println!("Follow it with `caffeinate2 attach --pid {pid}`.");
+                process::exit(0);
+            }
+            Err(err) => {
+                eprintln!("Error: Couldn't start background session: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    journal::repair();
+    // These outlive us if we're killed, so they're recorded before they're made
+    let mut journal = (!args.dry_run
+        && (args.entirely || args.disable_lpm || args.no_standby || args.maintain_wake_on_lan))
+        .then(|| Journal::new(process::id() as i32, sleep_types(&args), args.entirely));
+    if let Some(journal) = &journal {
+        journal.write();
+    }
+
+    let low_power = if args.disable_lpm && !args.dry_run {
+        let settings = low_power::enabled().and_then(|mut settings| {
+            // Turned off by another session, which might end before us
+            for setting in journal::low_power_of_others(process::id() as i32) {
+                if !settings.contains(&setting) {
+                    settings.push(setting);
+                }
+            }
+            if let Some(journal) = &mut journal {
+                journal.low_power = settings.clone();
+                journal.write();
+            }
+            low_power::disable(&settings)?;
+            Ok(settings)
+        });
+        match settings {
+            Ok(settings) => {
+                if !settings.is_empty() {
+                    println!("Turned Low Power Mode off until the session ends.");
+                }
+                settings
+            }
+            Err(err) => {
+                eprintln!("Error: Couldn't turn Low Power Mode off: {err}");
+                process::exit(1);
+            }
+        }
+    } else {
+        if args.verbose > 0 && !args.respect_lpm && low_power::is_enabled() == Some(true) {
+            println!("Low Power Mode is on, so the Mac might still sleep sooner than expected.");
+        }
+        Vec::new()
+    };
+
+    let standby = if (args.no_standby || args.maintain_wake_on_lan) && !args.dry_run {
+        let seconds = timeout_duration.map_or(standby_override::DEFAULT_DELAY, |duration| {
+            duration.num_seconds().clamp(0, u32::MAX as i64) as u32
+        });
+        let settings = standby_override::settings().and_then(|current| {
+            let has = |keys: &[&str]| {
+                current
+                    .iter()
+                    .any(|setting| keys.contains(&setting.key.as_str()))
+            };
+            let raising = if args.no_standby {
+                if !has(&standby::DELAY_KEYS) {
+                    eprintln!("Warning: This Mac has no standby delay to raise.");
+                }
+                standby::too_short(&current, seconds)
+            } else {
+                Vec::new()
+            };
+            let mut enabling = Vec::new();
+            if args.maintain_wake_on_lan {
+                if !has(&[standby::WAKE_ON_LAN_KEY]) {
+                    eprintln!("Warning: This Mac doesn't support Wake for network access.");
+                } else if !standby::wake_on_lan_off(&current).is_empty() {
+                    if unistd::Uid::effective().is_root() {
+                        enabling = standby::wake_on_lan_off(&current);
+                    } else {
+                        eprintln!("Warning: Wake for network access is off, so the Mac can't be woken remotely. Run with sudo to turn it on for the session.");
+                    }
+                }
+            }
+            // Changed by another session, which might end before us. Its originals are what
+            // has to be put back, not what the settings are now.
+            let mut settings = journal::standby_of_others(process::id() as i32);
+            for setting in raising.iter().chain(&enabling) {
+                if !settings
+                    .iter()
+                    .any(|other| other.source == setting.source && other.key == setting.key)
+                {
+                    settings.push(setting.clone());
+                }
+            }
+            if let Some(journal) = &mut journal {
+                journal.standby = settings.clone();
+                journal.write();
+            }
+            standby_override::set(&raising, seconds)?;
+            if !raising.is_empty() {
+                println!(
+                    "Raised the standby delay to {} until the session ends.",
+                    format_duration(chrono::Duration::seconds(seconds as i64))
+                );
+            }
+            standby_override::set(&enabling, 1)?;
+            if !enabling.is_empty() {
+                println!("Turned Wake for network access on until the session ends.");
+            }
+            Ok(settings)
+        });
+        match settings {
+            Ok(settings) => settings,
+            Err(err) => {
+                eprintln!("Error: Couldn't change the standby settings: {err}");
+                process::exit(1);
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let assertions = build_assertions(&args, &hardware);
+    if let (true, Some(duration)) = (args.kernel_timeout, timeout_duration) {
+        assertions.set_kernel_deadline(Instant::now() + duration.to_std().unwrap());
     }
-    if args.disk {
-        // Prevents the disk from stopping when idle.
-        assertions.push(iokit.create_assertion("PreventDiskIdle", state));
-    }
-    if args.system {
-        // Prevents the system from sleeping automatically.
-        assertions.push(iokit.create_assertion("PreventUserIdleSystemSleep", state));
-    }
-    if args.system_on_ac {
-        // Prevents the system from sleeping when on AC power.
-        assertions.push(iokit.create_assertion("PreventSystemSleep", state));
+    if args.repeat.is_some() {
+        assertions.add_condition("not on a break", true);
     }
+    if let Some(volume) = &args.while_mounted {
+        let path = volume_watch::volume_path(volume);
+        let mounted = volume_watch::is_mounted(&path);
+        if !mounted {
+            println!("{} isn't mounted, waiting for it.", path.display());
+        }
+        assertions.add_condition("volume mounted", mounted);
 
-    if args.user_active {
-        // Declares the user is active.
-        assertions.push(iokit.declare_user_activity(true));
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        volume_watch::watch(path.clone(), move |mounted| {
+            if watched.set_condition("volume mounted", mounted) && verbose {
+                if mounted {
+                    println!("{} mounted, preventing sleep.", path.display());
+                } else {
+                    println!("{} unmounted, allowing sleep.", path.display());
+                }
+            }
+        });
     }
+    if let Some(path) = &args.trigger_file {
+        assertions.add_condition("trigger file", trigger_file::exists(path));
 
-    if args.verbose {
-        println!("Assertions: {:?}", assertions);
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        let path = path.clone();
+        trigger_file::watch(path.clone(), move |exists| {
+            if watched.set_condition("trigger file", exists) && verbose {
+                if exists {
+                    println!("{} created, preventing sleep.", path.display());
+                } else {
+                    println!("{} removed, allowing sleep.", path.display());
+                }
+            }
+        });
     }
+    if let Some(name) = &args.while_focus {
+        let active = focus::is_active(name).unwrap_or_else(|err| {
+            eprintln!("Error: Couldn't read Focus state: {err}");
+            eprintln!(
+                "Your terminal might need Full Disk Access (System Settings > Privacy & Security)."
+            );
+            process::exit(1);
+        });
+        if !active {
+            println!("Focus \"{name}\" isn't on, waiting for it.");
+        }
+        assertions.add_condition("focus", active);
 
-    assertions
-}
-
-fn release_assertions(iokit: &power_management::IOKit, assertions: &Vec<u32>) {
-    for assertion in assertions {
-        iokit.release_assertion(*assertion);
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        let name = name.clone();
+        focus::watch(name.clone(), move |active| {
+            if watched.set_condition("focus", active) && verbose {
+                if active {
+                    println!("Focus \"{name}\" on, preventing sleep.");
+                } else {
+                    println!("Focus \"{name}\" off, allowing sleep.");
+                }
+            }
+        });
     }
-    if power_management::IOKit::get_sleep_disabled(iokit) {
-        iokit.set_sleep_disabled(false).unwrap_or_else(|_| {
-            eprintln!("Error: Insufficient privileges to disable sleep. Try running with sudo.");
+    #[cfg(feature = "scripting")]
+    if let Some(path) = &args.policy_script {
+        let script = caffeinate2::policy_script::PolicyScript::load(path).unwrap_or_else(|err| {
+            eprintln!("Error: Couldn't load {}: {err}", path.display());
             process::exit(1);
         });
-    }
-}
-
-/// Clap args
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Verbose mode
-    #[arg(short, long)]
-    verbose: bool,
-
-    /// Dry run. Don't actually prevent sleep.
-    /// Useful for testing.
-    #[arg(long)]
-    dry_run: bool,
-
-    /// Drop root privileges in command.
-    /// You need root to disable sleep entirely,
-    /// but some programs don't want to run as root.
-    #[arg(long)]
-    drop_root: bool,
-
-    /// Disable display sleep
-    #[arg(short, long)]
-    display: bool,
-
-    /// Disable disk idle sleep
-    #[arg(short = 'm', long)]
-    disk: bool,
-
-    /// Disable idle system sleep. [DEFAULT]
-    #[arg(short = 'i', long)]
-    system: bool,
-
-    /// Disable system sleep while not on battery
-    #[arg(short, long)]
-    system_on_ac: bool,
-
-    /// Disable system sleep entirely (ignores lid closing)
-    #[arg(short, long)]
-    entirely: bool,
+        // Hold everything if it fails, like without a script
+        let decision = script_policy::evaluate(&script)
+            .unwrap_or(caffeinate2::policy_script::Decision::All(true));
+        if args.verbose > 0 {
+            println!("Policy script: {}.", script_policy::describe(&decision));
+        }
+        assertions.add_condition_by_kind("policy script", |kind| {
+            script_policy::wants(&decision, kind)
+        });
 
-    /// Declare the user is active.
-    /// If the display is off, this option turns it on and prevents it from going into idle sleep.
-    #[arg(short, long)]
-    user_active: bool,
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        script_policy::watch(script, interval, move |decision| {
+            let changed = watched.set_condition_by_kind("policy script", |kind| {
+                script_policy::wants(&decision, kind)
+            });
+            if changed && verbose {
+                println!("Policy script: {}.", script_policy::describe(&decision));
+            }
+        });
+    }
+    for name in &args.while_plugin {
+        let path = plugin::path(name).unwrap_or_else(|err| {
+            eprintln!("Error: Couldn't use plugin {name}: {err}");
+            process::exit(1);
+        });
+        // Conditions are named for the whole session, which lasts as long as the process
+        let condition: &'static str = Box::leak(format!("plugin {name}").into_boxed_str());
+        // Until it says otherwise
+        assertions.add_condition(condition, true);
 
-    /// Wait for X seconds.
-    /// Also supports time units (like "1 day 2 hours 3mins 4s").
-    #[arg(short, long, name = "DURATION")]
-    timeout: Option<String>,
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        let name = name.clone();
+        plugin::watch(name.clone(), path, interval, move |hold| {
+            if watched.set_condition(condition, hold) && verbose {
+                if hold {
+                    println!("Plugin {name} says hold, preventing sleep.");
+                } else {
+                    println!("Plugin {name} says release, allowing sleep.");
+                }
+            }
+        });
+    }
+    if args.pause_when_switched_out {
+        let owner = session::owner_uid();
+        // Nobody's switched in at the login window, so that doesn't count
+        let at_console = move |console: Option<u32>| console.is_none_or(|uid| uid == owner);
+        let current = console_user::current();
+        if let Some((name, _)) = current.as_ref().filter(|(_, uid)| *uid != owner) {
+            println!("{name} is using the Mac, allowing sleep until you switch back.");
+        }
+        assertions.add_condition(
+            "user at the console",
+            at_console(current.map(|(_, uid)| uid)),
+        );
 
-    /// Wait for program with PID X to complete and pass its exit code.
-    #[arg(short, long, name = "PID")]
-    waitfor: Option<i32>,
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        console_user::watch(move |console| {
+            let here = at_console(console);
+            if watched.set_condition("user at the console", here) && verbose {
+                if here {
+                    println!("Switched back, preventing sleep.");
+                } else {
+                    println!("Another user switched in, allowing sleep.");
+                }
+            }
+        });
+    }
+    if args.release_on_lock {
+        let locked = screen_lock::is_locked();
+        if locked {
+            println!("The screen is locked, allowing sleep until it's unlocked.");
+        }
+        assertions.add_condition("screen unlocked", !locked);
 
-    /// Wait for given command to complete (takes priority above timeout and pid)
-    #[arg()]
-    command: Option<Vec<String>>,
-}
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        screen_lock::watch(move |locked| {
+            if watched.set_condition("screen unlocked", !locked) && verbose {
+                if locked {
+                    println!("Screen locked, allowing sleep.");
+                } else {
+                    println!("Screen unlocked, preventing sleep.");
+                }
+            }
+        });
+    }
+    if hardware.battery {
+        // macOS puts the Mac to sleep when the battery is about to run out. Don't fight it.
+        let warning = power_source::battery_warning();
+        if warning == BatteryWarning::Final {
+            println!("The battery is almost empty, allowing sleep until it's charged.");
+        }
+        if let (Some(hook), BatteryWarning::Early | BatteryWarning::Final) =
+            (&args.on_low_battery, warning)
+        {
+            run_low_battery_hook(hook, warning);
+        }
+        assertions.add_condition("battery not critical", warning != BatteryWarning::Final);
 
-fn parse_duration(duration: String) -> i64 {
-    // Use regex to split the duration into a bunch of number and unit pairs
-    let mut total_seconds = 0;
-    let re = regex::Regex::new(r"(\d+)\s*(s|m|h|d)").unwrap();
+        let watched = assertions.clone();
+        let hook = args.on_low_battery.clone();
+        power_source::watch_battery_warning(warning, move |warning| {
+            let critical = warning == BatteryWarning::Final;
+            if watched.set_condition("battery not critical", !critical) {
+                if critical {
+                    println!("The battery is almost empty, allowing sleep.");
+                } else {
+                    println!("The battery isn't critically low anymore, preventing sleep again.");
+                }
+            }
+            if let (Some(hook), false) = (&hook, warning == BatteryWarning::None) {
+                run_low_battery_hook(hook, warning);
+            }
+        });
+    } else if args.on_low_battery.is_some() {
+        eprintln!("Warning: This Mac has no battery, so the --on-low-battery hook never runs.");
+    }
+    if args.respect_lpm {
+        let enabled = low_power::is_enabled().unwrap_or(false);
+        if enabled {
+            println!("Low Power Mode is on, allowing sleep until it's turned off.");
+        }
+        assertions.add_condition("low power mode off", !enabled);
 
-    for captures in re.captures_iter(&duration) {
-        let number = captures[1]
-            .parse::<i64>()
-            .unwrap_or_else(|_| panic!("invalid timeout"));
-        let unit = &captures[2];
+        let watched = assertions.clone();
+        let verbose = args.verbose > 0;
+        low_power::watch(enabled, move |enabled| {
+            if watched.set_condition("low power mode off", !enabled) && verbose {
+                if enabled {
+                    println!("Low Power Mode turned on, allowing sleep.");
+                } else {
+                    println!("Low Power Mode turned off, preventing sleep.");
+                }
+            }
+        });
+    }
+    if let Some(max) = args.max_wake_temp {
+        let smc = smc::Smc::open().filter(|smc| smc.cpu_temperature().is_some());
+        let Some(smc) = smc else {
+            eprintln!("Error: Couldn't read the CPU temperature on this Mac, which --max-wake-temp needs.");
+            process::exit(1);
+        };
+        let period = parse_duration(args.max_wake_temp_for.clone());
+        assertions.add_condition("not overheating", true);
 
-        match unit {
-            "s" => total_seconds += number,
-            "m" => total_seconds += number * 60,
-            "h" => total_seconds += number * 3600,
-            "d" => total_seconds += number * 86400,
-            _ => panic!("invalid duration"),
+        let watched = assertions.clone();
+        let cutoff = Mutex::new(thermal::Cutoff::new(max, period));
+        let period = format_duration(chrono::Duration::seconds(period));
+        smc.watch_cpu_temperature(move |celsius| {
+            let now = chrono::Local::now().timestamp();
+            let message = match cutoff.lock().unwrap().observe(celsius, now) {
+                Some(thermal::Change::TooHot) => {
+                    watched.set_condition("not overheating", false);
+                    format!("The CPU has been over {max}°C for {period} (now {celsius:.0}°C), allowing sleep.")
+                }
+                Some(thermal::Change::CooledDown) => {
+                    watched.set_condition("not overheating", true);
+                    format!("The CPU has cooled down to {celsius:.0}°C, preventing sleep again.")
+                }
+                None => return,
+            };
+            println!("{message}");
+            notify::send("caffeinate2", &message);
+        });
+    }
+    if args.on_output_idle == child_output::OnIdle::Release {
+        // Until the command goes quiet
+        assertions.add_condition("output active", true);
+    }
+    assertions.refresh();
+    if args.wake_display && !args.dry_run {
+        match power_management::IOKit::new().wake_display() {
+            Ok(()) => {
+                // The display takes a moment to come on
+                thread::spawn(|| {
+                    thread::sleep(Duration::from_secs(2));
+                    if power_management::display_is_asleep() {
+                        eprintln!(
+                            "Warning: The display is still asleep (the lid might be closed)."
+                        );
+                    }
+                });
+            }
+            Err(code) => eprintln!(
+                "Warning: Couldn't wake the display ({}).",
+                power_management::describe_error(code)
+            ),
         }
     }
-
-    // If no units were specified, assume seconds
-    if total_seconds == 0 {
-        total_seconds = duration.parse().unwrap_or_else(|_| {
-            eprintln!("Error: Timeout isn't a valid duration or number!");
-            process::exit(1)
+    if (args.user_active && !args.user_active_once) || args.no_dim {
+        assertions.keep_user_active(USER_ACTIVITY_INTERVAL);
+    }
+    if args.verbose > 0 {
+        println!("Assertions: {:?}", assertions.ids());
+    }
+    if let Some(journal) = &mut journal {
+        journal.assertion_ids = assertions.ids();
+        journal.write();
+    }
+    powerd_watch::watch(assertions.clone());
+    let watched = assertions.clone();
+    wake_watch::watch(move |slept| {
+        if watched.any_held() {
+            sleep_log::report_sleep(slept);
+        }
+    });
+    if args.verbose > 0 {
+        // For keeping an eye on a Mac that's working hard with its lid closed
+        thread::spawn(|| {
+            let smc = smc::Smc::open();
+            loop {
+                let readings = smc::readings(smc.as_ref()).describe();
+                if !readings.is_empty() {
+                    println!("Health: {readings}.");
+                }
+                thread::sleep(TELEMETRY_INTERVAL);
+            }
         });
     }
+    // Keep App Nap from stretching our timers while we hold the assertions
+    #[cfg(feature = "objc")]
+    let _activity = objc_bridge::Activity::begin("caffeinate2 is preventing sleep");
 
-    total_seconds
-}
-
-fn main() {
-    let mut args = Args::parse();
-    if !(args.display
-        || args.disk
-        || args.system
-        || args.system_on_ac
-        || args.entirely
-        || args.user_active)
-    {
-        // Default to system sleep if no other options are specified
-        args.system = true;
+    let pid = process::id() as i32;
+    let ends_at = timeout_duration
+        .filter(|_| args.command.is_none() || args.hold_full_timeout)
+        .map(|duration| chrono::Local::now() + duration);
+    let session = Session {
+        version: session::VERSION,
+        pid,
+        name: args.name.clone(),
+        started: chrono::Local::now().timestamp(),
+        until: ends_at.map(|ends_at| ends_at.timestamp()),
+        types: sleep_types(&args),
+        command: command_str,
+        waitfor: args.waitfor.filter(|_| args.command.is_none()),
+        managed_by: args.managed_by.clone(),
+        progress: None,
+        user: session::user_name(session::owner_uid()),
+        uid: Some(session::owner_uid()),
+        output: args
+            .log_output
+            .as_ref()
+            .and_then(|path| std::path::absolute(path).ok())
+            .map(|path| path.display().to_string()),
+        args: reusable_args(),
+        tags: args.tag.iter().cloned().collect(),
+    };
+    if let Err(err) = session.register() {
+        eprintln!(
+            "Warning: Couldn't record the session in {} ({err}). It still prevents sleep, but `status`, `ctl` and --unique won't see it.",
+            session::dir().display()
+        );
+    }
+    // So it's no surprise when sleep doesn't come back once this one ends
+    if let Some(overlap) = status::overlap(&session) {
+        println!("{overlap}");
     }
 
-    if !cfg!(target_os = "macos") {
-        panic!("This program only works on macOS.");
+    if let Some(old) = args.adopt {
+        // Our assertions are held, so there's no gap when the old ones are released
+        match ctl::stop_and_wait(old) {
+            Ok(()) => println!("Took over session {old}."),
+            Err(err) => eprintln!("Warning: Couldn't stop session {old}: {err}"),
+        }
     }
 
-    if args.verbose {
-        println!("DEBUG {:#?}", &args);
+    if let (true, Some(target)) = (args.progress, args.waitfor) {
+        progress::watch(target, pid, args.verbose > 0);
     }
 
-    let mut sleep_str = "Preventing sleep types: ".to_string();
+    let persisted_id = args
+        .persist
+        .then(|| args.persisted_id.clone().unwrap_or_else(|| pid.to_string()));
+    if let Some(id) = &persisted_id {
+        persist_session(id, ends_at.unwrap().timestamp_millis(), pid);
+    }
 
-    sleep_str += "[ ";
+    // `ctl extend` rewrites our session file and pokes us with SIGUSR1
+    let deadline = Arc::new(AtomicI64::new(
+        ends_at.map_or(i64::MAX, |ends_at| ends_at.timestamp_millis()),
+    ));
+    let deadline_clone = deadline.clone();
+    let extend_persisted_id = persisted_id.clone();
+    let extend_assertions = assertions.clone();
+    let kernel_timeout = args.kernel_timeout;
+    let mut extend_signals = Signals::new([SIGUSR1]).unwrap();
+    thread::spawn(move || {
+        for _ in extend_signals.forever() {
+            if let Some(until) = Session::load(pid).and_then(|session| session.until) {
+                if until * 1000 > deadline_clone.swap(until * 1000, Ordering::SeqCst) {
+                    println!("Extended, now resuming {}.", format_time(until));
+                    if kernel_timeout {
+                        let left = until * 1000 - chrono::Local::now().timestamp_millis();
+                        extend_assertions.set_kernel_deadline(
+                            Instant::now() + Duration::from_millis(left.max(0) as u64),
+                        );
+                    }
+                    if let Some(id) = &extend_persisted_id {
+                        persist_session(id, until * 1000, pid);
+                    }
+                }
+            }
+        }
+    });
 
-    // Print types of sleep prevented
-    if args.display {
-        sleep_str += "Display ";
-    }
-    if args.disk {
-        sleep_str += "Disk ";
-    }
-    if args.system {
-        sleep_str += "System ";
-    }
-    if args.system_on_ac {
-        sleep_str += "System (if on AC) ";
-    }
-    if args.entirely {
-        sleep_str += "Entirely ";
+    if let Some(interval) = heartbeat_interval {
+        let assertions = assertions.clone();
+        let deadline = deadline.clone();
+        let types = sleep_types(&args).join(", ");
+        let started = chrono::Local::now();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let holding = if assertions.any_held() {
+                tr!("still-holding", types = types.clone())
+            } else {
+                tr!("holding-nothing")
+            };
+            let now = chrono::Local::now();
+            let time = match deadline.load(Ordering::SeqCst) {
+                i64::MAX => tr!(
+                    "time-so-far",
+                    duration = format_short_duration(now - started)
+                ),
+                deadline => tr!(
+                    "time-remaining",
+                    duration = format_short_duration(chrono::Duration::milliseconds(
+                        deadline - now.timestamp_millis()
+                    ))
+                ),
+            };
+            println!("{}", tr!("heartbeat", holding = holding, time = time));
+        });
     }
-    if args.user_active {
-        sleep_str += "User active ";
+
+    let mut exit_code = 0;
+    let reason;
+
+    if let (Some(Some(volume)), false) = (&args.disk, args.dry_run) {
+        disk_keepalive::start(volume, args.verbose > 0);
     }
-    sleep_str += "] ";
 
-    let iokit = power_management::IOKit::new();
-    let assertions = set_assertions(&iokit, &args, true);
+    let output_assertions = assertions.clone();
+    let break_assertions = assertions.clone();
+    // For the summary, so only when it's printed
+    let blocked_sleeps =
+        (!args.no_summary && !args.dry_run && (args.system || args.system_on_ac || args.entirely))
+            .then(|| blocked_sleeps::watch(assertions.clone()));
+    let cleanup = Cleanup::new(SessionState {
+        assertions,
+        pid,
+        low_power,
+        standby,
+    });
 
-    let mut exit_code = 0;
+    // PIDs of the wrapped commands while they run, so signals can be passed on to them
+    let child_pids = Arc::new(Mutex::new(Vec::<i32>::new()));
+    // The signal that was passed on (0 if none), so no more commands are started
+    let interrupted = Arc::new(AtomicI32::new(0));
 
-    let mut signals = Signals::new([SIGINT]).unwrap();
-    let assertions_clone = assertions.clone();
+    let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap();
+    let signal_cleanup = cleanup.clone();
+    let signal_child_pids = child_pids.clone();
+    let signal_interrupted = interrupted.clone();
+    let signal_persisted_id = persisted_id.clone();
+    let exit_reason_format = args.exit_reason;
+    // Printed once sleep is allowed again, however the session ends
+    let summary_types = (!args.no_summary).then(|| sleep_types(&args));
+    let summary_uid = session.uid;
+    let print_summary = move |held_for: Option<Duration>| {
+        if let (Some(types), Some(held_for)) = (&summary_types, held_for) {
+            if !held_for.is_zero() {
+                let blocked = blocked_sleeps
+                    .as_ref()
+                    .map(|blocked| blocked.lock().unwrap().count());
+                eprintln!("{}", session_summary(types, held_for, blocked));
+            }
+            if let Some(still) = status::still_prevented(pid, summary_uid) {
+                eprintln!("{still}");
+            }
+        }
+    };
+    let signal_print_summary = print_summary.clone();
     thread::spawn(move || {
-        if signals.forever().next().is_some() {
-            release_assertions(&power_management::IOKit::new(), &assertions_clone);
-            process::exit(exit_code);
+        for signal in signals.forever() {
+            let children = signal_child_pids.lock().unwrap().clone();
+            if !children.is_empty() {
+                // Let the commands decide how to stop. Once they exit, the main thread
+                // cleans up and passes the exit code on.
+                signal_interrupted.store(signal, Ordering::SeqCst);
+                if reached_children(signal) {
+                    trace!("signal {signal} came from the terminal, not forwarding it");
+                    continue;
+                }
+                for child in children {
+                    trace!("forwarding signal {signal} to child {child}");
+                    let _ = nix::sys::signal::kill(
+                        unistd::Pid::from_raw(child),
+                        nix::sys::signal::Signal::try_from(signal).ok(),
+                    );
+                }
+                continue;
+            }
+            signal_print_summary(signal_cleanup.run());
+            // Ctrl+C means the user is done with it. SIGTERM could just be a shutdown, so a
+            // --persist session stays recorded (`ctl stop` forgets it itself).
+            if let (SIGINT, Some(id)) = (signal, &signal_persisted_id) {
+                persist::remove(id);
+            }
+            let code = exit_code::from_signal(signal);
+            if let Some(format) = exit_reason_format {
+                eprintln!("{}", ExitReason::Signal { signal }.report(format, code));
+            }
+            process::exit(code);
         }
     });
 
-    if args.command.is_some() {
+    if args.until_shutdown_blocked {
+        let power_off_cleanup = cleanup.clone();
+        let power_off_print_summary = print_summary.clone();
+        power_off::watch(move |event| {
+            let message = format!("{}, releasing everything.", event.describe());
+            println!("{message}");
+            // A --persist session stays recorded, so it comes back after a restart
+            power_off_print_summary(power_off_cleanup.run());
+            if let Some(format) = exit_reason_format {
+                eprintln!("{}", ExitReason::Condition { message }.report(format, 0));
+            }
+            process::exit(0);
+        });
+    }
+
+    if !commands.is_empty() {
         // If command is passed, it takes priority over everything else
-        let command = args.command.unwrap();
-        // Disable sleep while running the given command
-        sleep_str += "until command finishes.";
-        println!("{sleep_str}");
+        // Disable sleep while running the given commands
+        if commands.len() == 1 {
+            println!("{sleep_str} {}", tr!("until-command"));
+        } else {
+            println!(
+                "{sleep_str} {}",
+                tr!("until-commands", count = commands.len())
+            );
+        }
+        if args.timeout.is_some() && !args.hold_full_timeout && args.verbose > 0 {
+            println!("{}", tr!("ends-with-command"));
+        }
 
         let uid;
         let gid;
@@ -237,21 +2317,244 @@ fn main() {
             gid = unistd::getgid().into();
         }
 
-        if args.verbose {
+        if args.verbose > 0 {
             println!("uid: {uid}, gid: {gid}");
         }
 
-        let mut child = process::Command::new("/bin/sh")
-            .arg("-c")
-            .arg(command.join(" "))
-            .stdout(process::Stdio::inherit())
-            .stderr(process::Stdio::inherit())
-            .uid(uid)
-            .gid(gid)
-            .spawn()
-            .unwrap();
+        let policy = task_policy::Policy {
+            nice: args.nice,
+            qos: args.qos,
+            background: args.background_task,
+        };
+        // Raising priority is only allowed as root, and the command runs as `uid`
+        if args.nice.is_some_and(|nice| nice < 0) && uid != 0 {
+            eprintln!("Error: A --nice value below 0 needs root (and no --drop-root).");
+            process::exit(1);
+        }
+
+        let sandbox_profile = args.sandbox.as_deref().map(|spec| {
+            sandbox::profile(spec).unwrap_or_else(|err| {
+                eprintln!("Error: {err}");
+                process::exit(1);
+            })
+        });
+        // The home folder of whoever the command runs as, for the read-only-home preset
+        let home = unistd::User::from_uid(uid.into())
+            .ok()
+            .flatten()
+            .map_or_else(|| PathBuf::from("/var/empty"), |user| user.dir);
+
+        // Output only goes through us when we need to watch or change it
+        let piped =
+            output_idle_timeout.is_some() || output_log.is_some() || args.prefix_child.is_some();
+        let output = |quiet: bool| {
+            if piped {
+                process::Stdio::piped()
+            } else if quiet {
+                process::Stdio::null()
+            } else {
+                process::Stdio::inherit()
+            }
+        };
+        let output_log = output_log.map(|log| Arc::new(Mutex::new(log)));
+        // Whether each running command has gone quiet, for --on-output-idle release.
+        // Sleep is only allowed once all of them have.
+        let idle_commands = Arc::new(Mutex::new(HashMap::<i32, bool>::new()));
+        let set_idle = {
+            let idle_commands = idle_commands.clone();
+            let output_assertions = output_assertions.clone();
+            move |child: i32, idle: Option<bool>| {
+                let mut idle_commands = idle_commands.lock().unwrap();
+                match idle {
+                    Some(idle) => {
+                        // It might have exited in the meantime
+                        if let Some(was_idle) = idle_commands.get_mut(&child) {
+                            *was_idle = idle;
+                        } else {
+                            return;
+                        }
+                    }
+                    None => {
+                        idle_commands.remove(&child);
+                    }
+                }
+                let active = idle_commands.is_empty() || idle_commands.values().any(|idle| !idle);
+                output_assertions.set_condition("output active", active);
+            }
+        };
+        let run = |index: usize, command: &str| {
+            let mut child = match &sandbox_profile {
+                Some(profile) => {
+                    let mut child = process::Command::new(SANDBOX_EXEC);
+                    child
+                        .arg("-p")
+                        .arg(profile)
+                        .arg("-D")
+                        .arg(format!("HOME={}", home.display()))
+                        .arg("/bin/sh");
+                    child
+                }
+                None => process::Command::new("/bin/sh"),
+            };
+            child
+                .arg("-c")
+                .arg(command)
+                .stdout(output(args.quiet_child))
+                .stderr(output(false))
+                .uid(uid)
+                .gid(gid);
+            if !policy.is_default() {
+                unsafe {
+                    child.pre_exec(move || policy.apply());
+                }
+            }
+            let mut child = child.spawn().unwrap();
+            let started = std::time::Instant::now();
+            let child_id = child.id() as i32;
+            child_pids.lock().unwrap().push(child_id);
+
+            let options = child_output::Options {
+                quiet: args.quiet_child,
+                prefix: args
+                    .prefix_child
+                    .as_ref()
+                    .map(|prefix| prefix.replace("{n}", &(index + 1).to_string())),
+            };
+            let forwarder = piped
+                .then(|| child_output::Forwarder::start(&mut child, output_log.clone(), &options));
+            if let (Some(forwarder), Some(timeout)) = (&forwarder, output_idle_timeout) {
+                let on_idle = args.on_output_idle;
+                let timeout_str = format_duration(chrono::Duration::from_std(timeout).unwrap());
+                let set_idle = set_idle.clone();
+                if on_idle == child_output::OnIdle::Release {
+                    // A new command starts out active, whatever the others are doing
+                    idle_commands.lock().unwrap().insert(child_id, false);
+                    set_idle(child_id, Some(false));
+                }
+                forwarder.watch_idle(timeout, move |idle| match (on_idle, idle) {
+                    (child_output::OnIdle::Warn, true) => {
+                        eprintln!("Warning: No output from the command for {timeout_str}, it might be hung.")
+                    }
+                    (child_output::OnIdle::Release, idle) => {
+                        if idle {
+                            eprintln!("No output from the command for {timeout_str}, allowing sleep until it prints something.");
+                        } else {
+                            eprintln!("The command is printing again, preventing sleep.");
+                        }
+                        set_idle(child_id, Some(idle));
+                    }
+                    (child_output::OnIdle::Kill, true) => {
+                        eprintln!("No output from the command for {timeout_str}, stopping it.");
+                        let _ = nix::sys::signal::kill(
+                            unistd::Pid::from_raw(child_id),
+                            nix::sys::signal::Signal::SIGTERM,
+                        );
+                    }
+                    (_, false) => {}
+                });
+            }
+
+            let (code, usage) = match proc_info::wait(child_id) {
+                Ok((status, usage)) => (exit_code::from_wait_status(status), Some(usage)),
+                Err(err) => {
+                    trace!("couldn't get the command's resource usage: {err}");
+                    (exit_code::from_status(child.wait().unwrap()), None)
+                }
+            };
+            child_pids.lock().unwrap().retain(|pid| *pid != child_id);
+            if let Some(forwarder) = forwarder {
+                forwarder.join();
+            }
+            if args.on_output_idle == child_output::OnIdle::Release {
+                set_idle(child_id, None);
+            }
+            if let (false, Some(usage)) = (args.no_summary, usage) {
+                eprintln!("{}", usage_summary(command, started.elapsed(), &usage));
+            }
+            code
+        };
 
-        exit_code = child.wait().unwrap().code().unwrap_or(0);
+        if args.parallel && commands.len() > 1 {
+            let codes: Vec<i32> = thread::scope(|scope| {
+                let running: Vec<_> = commands
+                    .iter()
+                    .enumerate()
+                    .map(|(index, command)| scope.spawn(move || run(index, command)))
+                    .collect();
+                running
+                    .into_iter()
+                    .map(|command| command.join().unwrap())
+                    .collect()
+            });
+            for (command, code) in commands.iter().zip(codes) {
+                if code != 0 {
+                    // Their output is mixed together, so say which ones failed
+                    eprintln!("`{command}` failed with exit code {code}.");
+                    if exit_code == 0 {
+                        exit_code = code;
+                    }
+                }
+            }
+        } else {
+            for (index, command) in commands.iter().enumerate() {
+                if commands.len() > 1 && args.verbose > 0 {
+                    println!(
+                        "Running command {} of {}: {command}",
+                        index + 1,
+                        commands.len()
+                    );
+                }
+                let code = run(index, command);
+                if code != 0 && exit_code == 0 {
+                    // The first failure is what's passed on
+                    exit_code = code;
+                }
+                if interrupted.load(Ordering::SeqCst) != 0 {
+                    break;
+                }
+                if code != 0 && args.stop_on_error && index + 1 < commands.len() {
+                    eprintln!("`{command}` failed with exit code {code}, not running the rest.");
+                    break;
+                }
+            }
+        }
+        let held_until = deadline.load(Ordering::SeqCst);
+        if let (true, 0) = (
+            args.hold_full_timeout && held_until > chrono::Local::now().timestamp_millis(),
+            interrupted.load(Ordering::SeqCst),
+        ) {
+            println!(
+                "{}",
+                tr!("hold-full-timeout", time = format_time(held_until / 1000))
+            );
+            timer::sleep_until(|| deadline.load(Ordering::SeqCst));
+        }
+        reason = match interrupted.load(Ordering::SeqCst) {
+            0 => ExitReason::CommandExit { code: exit_code },
+            signal => ExitReason::Signal { signal },
+        };
+    } else if let Some(label) = &args.waitfor_job {
+        println!("{sleep_str} {}", tr!("until-job", label = label.clone()));
+        exit_code = launchd_job::wait_while_loaded(label, args.verbose > 0);
+        println!("{label} was unloaded (last exit status {exit_code}).");
+        reason = ExitReason::JobUnloaded {
+            label: label.clone(),
+            code: exit_code,
+        };
+    } else if args.until_logout {
+        println!("{sleep_str} {}", tr!("until-logout"));
+        console_user::wait_for_logout(session::owner_uid());
+        println!("{}", tr!("logged-out"));
+        reason = ExitReason::Condition {
+            message: tr!("logged-out"),
+        };
+    } else if let Some(condition) = until_condition(&args) {
+        println!("{sleep_str} {}", condition.banner());
+        condition.wait(interval);
+        println!("{}", condition.met_message());
+        reason = ExitReason::Condition {
+            message: condition.met_message(),
+        };
     } else if args.timeout.is_some() || args.waitfor.is_some() {
         // If timeout or waitfor is used, wait appropriately
 
@@ -263,67 +2566,61 @@ fn main() {
         if timeout {
             // Timeout selected
             // Print how long we're waiting for
-            duration =
-                chrono::Duration::try_seconds(parse_duration(args.timeout.unwrap())).unwrap();
+            duration = timeout_duration.unwrap();
             end_time += duration;
-            let seconds = duration.num_seconds() % 60;
-            let minutes = duration.num_minutes() % 60;
-            let hours = duration.num_hours() % 24;
-            let days = duration.num_days();
-
-            sleep_str += &format!(
-                "for {}{}{}{}",
-                if days > 0 {
-                    format!("{} day{} ", days, if days != 1 { "s" } else { "" })
-                } else {
-                    String::from("")
-                },
-                if hours > 0 {
-                    format!("{} hour{} ", hours, if hours != 1 { "s" } else { "" })
-                } else {
-                    String::from("")
-                },
-                if minutes > 0 {
-                    format!("{} minute{} ", minutes, if minutes != 1 { "s" } else { "" })
-                } else {
-                    String::from("")
-                },
-                if seconds % 60 > 0 || seconds == 0 {
-                    format!(
-                        "{} second{}",
-                        seconds,
-                        if seconds % 60 != 1 { "s" } else { "" }
-                    )
-                } else {
-                    String::new()
-                }
+            print!(
+                "{sleep_str} {}",
+                tr!("for-duration", duration = format_duration(duration))
             );
+        } else {
+            print!("{sleep_str}");
         }
 
-        print!("{sleep_str}");
-
         if timeout && waitfor {
-            print!(" or ");
+            print!(" {}", tr!("or"));
         }
         if waitfor {
-            print!("until PID {} finishes", args.waitfor.unwrap());
+            print!(" {}", tr!("until-pid", pid = args.waitfor.unwrap()));
         }
         println!(".");
 
-        const SHORT_FMT: &str = "at %-I:%M:%S %p";
-        const LONG_FMT: &str = "on %B %-d at %-I:%M:%S %p";
-
         if timeout {
             // Print when we're resuming
-            println!(
-                "Resuming {}.",
-                if duration.num_seconds() > (60 * 60 * 24) {
-                    end_time.format(LONG_FMT)
-                } else {
-                    end_time.format(SHORT_FMT)
+            let time = format_datetime(&end_time, duration.num_seconds() > (60 * 60 * 24));
+            println!("{}", tr!("resuming", time = time));
+            timer::sleep_until(|| deadline.load(Ordering::SeqCst));
+
+            let rounds = args.repeat.unwrap_or(1);
+            for round in 2..=rounds {
+                let message = tr!(
+                    "round-done",
+                    round = round - 1,
+                    rounds = rounds,
+                    duration = format_duration(break_duration)
+                );
+                println!("{message}");
+                notify::send("caffeinate2", &message);
+                // Letting the display sleep is the cue to step away
+                break_assertions.set_condition("not on a break", false);
+                timer::sleep_for(break_duration.to_std().unwrap());
+
+                break_assertions.set_condition("not on a break", true);
+                let ends_at = chrono::Local::now() + duration;
+                deadline.store(ends_at.timestamp_millis(), Ordering::SeqCst);
+                if let Some(mut session) = Session::load(pid) {
+                    session.until = Some(ends_at.timestamp());
+                    let _ = session.register();
                 }
-            );
-            thread::sleep(duration.to_std().unwrap());
+                let message = tr!(
+                    "break-over",
+                    round = round,
+                    rounds = rounds,
+                    time = time_format::current().time(&ends_at)
+                );
+                println!("{message}");
+                notify::send("caffeinate2", &message);
+                timer::sleep_until(|| deadline.load(Ordering::SeqCst));
+            }
         }
 
         if waitfor {
@@ -345,61 +2642,90 @@ fn main() {
 
             let mut eventlist = [kev];
 
-            kq.kevent(&[kev], &mut eventlist, None).unwrap();
-            if args.verbose {
+            trace!("kevent registered: {:?}", kev);
+            let fired = kq.kevent(&[kev], &mut eventlist, None).unwrap();
+            trace!("kevent returned {} event(s): {:?}", fired, eventlist[0]);
+            if args.verbose > 0 {
                 println!("{:#?}", kev)
             };
 
             if eventlist[0].flags().contains(event::EventFlag::EV_ERROR) {
-                if eventlist[0].data() == nix::Error::ESRCH as isize {
+                let kind = if eventlist[0].data() == nix::Error::ESRCH as isize {
                     println!("PID {} not found", pid);
+                    "pid_not_found"
                 } else {
                     eprintln!(
                         "kevent error waiting for PID {}: {}",
                         pid,
                         nix::Error::from_raw(eventlist[0].data() as i32)
                     );
+                    "kevent"
+                };
+                if let Some(format) = args.exit_reason {
+                    let reason = ExitReason::Error {
+                        kind: String::from(kind),
+                    };
+                    eprintln!("{}", reason.report(format, 1));
                 }
                 process::exit(1);
             }
 
-            exit_code = eventlist[0].data() as i32;
+            // The event data is the raw wait status, not the exit code itself
+            exit_code = exit_code::from_wait_status(eventlist[0].data() as i32);
 
-            print!("PID {pid} finished ");
             let now = chrono::Local::now();
-            print!("{} ", now.format(SHORT_FMT));
-            println!("with exit code {}", exit_code);
+            println!(
+                "{}",
+                tr!(
+                    "pid-finished",
+                    pid = pid,
+                    time = format_datetime(&now, false),
+                    code = exit_code
+                )
+            );
+            reason = ExitReason::PidExit {
+                pid,
+                code: exit_code,
+            };
+        } else {
+            reason = ExitReason::Timeout;
         }
 
         // Wait for either the timeout or the process to finish
     } else {
         // If no timer arguments are provided, disable sleep until Ctrl+C is pressed
-        sleep_str += "until Ctrl+C pressed.";
-        println!("{}", sleep_str);
-        thread::park();
-    }
-    release_assertions(&iokit, &assertions);
-    process::exit(exit_code);
-}
+        println!("{sleep_str} {}", tr!("until-ctrl-c"));
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn test_parse_duration() {
-        let duration = "1d2h3m4s".to_string();
-        let result = super::parse_duration(duration);
-        assert_eq!(result, 93784);
-
-        let duration = "1day 2hrs3m".to_string();
-        let result = super::parse_duration(duration);
-        assert_eq!(result, 93780);
-
-        let duration = "3 minutes 17 hours 2 seconds".to_string();
-        let result = super::parse_duration(duration);
-        assert_eq!(result, 61382);
-
-        let duration = "45323".to_string();
-        let result = super::parse_duration(duration);
-        assert_eq!(result, 45323);
+        if let Some(interval) = remind_interval {
+            let started = chrono::Local::now();
+            loop {
+                timer::sleep_for(interval.to_std().unwrap());
+                let reminder = tr!(
+                    "still-preventing",
+                    duration = format_short_duration(chrono::Local::now() - started)
+                );
+                println!("{reminder}");
+                if args.notify {
+                    notify::send("caffeinate2", &reminder);
+                }
+            }
+        }
+        loop {
+            thread::park();
+        }
+    }
+    print_summary(cleanup.run());
+    if let Some(id) = &persisted_id {
+        persist::remove(id);
     }
+    // Only when it ended by itself, not when it was stopped through the commands
+    if interrupted.load(Ordering::SeqCst) == 0 {
+        for alarm in &args.alarm {
+            alarm.ring();
+        }
+    }
+    if let Some(format) = args.exit_reason {
+        eprintln!("{}", reason.report(format, exit_code));
+    }
+    process::exit(exit_code);
 }
@@ -0,0 +1,86 @@
+use crate::ctl;
+use crate::proc_info;
+use caffeinate2::caffeinate_args;
+use nix::unistd::Uid;
+use std::process;
+
+/// Where Apple's caffeinate lives
+const CAFFEINATE_PATH: &str = "/usr/bin/caffeinate";
+
+/// Replace running Apple caffeinate processes with caffeinate2 sessions that do the same,
+/// for as long as they had left. With `kill`, the originals are stopped afterwards.
+pub fn migrate(kill: bool, dry_run: bool) {
+    let uid = Uid::effective();
+    let originals: Vec<(i32, Vec<String>, proc_info::BsdInfo)> = proc_info::all_pids()
+        .into_iter()
+        .filter_map(|pid| {
+            let info = proc_info::bsd_info(pid)?;
+            // Other users' arguments can't be read without root anyway
+            if !uid.is_root() && info.uid != uid.as_raw() {
+                return None;
+            }
+            let (path, args) = proc_info::args(pid)?;
+            (path == CAFFEINATE_PATH).then_some((pid, args, info))
+        })
+        .collect();
+    if originals.is_empty() {
+        println!("No caffeinate processes running.");
+        return;
+    }
+
+    let now = chrono::Local::now().timestamp() as u64;
+    let mut failed = false;
+    for (pid, args, info) in originals {
+        let original = args.join(" ");
+        let intent = match caffeinate_args::parse(&args[1..]) {
+            Ok(intent) => intent,
+            Err(err) => {
+                eprintln!("Warning: Skipping `{original}` (PID {pid}): {err}.");
+                continue;
+            }
+        };
+        // caffeinate runs the utility as its child
+        let command_pid = intent
+            .command
+            .as_ref()
+            .and_then(|_| proc_info::children(pid).first().copied());
+        let elapsed = now.saturating_sub(info.start_tvsec);
+        let Some(mut new_args) = intent.caffeinate2_args(elapsed, command_pid) else {
+            println!("Skipping `{original}` (PID {pid}), which is about to finish.");
+            continue;
+        };
+        new_args.extend([String::from("--name"), format!("caffeinate-{pid}")]);
+
+        if dry_run {
+            println!(
+                "Would replace `{original}` (PID {pid}) with `caffeinate2 {}`.",
+                new_args.join(" ")
+            );
+            continue;
+        }
+        match ctl::spawn_background(&new_args) {
+            Ok(new_pid) => println!(
+                "Replaced `{original}` (PID {pid}) with `caffeinate2 {}` (PID {new_pid}).",
+                new_args.join(" ")
+            ),
+            Err(err) => {
+                eprintln!("Error: Couldn't start a session for `{original}` (PID {pid}): {err}");
+                failed = true;
+                continue;
+            }
+        }
+
+        if kill {
+            if intent.command.is_some() {
+                // Whatever started it would take that as the command finishing
+                println!("Left PID {pid} running, since something may be waiting for its command to finish.");
+            } else if let Err(err) = ctl::send_stop(pid) {
+                eprintln!("Error: Couldn't stop caffeinate (PID {pid}): {err}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
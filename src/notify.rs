@@ -0,0 +1,27 @@
+use std::process;
+
+/// Show a macOS notification, through UserNotifications when we run from an app bundle and
+/// through osascript otherwise (the framework refuses processes without one). Failures are
+/// ignored, since notifications are only ever a nice-to-have on top of terminal output.
+pub fn send(title: &str, message: &str) {
+    #[cfg(feature = "objc")]
+    if crate::objc_bridge::post_user_notification(title, message) {
+        return;
+    }
+    let script = format!(
+        "display notification {} with title {}",
+        quote(message),
+        quote(title)
+    );
+    let _ = process::Command::new("/usr/bin/osascript")
+        .arg("-e")
+        .arg(script)
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .status();
+}
+
+/// Quote a string as an AppleScript string literal
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
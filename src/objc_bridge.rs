@@ -0,0 +1,219 @@
+//! Objective-C APIs caffeinate2 uses: the NSProcessInfo activity, NSWorkspace's app launch and
+//! quit notifications, and UserNotifications. There are no bindings for AppKit or
+//! UserNotifications, so those are messaged by name behind typed wrappers. Their frameworks are
+//! only loaded when they're first used, to keep them off the startup path.
+
+use block2::RcBlock;
+use libloading::Library;
+use objc2::msg_send;
+use objc2::rc::Retained;
+use objc2::runtime::{AnyClass, AnyObject, Bool, NSObjectProtocol, ProtocolObject};
+use objc2_foundation::{
+    NSActivityOptions, NSBundle, NSError, NSNotification, NSNotificationCenter, NSOperationQueue,
+    NSProcessInfo, NSString,
+};
+use std::ffi::CStr;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+/// An NSProcessInfo activity, which stops App Nap from throttling us
+/// (and delaying timers) for as long as it's held. Ends when dropped.
+pub struct Activity {
+    token: Retained<ProtocolObject<dyn NSObjectProtocol>>,
+}
+
+impl Activity {
+    pub fn begin(reason: &str) -> Activity {
+        // Sleep is handled by our own assertions, so don't let the activity create another one
+        let token = NSProcessInfo::processInfo().beginActivityWithOptions_reason(
+            NSActivityOptions::UserInitiatedAllowingIdleSystemSleep,
+            &NSString::from_str(reason),
+        );
+        trace!("NSProcessInfo beginActivityWithOptions({:?})", reason);
+        Activity { token }
+    }
+}
+
+impl Drop for Activity {
+    fn drop(&mut self) {
+        unsafe { NSProcessInfo::processInfo().endActivity(&self.token) };
+        trace!("NSProcessInfo endActivity");
+    }
+}
+
+/// Look up a class from a framework, loading the framework first. The framework stays loaded.
+fn framework_class(framework: &str, class: &CStr) -> Option<&'static AnyClass> {
+    let path = format!("/System/Library/Frameworks/{framework}.framework/{framework}");
+    match unsafe { Library::new(&path) } {
+        // Its classes have to outlive this
+        Ok(library) => std::mem::forget(library),
+        Err(err) => {
+            trace!("dlopen {framework}: {err}");
+            return None;
+        }
+    }
+    let found = AnyClass::get(class);
+    trace!("{class:?} in {framework}: {}", found.is_some());
+    found
+}
+
+/// Whether an app launched or quit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppChange {
+    Launched,
+    Terminated,
+}
+
+/// An app launching or quitting, from NSWorkspace
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct AppEvent {
+    pub change: AppChange,
+    pub pid: i32,
+    pub bundle_id: Option<String>,
+}
+
+impl AppEvent {
+    fn from_notification(change: AppChange, notification: &NSNotification) -> Option<AppEvent> {
+        let info = notification.userInfo()?;
+        // The NSRunningApplication under NSWorkspaceApplicationKey
+        let key = NSString::from_str("NSWorkspaceApplicationKey");
+        let app: Option<Retained<AnyObject>> = unsafe { msg_send![&*info, objectForKey: &*key] };
+        let app = app?;
+        let pid: i32 = unsafe { msg_send![&*app, processIdentifier] };
+        let bundle_id: Option<Retained<NSString>> = unsafe { msg_send![&*app, bundleIdentifier] };
+        Some(AppEvent {
+            change,
+            pid,
+            bundle_id: bundle_id.map(|id| id.to_string()),
+        })
+    }
+}
+
+/// Observes NSWorkspace's app launch and quit notifications until it's dropped.
+/// Like all NSWorkspace notifications, they're only posted while the main thread runs its
+/// run loop, so it's for modes that leave the main thread to AppKit (none do yet).
+#[allow(dead_code)]
+pub struct AppWatcher {
+    center: Retained<NSNotificationCenter>,
+    observers: Vec<Retained<ProtocolObject<dyn NSObjectProtocol>>>,
+}
+
+#[allow(dead_code)]
+impl AppWatcher {
+    /// Call `callback` for every app that launches or quits, on a queue of its own.
+    /// None if AppKit couldn't be loaded.
+    pub fn start(callback: impl Fn(AppEvent) + Send + Sync + 'static) -> Option<AppWatcher> {
+        let workspace_class = framework_class("AppKit", c"NSWorkspace")?;
+        let workspace: Retained<AnyObject> = unsafe { msg_send![workspace_class, sharedWorkspace] };
+        let center: Retained<NSNotificationCenter> =
+            unsafe { msg_send![&*workspace, notificationCenter] };
+        let queue = NSOperationQueue::new();
+        let callback = Arc::new(callback);
+
+        let observers = [
+            (
+                AppChange::Launched,
+                "NSWorkspaceDidLaunchApplicationNotification",
+            ),
+            (
+                AppChange::Terminated,
+                "NSWorkspaceDidTerminateApplicationNotification",
+            ),
+        ]
+        .into_iter()
+        .map(|(change, name)| {
+            let callback = Arc::clone(&callback);
+            let block = RcBlock::new(move |notification: NonNull<NSNotification>| {
+                let notification = unsafe { notification.as_ref() };
+                if let Some(event) = AppEvent::from_notification(change, notification) {
+                    trace!("NSWorkspace: {event:?}");
+                    callback(event);
+                }
+            });
+            unsafe {
+                center.addObserverForName_object_queue_usingBlock(
+                    Some(&NSString::from_str(name)),
+                    None,
+                    Some(&queue),
+                    &block,
+                )
+            }
+        })
+        .collect();
+        Some(AppWatcher { center, observers })
+    }
+}
+
+impl Drop for AppWatcher {
+    fn drop(&mut self) {
+        for observer in &self.observers {
+            let observer: &AnyObject = observer.as_ref();
+            unsafe { self.center.removeObserver(observer) };
+        }
+    }
+}
+
+/// UNAuthorizationOptionAlert from UserNotifications/UNUserNotificationCenter.h
+const UN_AUTHORIZATION_OPTION_ALERT: usize = 1 << 2;
+
+/// Whether we run from an app bundle. UserNotifications refuses (by throwing) processes that
+/// don't.
+pub fn in_app_bundle() -> bool {
+    NSBundle::mainBundle().bundleIdentifier().is_some()
+}
+
+/// Post a notification through UserNotifications, asking for permission the first time.
+/// Returns false if it couldn't be posted, like outside an app bundle. Whether the user allowed
+/// it is only known later, so that's not reported.
+pub fn post_user_notification(title: &str, body: &str) -> bool {
+    if !in_app_bundle() {
+        return false;
+    }
+    let Some(center_class) = framework_class("UserNotifications", c"UNUserNotificationCenter")
+    else {
+        return false;
+    };
+    let (Some(content_class), Some(request_class)) = (
+        AnyClass::get(c"UNMutableNotificationContent"),
+        AnyClass::get(c"UNNotificationRequest"),
+    ) else {
+        return false;
+    };
+
+    unsafe {
+        let center: Retained<AnyObject> = msg_send![center_class, currentNotificationCenter];
+        let authorized = RcBlock::new(|granted: Bool, _error: *mut NSError| {
+            trace!("UserNotifications authorization: {}", granted.as_bool());
+        });
+        let () = msg_send![
+            &*center,
+            requestAuthorizationWithOptions: UN_AUTHORIZATION_OPTION_ALERT,
+            completionHandler: &*authorized
+        ];
+
+        let content: Retained<AnyObject> = msg_send![content_class, new];
+        let () = msg_send![&*content, setTitle: &*NSString::from_str(title)];
+        let () = msg_send![&*content, setBody: &*NSString::from_str(body)];
+        // Requests with the same identifier replace each other
+        let identifier = NSString::from_str(&format!(
+            "caffeinate2-{}",
+            chrono::Local::now()
+                .timestamp_nanos_opt()
+                .unwrap_or_default()
+        ));
+        let request: Retained<AnyObject> = msg_send![
+            request_class,
+            requestWithIdentifier: &*identifier,
+            content: &*content,
+            trigger: None::<&AnyObject>
+        ];
+        let () = msg_send![
+            &*center,
+            addNotificationRequest: &*request,
+            withCompletionHandler: None::<&block2::DynBlock<dyn Fn(*mut NSError)>>
+        ];
+    }
+    trace!("UserNotifications: posted {title:?}");
+    true
+}
@@ -0,0 +1,165 @@
+use caffeinate2::duration_parser::format_short_duration;
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::date::CFDate;
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_foundation::ConcreteCFType;
+
+/// Seconds between the Unix epoch and CFAbsoluteTime's, 2001-01-01
+const CF_ABSOLUTE_TIME_EPOCH: f64 = 978_307_200.0;
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOPMCopyAssertionsByProcess(assertions_by_pid: *mut CFDictionaryRef) -> i32;
+}
+
+/// An assertion some other process holds
+#[derive(Debug)]
+pub struct Assertion {
+    pub pid: i32,
+    /// What `-v` prints for our own assertions, and `release` accepts
+    pub id: Option<u32>,
+    pub process: Option<String>,
+    pub assertion_type: String,
+    pub name: Option<String>,
+    /// Seconds until powerd releases it by itself, or None if it has no timeout
+    pub expires_in: Option<i64>,
+}
+
+impl Assertion {
+    pub fn describe(&self) -> String {
+        let mut description = match &self.process {
+            Some(process) => format!("{process} (PID {})", self.pid),
+            None => format!("PID {}", self.pid),
+        };
+        description += &format!(": {}", self.assertion_type);
+        if let Some(name) = &self.name {
+            description += &format!(" \"{name}\"");
+        }
+        description += &match self.expires_in {
+            Some(seconds) => format!(
+                ", expires in {}",
+                format_short_duration(chrono::Duration::seconds(seconds))
+            ),
+            None => String::from(", no timeout"),
+        };
+        description
+    }
+}
+
+fn field<T: ConcreteCFType>(dictionary: &CFDictionary, key: &'static str) -> Option<T> {
+    let key = CFString::from_static_string(key);
+    let value = dictionary.find(key.as_CFTypeRef())?;
+    unsafe { CFType::wrap_under_get_rule(*value) }.downcast::<T>()
+}
+
+fn unix_time(date: &CFDate) -> f64 {
+    date.abs_time() + CF_ABSOLUTE_TIME_EPOCH
+}
+
+/// Seconds left before an assertion times out. powerd keeps how long was left when it last
+/// looked (which changes when the timer is paused, like during sleep), or else there's just
+/// the timeout it was created with.
+fn expires_in(time_left: Option<(f64, f64)>, timeout: Option<(f64, f64)>, now: f64) -> Option<i64> {
+    let expires_at = match (time_left, timeout) {
+        (Some((left, updated)), _) => updated + left,
+        // A timeout of 0 means none
+        (None, Some((seconds, started))) if seconds > 0.0 => started + seconds,
+        _ => return None,
+    };
+    Some((expires_at - now).max(0.0).round() as i64)
+}
+
+/// Active assertions held by processes other than `excluded` (like our own sessions)
+pub fn list(excluded: &[i32]) -> Vec<Assertion> {
+    let mut by_pid = std::ptr::null();
+    let result = unsafe { IOPMCopyAssertionsByProcess(&mut by_pid) };
+    trace!("IOPMCopyAssertionsByProcess() = {result:#X}");
+    if result != 0 || by_pid.is_null() {
+        return Vec::new();
+    }
+    let by_pid: CFDictionary = unsafe { CFDictionary::wrap_under_create_rule(by_pid) };
+    let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+
+    let (pids, lists) = by_pid.get_keys_and_values();
+    let mut assertions = Vec::new();
+    for (pid, list) in pids.into_iter().zip(lists) {
+        let Some(pid) = unsafe { CFType::wrap_under_get_rule(pid) }
+            .downcast::<CFNumber>()
+            .and_then(|pid| pid.to_i32())
+        else {
+            continue;
+        };
+        if excluded.contains(&pid) {
+            continue;
+        }
+        let Some(list) = unsafe { CFType::wrap_under_get_rule(list) }.downcast::<CFArray>() else {
+            continue;
+        };
+        for assertion in list.iter() {
+            let Some(assertion) =
+                unsafe { CFType::wrap_under_get_rule(*assertion) }.downcast::<CFDictionary>()
+            else {
+                continue;
+            };
+            // Released ones (level 0) can still be listed
+            let level = field::<CFNumber>(&assertion, "AssertLevel").and_then(|n| n.to_i64());
+            if level == Some(0) {
+                continue;
+            }
+            let Some(assertion_type) = field::<CFString>(&assertion, "AssertType") else {
+                continue;
+            };
+            let time_left = field::<CFNumber>(&assertion, "AssertTimeoutTimeLeft")
+                .and_then(|n| n.to_f64())
+                .zip(field::<CFDate>(&assertion, "AssertTimeoutUpdateTime").map(|d| unix_time(&d)));
+            let timeout = field::<CFNumber>(&assertion, "TimeoutSeconds")
+                .and_then(|n| n.to_f64())
+                .zip(field::<CFDate>(&assertion, "AssertStartWhen").map(|d| unix_time(&d)));
+            assertions.push(Assertion {
+                pid,
+                id: field::<CFNumber>(&assertion, "AssertionId")
+                    .and_then(|n| n.to_i64())
+                    .map(|id| id as u32),
+                process: field::<CFString>(&assertion, "Process Name").map(|s| s.to_string()),
+                assertion_type: assertion_type.to_string(),
+                name: field::<CFString>(&assertion, "AssertName").map(|s| s.to_string()),
+                expires_in: expires_in(time_left, timeout, now),
+            });
+        }
+    }
+    trace!("other assertions: {assertions:?}");
+    assertions.sort_by_key(|assertion| assertion.pid);
+    assertions
+}
+
+/// Active assertions a process holds, going by powerd
+pub fn held_by(pid: i32) -> Vec<Assertion> {
+    let mut assertions = list(&[]);
+    assertions.retain(|assertion| assertion.pid == pid);
+    assertions
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_expires_in() {
+        assert_eq!(super::expires_in(None, None, 1000.0), None);
+        assert_eq!(super::expires_in(None, Some((0.0, 900.0)), 1000.0), None);
+        assert_eq!(
+            super::expires_in(None, Some((720.0, 900.0)), 1000.0),
+            Some(620)
+        );
+        // What powerd last saw wins over the original timeout
+        assert_eq!(
+            super::expires_in(Some((300.0, 950.0)), Some((720.0, 900.0)), 1000.0),
+            Some(250)
+        );
+        assert_eq!(
+            super::expires_in(Some((10.0, 900.0)), None, 1000.0),
+            Some(0)
+        );
+    }
+}
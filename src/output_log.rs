@@ -0,0 +1,157 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How many rotated logs (path.1, path.2, ...) are kept
+const KEEP_ROTATED: u32 = 5;
+
+/// Which of the command's outputs some text came from
+#[derive(Debug, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A copy of a wrapped command's output, one timestamped line at a time (see --log-output)
+pub struct OutputLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// Rotate once the file would grow past this many bytes
+    max_size: Option<u64>,
+    /// The unfinished last line of each stream
+    partial: [Vec<u8>; 2],
+    /// Set after a write fails, so it's only reported once
+    failed: bool,
+}
+
+/// Parse a size like "500K", "10M" or "1G" (or plain bytes)
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&size[..index], 1 << 10),
+        Some((index, 'M' | 'm')) => (&size[..index], 1 << 20),
+        Some((index, 'G' | 'g')) => (&size[..index], 1 << 30),
+        _ => (size, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| format!("\"{size}\" isn't a size like \"10M\""))
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+    PathBuf::from(rotated)
+}
+
+impl OutputLog {
+    /// Open the log, adding to it if it's already there
+    pub fn open(path: &Path, max_size: Option<u64>) -> io::Result<OutputLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(OutputLog {
+            path: path.to_path_buf(),
+            size: file.metadata()?.len(),
+            file,
+            max_size,
+            partial: [Vec::new(), Vec::new()],
+            failed: false,
+        })
+    }
+
+    /// Move path to path.1, path.1 to path.2 and so on, and start a new file
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..KEEP_ROTATED).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, stream: Stream, line: &[u8]) -> io::Result<()> {
+        let prefix = format!(
+            "{} {} ",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            match stream {
+                Stream::Stdout => "out",
+                Stream::Stderr => "err",
+            }
+        );
+        let length = (prefix.len() + line.len() + 1) as u64;
+        if self
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + length > max_size)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(prefix.as_bytes())?;
+        self.file.write_all(line)?;
+        self.file.write_all(b"\n")?;
+        self.size += length;
+        Ok(())
+    }
+
+    fn report(&mut self, result: io::Result<()>) {
+        if let (Err(err), false) = (result, self.failed) {
+            eprintln!(
+                "Error: Couldn't write to {}: {err}. Output won't be logged anymore.",
+                self.path.display()
+            );
+            self.failed = true;
+        }
+    }
+
+    /// Log whatever complete lines there are, holding on to an unfinished one
+    pub fn write(&mut self, stream: Stream, data: &[u8]) {
+        if self.failed {
+            return;
+        }
+        let mut pending = std::mem::take(&mut self.partial[stream as usize]);
+        pending.extend_from_slice(data);
+        let mut lines: Vec<&[u8]> = pending.split(|byte| *byte == b'\n').collect();
+        // Whatever's after the last newline isn't a full line yet
+        let unfinished = lines.pop().unwrap_or_default().to_vec();
+        for line in lines {
+            let result = self.write_line(stream, line);
+            self.report(result);
+        }
+        self.partial[stream as usize] = unfinished;
+    }
+
+    /// Log the unfinished lines too, once the command is done
+    pub fn finish(&mut self) {
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            let unfinished = std::mem::take(&mut self.partial[stream as usize]);
+            if !unfinished.is_empty() && !self.failed {
+                let result = self.write_line(stream, &unfinished);
+                self.report(result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(super::parse_size("1024"), Ok(1024));
+        assert_eq!(super::parse_size("500K"), Ok(500 * 1024));
+        assert_eq!(super::parse_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(super::parse_size("1g"), Ok(1 << 30));
+        assert!(super::parse_size("0").is_err());
+        assert!(super::parse_size("M").is_err());
+        assert!(super::parse_size("ten").is_err());
+    }
+}
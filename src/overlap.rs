@@ -0,0 +1,40 @@
+//! Overlapping sessions. Sleep stays prevented until the last of them ends, which is easy to
+//! miss from the terminal of one that ends earlier.
+
+/// When the last of these sessions ends, given when each one does (None if that's not known,
+/// like for a command or without a timeout). None if any of them has no known end.
+pub fn union_deadline(ends: &[Option<i64>]) -> Option<i64> {
+    ends.iter()
+        .copied()
+        .collect::<Option<Vec<i64>>>()?
+        .into_iter()
+        .max()
+}
+
+/// Whether sleep stays prevented after a session ending at `own` does, going by the union
+/// deadline of all of them
+pub fn outlasts(union: Option<i64>, own: Option<i64>) -> bool {
+    match (union, own) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(union), Some(own)) => union > own,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_union_deadline() {
+        assert_eq!(
+            super::union_deadline(&[Some(10), Some(30), Some(20)]),
+            Some(30)
+        );
+        assert_eq!(super::union_deadline(&[Some(10), None]), None);
+        assert_eq!(super::union_deadline(&[]), None);
+
+        assert!(super::outlasts(Some(30), Some(20)));
+        assert!(!super::outlasts(Some(20), Some(20)));
+        assert!(super::outlasts(None, Some(20)));
+        assert!(!super::outlasts(None, None));
+    }
+}
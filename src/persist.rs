@@ -0,0 +1,140 @@
+use crate::ctl;
+use crate::session::{self, Session};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const LAUNCH_AGENT_LABEL: &str = "com.randomblock1.caffeinate2.rearm";
+
+/// A --persist session, recorded so `rearm` can start it again after a reboot
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PersistedSession {
+    /// Arguments to start it with. The timeout in them is replaced by what's left until `until`.
+    pub args: Vec<String>,
+    /// Unix timestamp (in milliseconds) of the original deadline
+    pub until: i64,
+    /// PID of the process currently holding it
+    pub pid: i32,
+}
+
+fn home() -> PathBuf {
+    PathBuf::from(std::env::var_os("HOME").unwrap_or_default())
+}
+
+/// Unlike the session directory, this has to survive reboots
+fn state_dir() -> PathBuf {
+    home().join("Library/Application Support/caffeinate2/persisted")
+}
+
+fn path(id: &str) -> PathBuf {
+    state_dir().join(format!("{id}.json"))
+}
+
+fn launch_agent_path() -> PathBuf {
+    home()
+        .join("Library/LaunchAgents")
+        .join(format!("{LAUNCH_AGENT_LABEL}.plist"))
+}
+
+impl PersistedSession {
+    pub fn save(&self, id: &str) -> io::Result<()> {
+        fs::create_dir_all(state_dir())?;
+        trace!(
+            "writing persisted session {}: {:?}",
+            path(id).display(),
+            self
+        );
+        session::write_atomically(&path(id), &serde_json::to_vec(self)?)
+    }
+
+    pub fn load(id: &str) -> Option<PersistedSession> {
+        let contents = fs::read(path(id)).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    /// All recorded sessions, by ID
+    fn list() -> Vec<(String, PersistedSession)> {
+        let Ok(entries) = fs::read_dir(state_dir()) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let id = entry.path().file_stem()?.to_str()?.to_string();
+                Some((id.clone(), Self::load(&id)?))
+            })
+            .collect()
+    }
+
+    /// Whether the process recorded as holding it is still a running caffeinate2 session
+    fn is_running(&self) -> bool {
+        session::is_alive(self.pid) && Session::load(self.pid).is_some()
+    }
+}
+
+/// A recorded session started with these arguments that should still be running but isn't
+/// (like after a crash). Starting it again picks up its deadline instead of starting over.
+pub fn find_interrupted(args: &[String]) -> Option<String> {
+    let now = chrono::Local::now().timestamp_millis();
+    PersistedSession::list()
+        .into_iter()
+        .find(|(_, persisted)| {
+            persisted.args == args && persisted.until > now && !persisted.is_running()
+        })
+        .map(|(id, _)| id)
+}
+
+/// Stop re-arming a session, because it finished or was stopped on purpose
+pub fn remove(id: &str) {
+    let result = fs::remove_file(path(id));
+    trace!("removing persisted session {id}: {result:?}");
+}
+
+/// Stop re-arming whatever session the process is holding (for `ctl stop`)
+pub fn remove_for_pid(pid: i32) {
+    for (id, persisted) in PersistedSession::list() {
+        if persisted.pid == pid {
+            remove(&id);
+        }
+    }
+}
+
+/// Install a LaunchAgent that runs `caffeinate2 rearm` at login, if there isn't one yet.
+/// Returns its path if it was just installed.
+pub fn install_launch_agent() -> io::Result<Option<PathBuf>> {
+    let agent_path = launch_agent_path();
+    if agent_path.exists() {
+        return Ok(None);
+    }
+
+    let exe = std::env::current_exe()?.display().to_string();
+    let mut agent = plist::Dictionary::new();
+    agent.insert(String::from("Label"), LAUNCH_AGENT_LABEL.into());
+    agent.insert(
+        String::from("ProgramArguments"),
+        plist::Value::Array(vec![exe.into(), String::from("rearm").into()]),
+    );
+    agent.insert(String::from("RunAtLoad"), true.into());
+
+    fs::create_dir_all(agent_path.parent().unwrap())?;
+    plist::to_file_xml(&agent_path, &agent).map_err(io::Error::other)?;
+    Ok(Some(agent_path))
+}
+
+/// Start every recorded session that isn't running, and forget the ones that are over
+pub fn rearm() {
+    let now = chrono::Local::now().timestamp_millis();
+    for (id, persisted) in PersistedSession::list() {
+        if persisted.until <= now {
+            remove(&id);
+        } else if !persisted.is_running() {
+            let mut args = persisted.args.clone();
+            args.extend([String::from("--persisted-id"), id.clone()]);
+            match ctl::spawn_background(&args) {
+                Ok(pid) => println!("Re-armed session {id} (PID {pid})."),
+                Err(err) => eprintln!("Error: Couldn't re-arm session {id}: {err}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! What kind of Mac this is, which changes what some options amount to (like --disk on one
+//! with only solid-state storage, or -e on one without a lid).
+
+/// Whether `ioreg -r -c IOPMrootDomain -d 1` shows a clamshell state, which only laptops have
+pub fn has_lid(ioreg: &str) -> bool {
+    ioreg.contains("\"AppleClamshellState\"")
+}
+
+/// Whether any drive in `ioreg -r -c IOBlockStorageDevice -d 1` spins. None if none of them
+/// say what they are.
+pub fn has_rotational_disk(ioreg: &str) -> Option<bool> {
+    if ioreg.contains("\"Medium Type\"=\"Rotational\"") {
+        Some(true)
+    } else if ioreg.contains("\"Medium Type\"") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Like "Apple silicon laptop" or "Intel desktop"
+pub fn describe(apple_silicon: bool, lid: bool) -> String {
+    let chip = if apple_silicon {
+        "Apple silicon"
+    } else {
+        "Intel"
+    };
+    let kind = if lid { "laptop" } else { "desktop" };
+    format!("{chip} {kind}")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_platform() {
+        let root_domain = "+-o IOPMrootDomain  <class IOPMrootDomain>\n    {\n      \"AppleClamshellState\" = No\n      \"SleepDisabled\" = No\n    }\n";
+        assert!(super::has_lid(root_domain));
+        assert!(!super::has_lid("+-o IOPMrootDomain\n    {\n    }\n"));
+
+        let nvme = "  \"Device Characteristics\" = {\"Medium Type\"=\"Solid State\",\"Product Name\"=\"APPLE SSD AP0512Q\"}\n";
+        let usb_drive = "  \"Device Characteristics\" = {\"Medium Type\"=\"Rotational\",\"Product Name\"=\"Expansion HDD\"}\n";
+        assert_eq!(super::has_rotational_disk(nvme), Some(false));
+        assert_eq!(
+            super::has_rotational_disk(&format!("{nvme}{usb_drive}")),
+            Some(true)
+        );
+        assert_eq!(super::has_rotational_disk(""), None);
+
+        assert_eq!(super::describe(true, false), "Apple silicon desktop");
+        assert_eq!(super::describe(false, true), "Intel laptop");
+    }
+}
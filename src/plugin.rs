@@ -0,0 +1,111 @@
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// Where condition plugins live: ~/.config/caffeinate2/conditions/<name>
+fn plugin_dir() -> PathBuf {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config")
+        });
+    config.join("caffeinate2/conditions")
+}
+
+/// Find a plugin by name
+pub fn path(name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(format!("\"{name}\" isn't a plugin name"));
+    }
+    let path = plugin_dir().join(name);
+    if !path.is_file() {
+        return Err(format!("there's no plugin at {}", path.display()));
+    }
+    Ok(path)
+}
+
+/// A plugin's answer: "hold" to prevent sleep, "release" to allow it
+fn parse_answer(line: &str) -> Option<bool> {
+    match line.trim().to_ascii_lowercase().as_str() {
+        "hold" => Some(true),
+        "release" => Some(false),
+        _ => None,
+    }
+}
+
+fn report_invalid(name: &str, line: &str) {
+    eprintln!(
+        "Warning: Plugin {name} printed \"{}\", expected hold or release.",
+        line.trim()
+    );
+}
+
+/// Run `plugin --check` and read its answer
+fn check(name: &str, path: &Path) -> io::Result<Option<bool>> {
+    let output = process::Command::new(path)
+        .arg("--check")
+        .stdin(process::Stdio::null())
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next().unwrap_or_default();
+    trace!("{} --check: {} {line:?}", path.display(), output.status);
+    let answer = parse_answer(line);
+    if answer.is_none() {
+        report_invalid(name, line);
+    }
+    Ok(answer)
+}
+
+/// Follow a plugin's answers. It's started with `--watch` and can keep running, printing a line
+/// whenever its answer changes. Once it exits, it's run with `--check` every `interval` instead,
+/// so plugins that only print one answer work too.
+pub fn watch(
+    name: String,
+    path: PathBuf,
+    interval: Duration,
+    callback: impl Fn(bool) + Send + 'static,
+) {
+    thread::spawn(move || {
+        match process::Command::new(&path)
+            .arg("--watch")
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .spawn()
+        {
+            Ok(mut child) => {
+                let stdout = child.stdout.take().unwrap();
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    trace!("{} --watch: {line:?}", path.display());
+                    match parse_answer(&line) {
+                        Some(hold) => callback(hold),
+                        None => report_invalid(&name, &line),
+                    }
+                }
+                let _ = child.wait();
+            }
+            Err(err) => eprintln!("Warning: Couldn't run plugin {name}: {err}"),
+        }
+
+        loop {
+            thread::sleep(interval);
+            match check(&name, &path) {
+                Ok(Some(hold)) => callback(hold),
+                Ok(None) => {}
+                Err(err) => eprintln!("Warning: Couldn't run plugin {name}: {err}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_answer() {
+        assert_eq!(super::parse_answer("hold\n"), Some(true));
+        assert_eq!(super::parse_answer(" Release "), Some(false));
+        assert_eq!(super::parse_answer(""), None);
+        assert_eq!(super::parse_answer("yes"), None);
+    }
+}
@@ -0,0 +1,146 @@
+//! Policy scripts for --policy-script: a rhai function that's given what the Mac is doing
+//! and decides what to keep awake.
+
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::path::Path;
+
+/// What a policy script gets to look at, passed to its `policy` function as a map
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    /// Battery charge in percent, if there's a battery
+    pub battery: Option<i64>,
+    pub on_ac: bool,
+    /// Seconds since the last keyboard or mouse input
+    pub idle_seconds: i64,
+    /// Name of the app in front, like "Safari"
+    pub frontmost_app: Option<String>,
+    pub hour: i64,
+    pub minute: i64,
+    /// 1 for Monday through 7 for Sunday
+    pub weekday: i64,
+}
+
+impl Inputs {
+    fn to_map(&self) -> Map {
+        let mut map = Map::new();
+        map.insert(
+            "battery".into(),
+            self.battery.map_or(Dynamic::UNIT, Dynamic::from),
+        );
+        map.insert("on_ac".into(), self.on_ac.into());
+        map.insert("idle_seconds".into(), self.idle_seconds.into());
+        map.insert(
+            "frontmost_app".into(),
+            self.frontmost_app
+                .clone()
+                .map_or(Dynamic::UNIT, Dynamic::from),
+        );
+        map.insert("hour".into(), self.hour.into());
+        map.insert("minute".into(), self.minute.into());
+        map.insert("weekday".into(), self.weekday.into());
+        map
+    }
+}
+
+/// What a policy script wants kept awake
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Everything the session holds (true), or nothing (false)
+    All(bool),
+    /// Only these sleep types (like "display" or "system") or assertion types
+    Only(Vec<String>),
+}
+
+pub struct PolicyScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl PolicyScript {
+    pub fn compile(source: &str) -> Result<PolicyScript, String> {
+        let engine = Engine::new();
+        let ast = engine.compile(source).map_err(|err| err.to_string())?;
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == "policy")
+        {
+            return Err(String::from("it doesn't define a policy(inputs) function"));
+        }
+        Ok(PolicyScript { engine, ast })
+    }
+
+    pub fn load(path: &Path) -> Result<PolicyScript, String> {
+        let source = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Self::compile(&source)
+    }
+
+    /// Run the script's `policy` function. It returns true or false for everything,
+    /// or an array of what to keep.
+    pub fn evaluate(&self, inputs: &Inputs) -> Result<Decision, String> {
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, "policy", (inputs.to_map(),))
+            .map_err(|err| err.to_string())?;
+        if let Some(hold) = result.clone().try_cast::<bool>() {
+            return Ok(Decision::All(hold));
+        }
+        let Some(array) = result.clone().try_cast::<rhai::Array>() else {
+            return Err(format!(
+                "policy() returned {}, expected true, false or an array",
+                result.type_name()
+            ));
+        };
+        array
+            .into_iter()
+            .map(|item| {
+                item.into_string()
+                    .map_err(|kind| format!("policy() returned an array with a {kind} in it"))
+            })
+            .collect::<Result<_, _>>()
+            .map(Decision::Only)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, Inputs, PolicyScript};
+
+    #[test]
+    fn test_evaluate() {
+        let script = PolicyScript::compile(
+            r#"
+            fn policy(inputs) {
+                if inputs.battery != () && inputs.battery < 20 { return false; }
+                if inputs.frontmost_app == "Keynote" { return ["display", "system"]; }
+                inputs.hour >= 9 && inputs.hour < 17
+            }
+            "#,
+        )
+        .unwrap();
+        let inputs = Inputs {
+            hour: 10,
+            ..Inputs::default()
+        };
+        assert_eq!(script.evaluate(&inputs), Ok(Decision::All(true)));
+        let low_battery = Inputs {
+            battery: Some(10),
+            ..inputs.clone()
+        };
+        assert_eq!(script.evaluate(&low_battery), Ok(Decision::All(false)));
+        let presenting = Inputs {
+            frontmost_app: Some(String::from("Keynote")),
+            ..inputs
+        };
+        assert_eq!(
+            script.evaluate(&presenting),
+            Ok(Decision::Only(vec![
+                String::from("display"),
+                String::from("system")
+            ]))
+        );
+
+        assert!(PolicyScript::compile("fn other() { true }").is_err());
+        let wrong_type = PolicyScript::compile("fn policy(inputs) { 42 }").unwrap();
+        assert!(wrong_type.evaluate(&Inputs::default()).is_err());
+    }
+}
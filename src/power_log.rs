@@ -0,0 +1,177 @@
+//! Reading powerd's records of why the Mac went to sleep, from `pmset -g log` or the unified log.
+
+use chrono::{DateTime, FixedOffset};
+
+/// One line of `pmset -g log`, like
+/// `2024-03-02 17:00:00 +0100 Sleep  <tab>Entering Sleep state due to 'Clamshell Sleep': Using BATT`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerEvent {
+    pub time: DateTime<FixedOffset>,
+    /// What kind of event it is, like "Sleep", "Wake", "DarkWake" or "Assertions"
+    pub domain: String,
+    pub message: String,
+}
+
+/// Parse a line of `pmset -g log`. Headers and other lines without a timestamp are skipped.
+pub fn parse_pmset_line(line: &str) -> Option<PowerEvent> {
+    // The timestamp is always 25 characters long
+    let time = DateTime::parse_from_str(line.get(..25)?, "%Y-%m-%d %H:%M:%S %z").ok()?;
+    let rest = line[25..].trim_start();
+    let (domain, message) = rest.split_once('\t').unwrap_or((rest, ""));
+    Some(PowerEvent {
+        time,
+        domain: domain.trim().to_string(),
+        message: message.trim().to_string(),
+    })
+}
+
+/// The reason powerd recorded for a sleep, like "Clamshell Sleep" in
+/// "Entering Sleep state due to 'Clamshell Sleep':TCPKeepAlive=active"
+pub fn sleep_reason(message: &str) -> Option<&str> {
+    let (_, rest) = message.split_once("Entering Sleep state due to '")?;
+    let (reason, _) = rest.split_once('\'')?;
+    Some(reason)
+}
+
+/// The reason recorded for a wake, like "EC.LidOpen/Lid Open" in
+/// "Wake from Deep Idle [CDNVA] : due to EC.LidOpen/Lid Open Using BATT (Charge:85%)"
+pub fn wake_reason(message: &str) -> Option<&str> {
+    let (_, rest) = message.split_once("due to ")?;
+    let reason = rest.split(" Using ").next().unwrap_or(rest);
+    Some(reason.trim())
+}
+
+/// A sleep and what happened around it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SleepRecord {
+    pub sleep: PowerEvent,
+    /// The next wake (or dark wake) after it, if there's been one
+    pub wake: Option<PowerEvent>,
+    /// Assertion changes in the minutes before the sleep, like one being released
+    pub assertions: Vec<PowerEvent>,
+}
+
+impl SleepRecord {
+    pub fn reason(&self) -> Option<&str> {
+        sleep_reason(&self.sleep.message)
+    }
+
+    /// Whether it's just the Mac going back to sleep after waking up for maintenance
+    pub fn is_maintenance(&self) -> bool {
+        matches!(
+            self.reason(),
+            Some("Maintenance Sleep" | "Sleep Service Back to Sleep")
+        )
+    }
+}
+
+/// How far back assertion changes count as "just before" a sleep
+const ASSERTION_WINDOW_SECS: i64 = 120;
+
+/// Group a log (oldest first) into sleeps, each with its wake and the assertion changes just before it
+pub fn sleep_records(events: &[PowerEvent]) -> Vec<SleepRecord> {
+    let mut records = Vec::new();
+    for (index, event) in events.iter().enumerate() {
+        if event.domain != "Sleep" {
+            continue;
+        }
+        let wake = events[index + 1..]
+            .iter()
+            .find(|later| later.domain.ends_with("Wake"))
+            .cloned();
+        let mut assertions: Vec<PowerEvent> = events[..index]
+            .iter()
+            .rev()
+            .take_while(|earlier| {
+                (event.time - earlier.time).num_seconds() <= ASSERTION_WINDOW_SECS
+                    && earlier.domain != "Sleep"
+                    && !earlier.domain.ends_with("Wake")
+            })
+            .filter(|earlier| earlier.domain == "Assertions")
+            .cloned()
+            .collect();
+        assertions.reverse();
+        records.push(SleepRecord {
+            sleep: event.clone(),
+            wake,
+            assertions,
+        });
+    }
+    records
+}
+
+/// A plain explanation of a sleep reason, for the ones that override assertions
+pub fn explain(reason: &str) -> &'static str {
+    // Software sleeps include the PID that asked, like "Software Sleep pid=123"
+    match reason.split(" pid=").next().unwrap_or(reason) {
+        "Clamshell Sleep" => "the lid was closed",
+        "Thermal Emergency Sleep" => "the Mac was overheating",
+        "Low Power Sleep" => "the battery was almost empty",
+        "Software Sleep" => {
+            "something asked it to (like Sleep in the Apple menu or `pmset sleepnow`)"
+        }
+        "Power Button Sleep" => "the power button was pressed",
+        "Idle Sleep" => "it was idle and nothing was preventing sleep",
+        "Maintenance Sleep" | "Sleep Service Back to Sleep" => {
+            "it went back to sleep after waking up briefly for maintenance"
+        }
+        _ => "of something caffeinate2 doesn't recognize",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_pmset_line() {
+        let event = super::parse_pmset_line(
+            "2024-03-02 17:00:00 +0100 Sleep               \tEntering Sleep state due to 'Clamshell Sleep':TCPKeepAlive=active Using BATT (Charge:85%)\t2 secs",
+        )
+        .unwrap();
+        assert_eq!(event.domain, "Sleep");
+        assert_eq!(event.time.to_rfc3339(), "2024-03-02T17:00:00+01:00");
+        assert_eq!(super::sleep_reason(&event.message), Some("Clamshell Sleep"));
+
+        assert_eq!(
+            super::parse_pmset_line("Time stamp                Domain"),
+            None
+        );
+        assert_eq!(super::parse_pmset_line(""), None);
+    }
+
+    #[test]
+    fn test_sleep_records() {
+        let log = "\
+2024-03-02 16:50:00 +0100 Assertions          \tPID 42(caffeinate2) Created PreventUserIdleSystemSleep
+2024-03-02 16:59:30 +0100 Assertions          \tPID 42(caffeinate2) Released PreventUserIdleSystemSleep
+2024-03-02 17:00:00 +0100 Sleep               \tEntering Sleep state due to 'Idle Sleep': Using AC (Charge:100%)
+2024-03-02 17:12:00 +0100 Wake                \tWake from Deep Idle [CDNVA] : due to UserActivity Assertion Using AC (Charge:100%)
+2024-03-02 18:00:00 +0100 Sleep               \tEntering Sleep state due to 'Maintenance Sleep': Using AC";
+        let events: Vec<_> = log.lines().filter_map(super::parse_pmset_line).collect();
+        let records = super::sleep_records(&events);
+        assert_eq!(records.len(), 2);
+
+        assert_eq!(records[0].reason(), Some("Idle Sleep"));
+        assert_eq!(
+            records[0]
+                .wake
+                .as_ref()
+                .and_then(|wake| super::wake_reason(&wake.message)),
+            Some("UserActivity Assertion")
+        );
+        // Only the release is close enough to the sleep to count
+        assert_eq!(records[0].assertions.len(), 1);
+        assert!(records[0].assertions[0].message.contains("Released"));
+
+        assert!(records[1].is_maintenance());
+        assert_eq!(records[1].wake, None);
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!(super::explain("Clamshell Sleep"), "the lid was closed");
+        assert_eq!(
+            super::explain("Software Sleep pid=123"),
+            super::explain("Software Sleep")
+        );
+    }
+}
@@ -1,10 +1,12 @@
-use core_foundation::base::{TCFType, TCFTypeRef};
+use core_foundation::base::{CFRelease, TCFType, TCFTypeRef};
 use core_foundation::boolean::CFBoolean;
 use core_foundation::dictionary::{CFDictionaryGetValueIfPresent, CFDictionaryRef};
-use core_foundation::number::CFBooleanRef;
+use core_foundation::number::{CFBooleanRef, CFNumber};
 use core_foundation::string::{CFString, CFStringRef};
 use libloading::{Library, Symbol};
+use once_cell::sync::Lazy;
 use std::mem::MaybeUninit;
+use std::time::Duration;
 
 // constants
 type IOPMAssertionID = u32;
@@ -12,41 +14,181 @@ type IOPMAssertionLevel = u32;
 const IOPMASSERTION_LEVEL_ON: u32 = 255;
 const IOPMASSERTION_LEVEL_OFF: u32 = 0;
 
+/// kIOPMNullAssertionID
+const KIOPM_NULL_ASSERTION_ID: u32 = 0;
+
+/// kIOPMUserActiveLocal, activity from someone sitting at the Mac
+const KIOPM_USER_ACTIVE_LOCAL: u32 = 0;
+
+// See IOKit/IOReturn.h for error codes.
+pub const IORETURN_NOT_PRIVILEGED: u32 = 0xE00002C1;
+const IORETURN_BAD_ARGUMENT: u32 = 0xE00002C2;
+const IORETURN_NOT_PERMITTED: u32 = 0xE00002E2;
+const IORETURN_UNSUPPORTED: u32 = 0xE00002C7;
+const IORETURN_NO_RESOURCES: u32 = 0xE00002BE;
+const IORETURN_IPC_ERROR: u32 = 0xE00002BF;
+const IORETURN_BUSY: u32 = 0xE00002D5;
+const IORETURN_TIMEOUT: u32 = 0xE00002D6;
+const IORETURN_OFFLINE: u32 = 0xE00002D7;
+const IORETURN_NOT_READY: u32 = 0xE00002D8;
+const IORETURN_NOT_RESPONDING: u32 = 0xE00002ED;
+
+/// Whether an error is likely to go away by itself, like while powerd is restarting
+pub fn is_transient(code: u32) -> bool {
+    matches!(
+        code,
+        IORETURN_NO_RESOURCES
+            | IORETURN_IPC_ERROR
+            | IORETURN_BUSY
+            | IORETURN_TIMEOUT
+            | IORETURN_OFFLINE
+            | IORETURN_NOT_READY
+            | IORETURN_NOT_RESPONDING
+    )
+}
+
+/// A human-readable explanation of an IOReturn error code
+pub fn describe_error(code: u32) -> String {
+    let reason = match code {
+        IORETURN_NOT_PRIVILEGED => "needs root",
+        IORETURN_NOT_PERMITTED => "not permitted here",
+        IORETURN_UNSUPPORTED => "not supported on this Mac",
+        IORETURN_BAD_ARGUMENT => "invalid argument",
+        code if is_transient(code) => "powerd isn't responding",
+        _ => "unexpected error",
+    };
+    format!("{reason}, code {code:#X}")
+}
+
+extern "C" {
+    // In libSystem. Nonzero if the process runs in a sandbox (like the App Sandbox).
+    fn sandbox_check(pid: i32, operation: *const std::os::raw::c_char, filter_type: i32) -> i32;
+}
+
+/// Whether we're sandboxed, where some power management calls are denied no matter who runs us
+pub fn is_sandboxed() -> bool {
+    let result = unsafe { sandbox_check(std::process::id() as i32, std::ptr::null(), 0) };
+    trace!("sandbox_check(self) = {}", result);
+    result != 0
+}
+
+// Public IOKit API, linked directly so it can't fail to load at runtime
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOPMAssertionCreateWithName(
+        assertion_type: CFStringRef,
+        level: IOPMAssertionLevel,
+        name: CFStringRef,
+        id: *mut IOPMAssertionID,
+    ) -> i32;
+    fn IOPMAssertionCreateWithDescription(
+        assertion_type: CFStringRef,
+        name: CFStringRef,
+        details: CFStringRef,
+        human_readable_reason: CFStringRef,
+        localization_bundle_path: CFStringRef,
+        timeout: f64,
+        timeout_action: CFStringRef,
+        id: *mut IOPMAssertionID,
+    ) -> i32;
+    fn IOPMAssertionSetProperty(
+        id: IOPMAssertionID,
+        key: CFStringRef,
+        value: core_foundation::base::CFTypeRef,
+    ) -> i32;
+    fn IOPMAssertionRelease(id: IOPMAssertionID) -> u32;
+    fn IOPMAssertionDeclareUserActivity(
+        name: CFStringRef,
+        level: IOPMAssertionLevel,
+        id: *mut IOPMAssertionID,
+    ) -> i32;
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGMainDisplayID() -> u32;
+    fn CGDisplayIsAsleep(display: u32) -> i32;
+}
+
+/// Whether the main display is asleep
+pub fn display_is_asleep() -> bool {
+    let asleep = unsafe { CGDisplayIsAsleep(CGMainDisplayID()) } != 0;
+    trace!("CGDisplayIsAsleep(main) = {}", asleep);
+    asleep
+}
+
+// Private power settings API, which isn't in the SDK headers, so it's looked up at runtime
+type CopySystemPowerSettingsFn = unsafe extern "C" fn() -> CFDictionaryRef;
+type SetSystemPowerSettingFn = unsafe extern "C" fn(CFStringRef, CFBooleanRef) -> u32;
+
+const IOKIT_PATH: &str = "/System/Library/Frameworks/IOKit.framework/IOKit";
+
+/// IOKit, opened by path. If that's refused (like by the hardened runtime, or because the
+/// binary is quarantined), the copy already linked into the process is searched instead.
+static IOKIT_LIBRARY: Lazy<Library> = Lazy::new(|| match unsafe { Library::new(IOKIT_PATH) } {
+    Ok(library) => {
+        trace!("dlopen IOKit: ok");
+        library
+    }
+    Err(err) => {
+        trace!("dlopen IOKit: {err}");
+        eprintln!(
+            "Warning: Couldn't load IOKit ({err}), so sleep settings are looked up in the copy caffeinate2 is linked against. If -e still fails, check that caffeinate2 isn't quarantined (`xattr -d com.apple.quarantine \"$(which caffeinate2)\"`)."
+        );
+        libloading::os::unix::Library::this().into()
+    }
+});
+
+/// The private IOKit functions looked up at runtime
+pub const PRIVATE_SYMBOLS: [&str; 2] = ["IOPMCopySystemPowerSettings", "IOPMSetSystemPowerSetting"];
+
+/// Whether a private IOKit symbol can be looked up on this version of macOS
+pub fn has_private_symbol(name: &str) -> bool {
+    let found =
+        unsafe { IOKIT_LIBRARY.get::<*const std::os::raw::c_void>(name.as_bytes()) }.is_ok();
+    trace!("dlsym IOKit {name}: {found}");
+    found
+}
+
+/// Look up a private IOKit symbol and copy out the function pointer. Fails with
+/// kIOReturnUnsupported if this version of macOS doesn't have it.
+fn private_symbol<T: Copy>(name: &[u8]) -> Result<T, u32> {
+    let symbol: Result<Symbol<T>, _> = unsafe { IOKIT_LIBRARY.get(name) };
+    match symbol {
+        Ok(symbol) => Ok(*symbol),
+        Err(err) => {
+            trace!("dlsym IOKit {}: {err}", String::from_utf8_lossy(name));
+            Err(IORETURN_UNSUPPORTED)
+        }
+    }
+}
+
 // global variables
 pub struct IOKit {
-    library: Library,
     assertion_name: CFString,
 }
 
+// The only state is an immutable CFString, and immutable CF objects are safe to share between threads
+unsafe impl Send for IOKit {}
+unsafe impl Sync for IOKit {}
+
 // functions
 impl IOKit {
     pub fn new() -> IOKit {
-        let library =
-            unsafe { Library::new("/System/Library/Frameworks/IOKit.framework/IOKit").unwrap() };
         let assertion_name = CFString::new("caffeinate2");
-        IOKit {
-            library,
-            assertion_name,
-        }
+        IOKit { assertion_name }
     }
 
-    fn iopm_copy_power_settings(&self) -> CFDictionaryRef {
-        let iokit = &self.library;
-        let iopm_copy_power_settings: Symbol<unsafe extern "C" fn() -> CFDictionaryRef> =
-            unsafe { iokit.get(b"IOPMCopySystemPowerSettings") }.unwrap();
-        unsafe { iopm_copy_power_settings() }
+    /// The system power settings, which the caller has to release. None if they can't be read.
+    fn iopm_copy_power_settings(&self) -> Option<CFDictionaryRef> {
+        let iopm_copy_power_settings: CopySystemPowerSettingsFn =
+            private_symbol(b"IOPMCopySystemPowerSettings").ok()?;
+        let settings = unsafe { iopm_copy_power_settings() };
+        trace!("IOPMCopySystemPowerSettings() = {:?}", settings);
+        (!settings.is_null()).then_some(settings)
     }
 
-    pub fn create_assertion(&self, assertion_type: &str, state: bool) -> u32 {
-        let iokit = &self.library;
-        let iopmassertion_create_with_name: Symbol<
-            unsafe extern "C" fn(
-                CFStringRef,
-                IOPMAssertionLevel,
-                CFStringRef,
-                *mut IOPMAssertionID,
-            ) -> i32,
-        > = unsafe { iokit.get(b"IOPMAssertionCreateWithName") }.unwrap();
+    pub fn create_assertion(&self, assertion_type: &str, state: bool) -> Result<u32, u32> {
         let type_ = CFString::new(assertion_type);
         let level = if state {
             IOPMASSERTION_LEVEL_ON
@@ -56,21 +198,24 @@ impl IOKit {
         let id = {
             let mut id = MaybeUninit::uninit();
             let status = unsafe {
-                iopmassertion_create_with_name(
+                IOPMAssertionCreateWithName(
                     type_.as_concrete_TypeRef(),
                     level,
                     self.assertion_name.as_concrete_TypeRef(),
                     id.as_mut_ptr(),
                 )
             };
-            if status == 0 {
-                unsafe { id.assume_init() }
-            } else {
-                panic!(
-                    "Failed to create power management assertion with code: {:X}",
-                    status
-                );
+            trace!(
+                "IOPMAssertionCreateWithName({:?}, {}, {:?}) = {:#X}",
+                assertion_type,
+                level,
+                self.assertion_name,
+                status
+            );
+            if status != 0 {
+                return Err(status as u32);
             }
+            unsafe { id.assume_init() }
         };
 
         #[cfg(debug_assertions)]
@@ -79,21 +224,76 @@ impl IOKit {
             id
         );
 
-        id
+        Ok(id)
+    }
+
+    /// Create an assertion that powerd releases by itself after `timeout`, even if we're
+    /// stopped or frozen by then
+    pub fn create_assertion_with_timeout(
+        &self,
+        assertion_type: &str,
+        timeout: Duration,
+    ) -> Result<u32, u32> {
+        let type_ = CFString::new(assertion_type);
+        let timeout_action = CFString::from_static_string("TimeoutActionRelease");
+        let mut id = KIOPM_NULL_ASSERTION_ID;
+        let status = unsafe {
+            IOPMAssertionCreateWithDescription(
+                type_.as_concrete_TypeRef(),
+                self.assertion_name.as_concrete_TypeRef(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                timeout.as_secs_f64(),
+                timeout_action.as_concrete_TypeRef(),
+                &mut id,
+            )
+        };
+        trace!(
+            "IOPMAssertionCreateWithDescription({:?}, {:?}, timeout {:?}) = {:#X}",
+            assertion_type,
+            self.assertion_name,
+            timeout,
+            status
+        );
+        if status != 0 {
+            return Err(status as u32);
+        }
+        Ok(id)
     }
 
-    pub fn release_assertion(&self, assertion_id: u32) {
-        let iokit = &self.library;
-        let iopmassertion_release: Symbol<unsafe extern "C" fn(IOPMAssertionID) -> u32> =
-            unsafe { iokit.get(b"IOPMAssertionRelease") }.unwrap();
+    /// Restart a held assertion's timeout so powerd releases it `timeout` from now
+    pub fn set_assertion_timeout(&self, assertion_id: u32, timeout: Duration) -> Result<(), u32> {
+        let key = CFString::from_static_string("TimeoutSeconds");
+        let value = CFNumber::from(timeout.as_secs_f64());
+        let status = unsafe {
+            IOPMAssertionSetProperty(
+                assertion_id,
+                key.as_concrete_TypeRef(),
+                value.as_CFTypeRef(),
+            )
+        };
+        trace!(
+            "IOPMAssertionSetProperty({}, TimeoutSeconds, {:?}) = {:#X}",
+            assertion_id,
+            timeout,
+            status
+        );
+        match status {
+            0 => Ok(()),
+            _ => Err(status as u32),
+        }
+    }
 
+    pub fn release_assertion(&self, assertion_id: u32) -> Result<(), u32> {
         #[cfg(debug_assertions)]
         println!(
             "Releasing power management assertion with ID: {}",
             assertion_id
         );
 
-        let status = unsafe { iopmassertion_release(assertion_id) };
+        let status = unsafe { IOPMAssertionRelease(assertion_id) };
+        trace!("IOPMAssertionRelease({}) = {:#X}", assertion_id, status);
 
         match status {
             0 => {
@@ -102,69 +302,101 @@ impl IOKit {
                     "Successfully released power management assertion with ID: {}",
                     assertion_id
                 );
+                Ok(())
             }
-            0xE00002C2 => {
+            IORETURN_BAD_ARGUMENT => {
                 #[cfg(debug_assertions)]
                 println!("Assertion {} already released", assertion_id);
+                Ok(())
             }
-            _ => panic!(
-                "Failed to release power management assertion with code: {:X}",
-                status
-            ),
+            _ => Err(status),
         }
     }
 
-    pub fn declare_user_activity(&self, state: bool) -> u32 {
-        let iokit = &self.library;
-        let iopmassertion_declare_user_activity: Symbol<
-            unsafe extern "C" fn(CFStringRef, IOPMAssertionLevel, *mut IOPMAssertionID) -> i32,
-        > = unsafe { iokit.get(b"IOPMAssertionDeclareUserActivity") }.unwrap();
+    /// Wake the display like a key press would. It then stays on until it's been idle for
+    /// its usual sleep time, so the declaration itself isn't kept.
+    pub fn wake_display(&self) -> Result<(), u32> {
+        let mut id = KIOPM_NULL_ASSERTION_ID;
+        let status = unsafe {
+            IOPMAssertionDeclareUserActivity(
+                self.assertion_name.as_concrete_TypeRef(),
+                KIOPM_USER_ACTIVE_LOCAL,
+                &mut id,
+            )
+        };
+        trace!(
+            "IOPMAssertionDeclareUserActivity({:?}, local) = {:#X}",
+            self.assertion_name,
+            status
+        );
+        if status != 0 {
+            return Err(status as u32);
+        }
+        self.release_assertion(id)
+    }
+
+    pub fn declare_user_activity(&self, state: bool) -> Result<u32, u32> {
+        self.redeclare_user_activity(state, KIOPM_NULL_ASSERTION_ID)
+    }
 
+    /// Declare user activity again, restarting the timeout of an existing declaration.
+    /// A declaration only lasts as long as the display sleep timer, so it has to be repeated.
+    pub fn redeclare_user_activity(&self, state: bool, assertion_id: u32) -> Result<u32, u32> {
         let level = if state {
             IOPMASSERTION_LEVEL_ON
         } else {
             IOPMASSERTION_LEVEL_OFF
         };
 
-        let mut id = MaybeUninit::uninit();
+        // In and out: an existing ID is updated rather than replaced
+        let mut id = assertion_id;
         let status = unsafe {
-            iopmassertion_declare_user_activity(
+            IOPMAssertionDeclareUserActivity(
                 self.assertion_name.as_concrete_TypeRef(),
                 level,
-                id.as_mut_ptr(),
+                &mut id,
             )
         };
+        trace!(
+            "IOPMAssertionDeclareUserActivity({:?}, {}, {}) = {:#X}",
+            self.assertion_name,
+            level,
+            assertion_id,
+            status
+        );
         if status != 0 {
-            panic!("Failed to declare user activity with code: {:X}", status);
+            return Err(status as u32);
         }
 
-        let id = unsafe { id.assume_init() };
-
         #[cfg(debug_assertions)]
         println!("Successfully declared user activity with ID: {}", id);
 
-        id
+        Ok(id)
     }
 
     pub fn set_sleep_disabled(&self, sleep_disabled: bool) -> Result<(), u32> {
-        let iokit = &self.library;
-        let iopm_set_system_power_setting: libloading::Symbol<
-            unsafe extern "C" fn(CFString, CFBoolean) -> u32,
-        > = unsafe { iokit.get(b"IOPMSetSystemPowerSetting").unwrap() };
-
         let sleep_disabled_bool = if sleep_disabled {
             CFBoolean::true_value()
         } else {
             CFBoolean::false_value()
         };
 
+        let iopm_set_system_power_setting: SetSystemPowerSettingFn =
+            private_symbol(b"IOPMSetSystemPowerSetting")?;
+        let key = CFString::from_static_string("SleepDisabled");
         let result = unsafe {
             iopm_set_system_power_setting(
-                CFString::from_static_string("SleepDisabled"),
-                sleep_disabled_bool,
+                key.as_concrete_TypeRef(),
+                sleep_disabled_bool.as_concrete_TypeRef(),
             )
         };
 
+        trace!(
+            "IOPMSetSystemPowerSetting(SleepDisabled, {}) = {:#X}",
+            sleep_disabled,
+            result
+        );
+
         #[cfg(debug_assertions)]
         println!(
             "Got result {:X} when {} sleep",
@@ -176,36 +408,39 @@ impl IOKit {
             }
         );
 
-        // See IOKit/IOReturn.h for error codes.
         if result == 0 {
-            // Success
             Ok(())
-        } else if result == 0xE00002C1 {
-            // Insufficient privileges
-            Err(result)
         } else {
-            panic!(
-                "Error: Failed to modify system sleep with code: {:X}",
-                result
-            );
+            Err(result)
         }
     }
 
+    /// Whether SleepDisabled is on. If the settings can't be read, it can't have been turned on
+    /// by us either, so that counts as off.
     pub fn get_sleep_disabled(&self) -> bool {
         let mut ptr: *const std::os::raw::c_void = std::ptr::null();
 
+        let Some(settings) = self.iopm_copy_power_settings() else {
+            return false;
+        };
         let result = unsafe {
             CFDictionaryGetValueIfPresent(
-                self.iopm_copy_power_settings(),
+                settings,
                 CFString::new("SleepDisabled").as_CFTypeRef().as_void_ptr(),
                 &mut ptr,
             )
         };
 
-        if result == 0 {
-            panic!("Failed to get SleepDisabled value!");
-        }
+        trace!(
+            "CFDictionaryGetValueIfPresent(SleepDisabled) = {}, value {:?}",
+            result,
+            ptr
+        );
 
-        ptr as CFBooleanRef == unsafe { core_foundation::number::kCFBooleanTrue }
+        // Missing until it's been set at least once
+        let sleep_disabled = result != 0
+            && ptr as CFBooleanRef == unsafe { core_foundation::number::kCFBooleanTrue };
+        unsafe { CFRelease(settings as *const std::os::raw::c_void) };
+        sleep_disabled
     }
 }
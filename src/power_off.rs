@@ -0,0 +1,165 @@
+use core_foundation::base::TCFType;
+use core_foundation::dictionary::CFDictionaryRef;
+use core_foundation::runloop::{
+    kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopSource, CFRunLoopSourceRef,
+};
+use core_foundation::string::{CFString, CFStringRef};
+use std::os::raw::c_void;
+use std::thread;
+
+/// Posted by loginwindow once the user has confirmed a shutdown or restart
+const SHUTDOWN_NOTIFICATION: &str = "com.apple.shutdownInitiated";
+const RESTART_NOTIFICATION: &str = "com.apple.restartInitiated";
+
+/// CFNotificationSuspensionBehaviorDeliverImmediately
+const DELIVER_IMMEDIATELY: isize = 4;
+
+/// kIOMessageCanSystemSleep and kIOMessageSystemWillSleep from IOKit/IOMessage.h
+const CAN_SYSTEM_SLEEP: u32 = 0xE000_0270;
+const SYSTEM_WILL_SLEEP: u32 = 0xE000_0280;
+
+type CFNotificationCallback = extern "C" fn(
+    center: *mut c_void,
+    observer: *mut c_void,
+    name: CFStringRef,
+    object: *const c_void,
+    user_info: CFDictionaryRef,
+);
+
+type IOServiceInterestCallback =
+    extern "C" fn(refcon: *mut c_void, service: u32, message_type: u32, argument: *mut c_void);
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFNotificationCenterGetDistributedCenter() -> *mut c_void;
+    fn CFNotificationCenterAddObserver(
+        center: *mut c_void,
+        observer: *const c_void,
+        callback: CFNotificationCallback,
+        name: CFStringRef,
+        object: *const c_void,
+        suspension_behavior: isize,
+    );
+}
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IORegisterForSystemPower(
+        refcon: *mut c_void,
+        notify_port: *mut *mut c_void,
+        callback: IOServiceInterestCallback,
+        notifier: *mut u32,
+    ) -> u32;
+    fn IONotificationPortGetRunLoopSource(notify: *mut c_void) -> CFRunLoopSourceRef;
+    fn IOAllowPowerChange(kernel_port: u32, notification_id: isize) -> i32;
+}
+
+/// What the user asked the Mac to do
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    Shutdown,
+    Restart,
+    Sleep,
+}
+
+impl Event {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Event::Shutdown => "The Mac is shutting down",
+            Event::Restart => "The Mac is restarting",
+            Event::Sleep => "The Mac is going to sleep",
+        }
+    }
+}
+
+type Callback = Box<dyn Fn(Event) + Send>;
+
+/// Everything the power notification callback needs
+struct Watcher {
+    callback: Callback,
+    /// The connection IORegisterForSystemPower returned, for acknowledging sleep
+    root_port: u32,
+}
+
+extern "C" fn power_off_initiated(
+    _center: *mut c_void,
+    observer: *mut c_void,
+    name: CFStringRef,
+    _object: *const c_void,
+    _user_info: CFDictionaryRef,
+) {
+    let watcher = unsafe { &*(observer as *const Watcher) };
+    let name = unsafe { CFString::wrap_under_get_rule(name) };
+    trace!("distributed notification {}", name);
+    (watcher.callback)(if name == RESTART_NOTIFICATION {
+        Event::Restart
+    } else {
+        Event::Shutdown
+    });
+}
+
+extern "C" fn system_power_changed(
+    refcon: *mut c_void,
+    _service: u32,
+    message_type: u32,
+    argument: *mut c_void,
+) {
+    let watcher = unsafe { &*(refcon as *const Watcher) };
+    trace!("system power message {message_type:#X}");
+    match message_type {
+        // Idle sleep is up to the assertions, not us
+        CAN_SYSTEM_SLEEP => unsafe {
+            IOAllowPowerChange(watcher.root_port, argument as isize);
+        },
+        SYSTEM_WILL_SLEEP => {
+            // Sleep can't be stopped anymore at this point, so don't hold it up
+            unsafe { IOAllowPowerChange(watcher.root_port, argument as isize) };
+            (watcher.callback)(Event::Sleep);
+        }
+        _ => {}
+    }
+}
+
+/// Call `callback` as soon as the user shuts down or restarts the Mac, or it starts going to sleep.
+/// It runs on its own thread.
+pub fn watch(callback: impl Fn(Event) + Send + 'static) {
+    let callback: Callback = Box::new(callback);
+    thread::spawn(move || {
+        // Lives as long as the run loop, which is forever
+        let watcher = Box::into_raw(Box::new(Watcher {
+            callback,
+            root_port: 0,
+        }));
+        unsafe {
+            let mut notify_port = std::ptr::null_mut();
+            let mut notifier = 0;
+            let root_port = IORegisterForSystemPower(
+                watcher as *mut c_void,
+                &mut notify_port,
+                system_power_changed,
+                &mut notifier,
+            );
+            trace!("IORegisterForSystemPower() = {root_port}");
+            if root_port != 0 {
+                (*watcher).root_port = root_port;
+                let source = IONotificationPortGetRunLoopSource(notify_port);
+                let source = CFRunLoopSource::wrap_under_get_rule(source);
+                CFRunLoop::get_current().add_source(&source, kCFRunLoopDefaultMode);
+            }
+
+            let center = CFNotificationCenterGetDistributedCenter();
+            for name in [SHUTDOWN_NOTIFICATION, RESTART_NOTIFICATION] {
+                let name = CFString::new(name);
+                CFNotificationCenterAddObserver(
+                    center,
+                    watcher as *const c_void,
+                    power_off_initiated,
+                    name.as_concrete_TypeRef(),
+                    std::ptr::null(),
+                    DELIVER_IMMEDIATELY,
+                );
+            }
+        }
+        CFRunLoop::run_current();
+    });
+}
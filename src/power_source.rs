@@ -0,0 +1,179 @@
+use core_foundation::array::{CFArray, CFArrayRef};
+use core_foundation::base::{CFRelease, CFType, CFTypeRef, TCFType};
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::number::CFNumber;
+use core_foundation::runloop::{
+    kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopSource, CFRunLoopSourceRef,
+};
+use core_foundation::string::{CFString, CFStringRef};
+use std::os::raw::c_void;
+use std::sync::Mutex;
+use std::thread;
+
+type IOPowerSourceCallbackType = extern "C" fn(context: *mut c_void);
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOPSCopyPowerSourcesInfo() -> CFTypeRef;
+    fn IOPSGetProvidingPowerSourceType(snapshot: CFTypeRef) -> CFStringRef;
+    fn IOPSCopyPowerSourcesList(snapshot: CFTypeRef) -> CFArrayRef;
+    fn IOPSGetPowerSourceDescription(snapshot: CFTypeRef, source: CFTypeRef) -> CFDictionaryRef;
+    fn IOPSGetBatteryWarningLevel() -> u32;
+    fn IOPSNotificationCreateRunLoopSource(
+        callback: IOPowerSourceCallbackType,
+        context: *mut c_void,
+    ) -> CFRunLoopSourceRef;
+}
+
+/// Whether the Mac is currently running on AC power
+pub fn on_ac_power() -> bool {
+    let snapshot = unsafe { IOPSCopyPowerSourcesInfo() };
+    if snapshot.is_null() {
+        // No power source info at all, which means a desktop on mains power
+        return true;
+    }
+
+    let source_type = unsafe { IOPSGetProvidingPowerSourceType(snapshot) };
+    let on_ac = !source_type.is_null()
+        && unsafe { CFString::wrap_under_get_rule(source_type) } == "AC Power";
+    trace!(
+        "IOPSGetProvidingPowerSourceType() = {:?}, on AC: {}",
+        source_type,
+        on_ac
+    );
+
+    unsafe { CFRelease(snapshot) };
+    on_ac
+}
+
+fn number(description: &CFDictionary, key: &'static str) -> Option<i64> {
+    let key = CFString::from_static_string(key);
+    let value = description.find(key.as_CFTypeRef())?;
+    unsafe { CFType::wrap_under_get_rule(*value) }
+        .downcast::<CFNumber>()?
+        .to_i64()
+}
+
+/// Read something from the internal battery's description. None on Macs without one.
+fn internal_battery<T>(read: impl Fn(&CFDictionary) -> Option<T>) -> Option<T> {
+    let snapshot = unsafe { IOPSCopyPowerSourcesInfo() };
+    if snapshot.is_null() {
+        return None;
+    }
+    let snapshot = unsafe { CFType::wrap_under_create_rule(snapshot) };
+    let sources = unsafe { IOPSCopyPowerSourcesList(snapshot.as_CFTypeRef()) };
+    if sources.is_null() {
+        return None;
+    }
+    let sources: CFArray = unsafe { CFArray::wrap_under_create_rule(sources) };
+    sources.iter().find_map(|source| {
+        let description =
+            unsafe { IOPSGetPowerSourceDescription(snapshot.as_CFTypeRef(), *source) };
+        if description.is_null() {
+            return None;
+        }
+        let description: CFDictionary = unsafe { CFDictionary::wrap_under_get_rule(description) };
+        let kind = description.find(CFString::from_static_string("Type").as_CFTypeRef())?;
+        if unsafe { CFString::wrap_under_get_rule(*kind as CFStringRef) } != "InternalBattery" {
+            return None;
+        }
+        read(&description)
+    })
+}
+
+/// The internal battery's charge in percent. None on Macs without one.
+pub fn battery_charge() -> Option<i64> {
+    let charge = internal_battery(|description| {
+        let current = number(description, "Current Capacity")?;
+        let max = number(description, "Max Capacity").filter(|max| *max > 0)?;
+        Some(current * 100 / max)
+    });
+    trace!("battery charge: {charge:?}");
+    charge
+}
+
+/// How long the battery should last at the recent discharge rate, in minutes. None while
+/// macOS is still working that out (right after unplugging), on AC, or without a battery.
+pub fn time_to_empty() -> Option<i64> {
+    let minutes = internal_battery(|description| number(description, "Time to Empty"));
+    trace!("battery time to empty: {minutes:?}");
+    minutes.filter(|minutes| *minutes > 0)
+}
+
+/// How close the battery is to running out, going by the warnings macOS shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryWarning {
+    None,
+    /// kIOPSLowBatteryWarningEarly, the first low battery warning
+    Early,
+    /// kIOPSLowBatteryWarningFinal, shortly before macOS puts the Mac to sleep
+    Final,
+}
+
+impl BatteryWarning {
+    /// What --on-low-battery hooks get in CAFFEINATE2_BATTERY_WARNING
+    pub fn name(self) -> &'static str {
+        match self {
+            BatteryWarning::None => "none",
+            BatteryWarning::Early => "early",
+            BatteryWarning::Final => "final",
+        }
+    }
+}
+
+/// The current low battery warning level
+pub fn battery_warning() -> BatteryWarning {
+    let level = unsafe { IOPSGetBatteryWarningLevel() };
+    trace!("IOPSGetBatteryWarningLevel() = {level}");
+    match level {
+        2 => BatteryWarning::Early,
+        3 => BatteryWarning::Final,
+        _ => BatteryWarning::None,
+    }
+}
+
+type Callback = Box<dyn Fn() + Send>;
+
+extern "C" fn power_source_changed(context: *mut c_void) {
+    let callback = unsafe { &*(context as *const Callback) };
+    callback();
+}
+
+/// Call `callback` with the new AC state whenever the power source changes.
+/// It runs on its own thread, and can be called repeatedly with the same state
+/// (battery level changes count as power source changes too).
+pub fn watch(callback: impl Fn(bool) + Send + 'static) {
+    watch_changes(move || callback(on_ac_power()));
+}
+
+/// Call `callback` whenever the low battery warning level changes, starting from `warning`.
+/// It runs on its own thread.
+pub fn watch_battery_warning(
+    warning: BatteryWarning,
+    callback: impl Fn(BatteryWarning) + Send + 'static,
+) {
+    let last = Mutex::new(warning);
+    // Battery level changes count as power source changes, so each one is a chance to check
+    watch_changes(move || {
+        let warning = battery_warning();
+        let mut last = last.lock().unwrap();
+        if warning != *last {
+            *last = warning;
+            callback(warning);
+        }
+    });
+}
+
+fn watch_changes(callback: impl Fn() + Send + 'static) {
+    let callback: Callback = Box::new(callback);
+    thread::spawn(move || {
+        // Lives as long as the run loop, which is forever
+        let context = Box::into_raw(Box::new(callback)) as *mut c_void;
+        unsafe {
+            let source = IOPSNotificationCreateRunLoopSource(power_source_changed, context);
+            let source = CFRunLoopSource::wrap_under_create_rule(source);
+            CFRunLoop::get_current().add_source(&source, kCFRunLoopDefaultMode);
+        }
+        CFRunLoop::run_current();
+    });
+}
@@ -0,0 +1,63 @@
+use crate::assertions::Assertions;
+use nix::sys::event;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for launchd to start powerd again
+const RESTART_WAIT: Duration = Duration::from_secs(30);
+
+fn powerd_pid() -> Option<i32> {
+    let output = process::Command::new("/usr/bin/pgrep")
+        .args(["-x", "powerd"])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Block until a process exits
+fn wait_for_exit(pid: i32) -> nix::Result<()> {
+    let kq = event::Kqueue::new()?;
+    let kev = event::KEvent::new(
+        pid as usize,
+        event::EventFilter::EVFILT_PROC,
+        event::EventFlag::EV_ADD | event::EventFlag::EV_ENABLE | event::EventFlag::EV_ONESHOT,
+        event::FilterFlag::NOTE_EXIT,
+        0,
+        0,
+    );
+    let mut eventlist = [kev];
+    kq.kevent(&[kev], &mut eventlist, None)?;
+    trace!("powerd ({pid}) exited: {:?}", eventlist[0]);
+    Ok(())
+}
+
+/// Assertions belong to powerd, so they're gone when it restarts. Watch for that and
+/// create them again.
+pub fn watch(assertions: Assertions) {
+    thread::spawn(move || loop {
+        let Some(pid) = powerd_pid() else {
+            trace!("couldn't find powerd, not watching for restarts");
+            return;
+        };
+        if let Err(err) = wait_for_exit(pid) {
+            trace!("couldn't watch powerd: {err}");
+            return;
+        }
+
+        let mut waited = Duration::ZERO;
+        while powerd_pid().is_none() && waited < RESTART_WAIT {
+            thread::sleep(Duration::from_millis(500));
+            waited += Duration::from_millis(500);
+        }
+        // If it still isn't back, this fails and is reported like any other transient failure
+        let recreated = assertions.recreate();
+        eprintln!("powerd restarted, re-created {recreated} assertion(s).");
+    });
+}
@@ -0,0 +1,113 @@
+//! Checks before a long session for things that would foreseeably cut it short, like a
+//! scheduled shutdown (see --strict-preflight).
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+/// Scheduled events that end a session
+pub const ENDING_EVENTS: [&str; 3] = ["shutdown", "restart", "sleep"];
+
+/// A power event from `pmset -g sched`
+#[derive(Debug, PartialEq)]
+pub struct ScheduledEvent {
+    /// Like "shutdown" or "wake"
+    pub kind: String,
+    /// The next time it happens
+    pub at: NaiveDateTime,
+    pub repeating: bool,
+}
+
+/// The events of these `kinds` that `pmset -g sched` lists, like
+/// ` [0]  shutdown at 10/17/2026 02:00:00 by 'pmset'` or, under "Repeating power events:",
+/// `  shutdown at 2:00AM every day`. Repeating ones are taken to be daily, so they're never missed.
+pub fn scheduled_events(output: &str, now: NaiveDateTime, kinds: &[&str]) -> Vec<ScheduledEvent> {
+    let mut repeating = false;
+    let mut events = Vec::new();
+    for line in output.lines() {
+        if line.starts_with("Repeating") {
+            repeating = true;
+            continue;
+        }
+        let line = line.trim();
+        // Drop the " [0]" index of one-off events
+        let line = match line.strip_prefix('[') {
+            Some(rest) => rest.split_once(']').map_or("", |(_, rest)| rest.trim()),
+            None => line,
+        };
+        let Some((kind, rest)) = line.split_once(" at ") else {
+            continue;
+        };
+        if !kinds.contains(&kind) {
+            continue;
+        }
+        let at = if repeating {
+            let Some(time) = rest
+                .split_whitespace()
+                .next()
+                .and_then(|time| NaiveTime::parse_from_str(time, "%I:%M%p").ok())
+            else {
+                continue;
+            };
+            let today = now.date().and_time(time);
+            if today > now {
+                today
+            } else {
+                today + Duration::days(1)
+            }
+        } else {
+            let mut words = rest.split_whitespace();
+            let (Some(date), Some(time)) = (words.next(), words.next()) else {
+                continue;
+            };
+            let date = NaiveDate::parse_from_str(date, "%m/%d/%Y");
+            let time = NaiveTime::parse_from_str(time, "%H:%M:%S");
+            let (Ok(date), Ok(time)) = (date, time) else {
+                continue;
+            };
+            date.and_time(time)
+        };
+        events.push(ScheduledEvent {
+            kind: kind.to_string(),
+            at,
+            repeating,
+        });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_scheduled_events() {
+        let output = "Scheduled power events:\n [0]  wake at 10/17/2026 06:00:00 by 'com.apple.alarm'\n [1]  shutdown at 10/17/2026 02:00:00 by 'pmset'\nRepeating power events:\n  restart at 1:30AM every day\n  wakepoweron at 7:00AM weekdays only\n";
+        let now = NaiveDate::from_ymd_opt(2026, 10, 16)
+            .unwrap()
+            .and_hms_opt(22, 0, 0)
+            .unwrap();
+        let events = super::scheduled_events(output, now, &super::ENDING_EVENTS);
+        assert_eq!(
+            events,
+            vec![
+                super::ScheduledEvent {
+                    kind: String::from("shutdown"),
+                    at: NaiveDate::from_ymd_opt(2026, 10, 17)
+                        .unwrap()
+                        .and_hms_opt(2, 0, 0)
+                        .unwrap(),
+                    repeating: false,
+                },
+                super::ScheduledEvent {
+                    kind: String::from("restart"),
+                    at: NaiveDate::from_ymd_opt(2026, 10, 17)
+                        .unwrap()
+                        .and_hms_opt(1, 30, 0)
+                        .unwrap(),
+                    repeating: true,
+                },
+            ]
+        );
+        assert_eq!(super::scheduled_events(output, now, &["wake"]).len(), 1);
+        assert!(super::scheduled_events("No scheduled events.\n", now, &["wake"]).is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::low_power;
+use crate::power_source;
+use caffeinate2::duration_parser::format_short_duration;
+use caffeinate2::i18n::format_duration;
+use caffeinate2::preflight;
+use std::path::Path;
+
+/// Sessions at least this long are checked before they start
+pub const MIN_DURATION: chrono::Duration = chrono::Duration::hours(1);
+
+/// Below this charge, a long session on battery probably won't make it
+const MIN_BATTERY_CHARGE: i64 = 50;
+
+/// The least free space left for --log-output, in bytes
+const MIN_FREE_SPACE: u64 = 1_000_000_000;
+
+/// Free space on the volume `path` is (or would be) on, in bytes
+fn free_space(path: &Path) -> Option<u64> {
+    let path = std::path::absolute(path).ok()?;
+    // The file itself might not exist yet
+    let directory = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let stats = nix::sys::statvfs::statvfs(directory).ok()?;
+    Some(stats.blocks_available() as u64 * stats.fragment_size() as u64)
+}
+
+/// What's likely to get in the way of a session lasting `duration` (None if that's not known).
+/// `log_output` is where the command's output is written, if anywhere.
+pub fn problems(duration: Option<chrono::Duration>, log_output: Option<&Path>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if let Some(path) = log_output {
+        if let Some(free) = free_space(path).filter(|free| *free < MIN_FREE_SPACE) {
+            problems.push(format!(
+                "Only {} MB is free for {}.",
+                free / 1_000_000,
+                path.display()
+            ));
+        }
+    }
+
+    let Some(duration) = duration else {
+        return problems;
+    };
+    let length = format_duration(duration);
+
+    if !power_source::on_ac_power() {
+        // Going by the recent discharge rate if macOS has worked it out, or the charge if not
+        match power_source::time_to_empty() {
+            Some(minutes) if chrono::Duration::minutes(minutes) < duration => {
+                problems.push(format!(
+                    "The battery will likely run out in about {}, but the session is {length}. Plug it in, or use --system-on-ac to only prevent sleep while plugged in.",
+                    format_short_duration(chrono::Duration::minutes(minutes))
+                ));
+            }
+            Some(_) => {}
+            None => {
+                if let Some(charge) =
+                    power_source::battery_charge().filter(|charge| *charge < MIN_BATTERY_CHARGE)
+                {
+                    problems.push(format!(
+                        "The Mac is on battery with {charge}% left, which might not last {length}. Plug it in to be sure."
+                    ));
+                }
+            }
+        }
+    }
+
+    let now = chrono::Local::now().naive_local();
+    let ends = now + duration;
+    if let Ok(schedule) = low_power::pmset(&["-g", "sched"]) {
+        for event in preflight::scheduled_events(&schedule, now, &preflight::ENDING_EVENTS) {
+            if event.at > now && event.at < ends {
+                let repeating = if event.repeating { " (repeating)" } else { "" };
+                problems.push(format!(
+                    "A {}{repeating} is scheduled for {}, before the session would end. See `pmset -g sched`.",
+                    event.kind,
+                    event.at.format("%Y-%m-%d %H:%M")
+                ));
+            }
+        }
+    }
+    problems
+}
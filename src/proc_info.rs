@@ -0,0 +1,275 @@
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::raw::c_void;
+use std::time::Duration;
+
+/// RUSAGE_INFO_V2 from sys/resource.h
+const RUSAGE_INFO_V2: i32 = 2;
+
+/// struct rusage_info_v2 from sys/resource.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RusageInfo {
+    pub uuid: [u8; 16],
+    pub user_time: u64,
+    pub system_time: u64,
+    pub pkg_idle_wkups: u64,
+    pub interrupt_wkups: u64,
+    pub pageins: u64,
+    pub wired_size: u64,
+    pub resident_size: u64,
+    pub phys_footprint: u64,
+    pub proc_start_abstime: u64,
+    pub proc_exit_abstime: u64,
+    pub child_user_time: u64,
+    pub child_system_time: u64,
+    pub child_pkg_idle_wkups: u64,
+    pub child_interrupt_wkups: u64,
+    pub child_pageins: u64,
+    pub child_elapsed_abstime: u64,
+    pub diskio_bytesread: u64,
+    pub diskio_byteswritten: u64,
+}
+
+/// struct timeval from sys/_types/_timeval.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Timeval {
+    tv_sec: i64,
+    tv_usec: i32,
+}
+
+impl Timeval {
+    fn duration(&self) -> Duration {
+        Duration::from_secs(self.tv_sec as u64) + Duration::from_micros(self.tv_usec as u64)
+    }
+}
+
+/// struct rusage from sys/resource.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Rusage {
+    ru_utime: Timeval,
+    ru_stime: Timeval,
+    /// In bytes on macOS (kilobytes on Linux)
+    ru_maxrss: i64,
+    ru_ixrss: i64,
+    ru_idrss: i64,
+    ru_isrss: i64,
+    ru_minflt: i64,
+    ru_majflt: i64,
+    ru_nswap: i64,
+    ru_inblock: i64,
+    ru_oublock: i64,
+    ru_msgsnd: i64,
+    ru_msgrcv: i64,
+    ru_nsignals: i64,
+    ru_nvcsw: i64,
+    ru_nivcsw: i64,
+}
+
+/// struct proc_bsdinfo from sys/proc_info.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BsdInfo {
+    pub flags: u32,
+    pub status: u32,
+    pub xstatus: u32,
+    pub pid: u32,
+    pub ppid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub ruid: u32,
+    pub rgid: u32,
+    pub svuid: u32,
+    pub svgid: u32,
+    rfu_1: u32,
+    pub comm: [u8; 16],
+    pub name: [u8; 32],
+    pub nfiles: u32,
+    pub pgid: u32,
+    pub pjobc: u32,
+    pub e_tdev: u32,
+    pub e_tpgid: u32,
+    pub nice: i32,
+    /// When it started, as a Unix timestamp
+    pub start_tvsec: u64,
+    pub start_tvusec: u64,
+}
+
+/// PROC_PIDTBSDINFO from sys/proc_info.h
+const PROC_PIDTBSDINFO: i32 = 3;
+
+/// CTL_KERN and KERN_PROCARGS2 from sys/sysctl.h
+const CTL_KERN: i32 = 1;
+const KERN_PROCARGS2: i32 = 49;
+
+extern "C" {
+    // In libproc, part of libSystem
+    fn proc_pid_rusage(pid: i32, flavor: i32, buffer: *mut RusageInfo) -> i32;
+    fn proc_pidinfo(pid: i32, flavor: i32, arg: u64, buffer: *mut c_void, size: i32) -> i32;
+    fn proc_listallpids(buffer: *mut c_void, size: i32) -> i32;
+    fn proc_listchildpids(ppid: i32, buffer: *mut c_void, size: i32) -> i32;
+    fn wait4(pid: i32, status: *mut i32, options: i32, rusage: *mut Rusage) -> i32;
+    fn sysctl(
+        name: *mut i32,
+        namelen: u32,
+        oldp: *mut c_void,
+        oldlenp: *mut usize,
+        newp: *mut c_void,
+        newlen: usize,
+    ) -> i32;
+}
+
+/// What a finished child process used, as reported by wait4
+#[derive(Debug, Clone, Copy)]
+pub struct ChildUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// Maximum resident set size, in bytes
+    pub max_rss: u64,
+}
+
+/// Wait for a child process to exit, returning its raw wait status and what it used
+pub fn wait(pid: i32) -> io::Result<(i32, ChildUsage)> {
+    let mut status = 0;
+    let mut usage = MaybeUninit::<Rusage>::uninit();
+    loop {
+        let result = unsafe { wait4(pid, &mut status, 0, usage.as_mut_ptr()) };
+        trace!("wait4({pid}) = {result}");
+        if result == pid {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    let usage = unsafe { usage.assume_init() };
+    Ok((
+        status,
+        ChildUsage {
+            user_time: usage.ru_utime.duration(),
+            system_time: usage.ru_stime.duration(),
+            max_rss: usage.ru_maxrss.max(0) as u64,
+        },
+    ))
+}
+
+/// Resource usage of a process. Only works for our own processes unless we're root.
+pub fn rusage(pid: i32) -> Option<RusageInfo> {
+    let mut info = MaybeUninit::<RusageInfo>::uninit();
+    let result = unsafe { proc_pid_rusage(pid, RUSAGE_INFO_V2, info.as_mut_ptr()) };
+    trace!("proc_pid_rusage({pid}) = {result}");
+    (result == 0).then(|| unsafe { info.assume_init() })
+}
+
+/// Fill a buffer of PIDs with a libproc list function, which returns how many it wrote
+fn list_pids(list: impl Fn(*mut c_void, i32) -> i32) -> Vec<i32> {
+    // Room for more than there are, since processes can start in between
+    let mut pids = vec![0i32; 8192];
+    let size = (pids.len() * std::mem::size_of::<i32>()) as i32;
+    let count = list(pids.as_mut_ptr() as *mut c_void, size);
+    pids.truncate(count.max(0) as usize);
+    pids.retain(|&pid| pid > 0);
+    pids
+}
+
+/// Every running process
+pub fn all_pids() -> Vec<i32> {
+    let pids = list_pids(|buffer, size| unsafe { proc_listallpids(buffer, size) });
+    trace!("proc_listallpids() = {} pids", pids.len());
+    pids
+}
+
+/// A process's direct children
+pub fn children(pid: i32) -> Vec<i32> {
+    let pids = list_pids(|buffer, size| unsafe { proc_listchildpids(pid, buffer, size) });
+    trace!("proc_listchildpids({pid}) = {pids:?}");
+    pids
+}
+
+/// Who owns a process and when it started
+pub fn bsd_info(pid: i32) -> Option<BsdInfo> {
+    let mut info = MaybeUninit::<BsdInfo>::uninit();
+    let size = std::mem::size_of::<BsdInfo>() as i32;
+    let result = unsafe {
+        proc_pidinfo(
+            pid,
+            PROC_PIDTBSDINFO,
+            0,
+            info.as_mut_ptr() as *mut c_void,
+            size,
+        )
+    };
+    trace!("proc_pidinfo({pid}, PROC_PIDTBSDINFO) = {result}");
+    (result == size).then(|| unsafe { info.assume_init() })
+}
+
+/// A process's executable path and arguments (argv\[0\] included). Only works for our own
+/// processes unless we're root.
+pub fn args(pid: i32) -> Option<(String, Vec<String>)> {
+    let mut name = [CTL_KERN, KERN_PROCARGS2, pid];
+    let mut size = 0;
+    let mut read = |buffer: *mut c_void, size: &mut usize| unsafe {
+        sysctl(
+            name.as_mut_ptr(),
+            name.len() as u32,
+            buffer,
+            size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if read(std::ptr::null_mut(), &mut size) != 0 {
+        trace!(
+            "sysctl(KERN_PROCARGS2, {pid}): {}",
+            io::Error::last_os_error()
+        );
+        return None;
+    }
+    let mut buffer = vec![0u8; size];
+    if read(buffer.as_mut_ptr() as *mut c_void, &mut size) != 0 {
+        trace!(
+            "sysctl(KERN_PROCARGS2, {pid}): {}",
+            io::Error::last_os_error()
+        );
+        return None;
+    }
+    buffer.truncate(size);
+    parse_procargs(&buffer)
+}
+
+/// KERN_PROCARGS2 is argc, then the executable path, NUL padding, and the NUL-terminated
+/// arguments (followed by the environment, which is left alone)
+fn parse_procargs(buffer: &[u8]) -> Option<(String, Vec<String>)> {
+    let argc = i32::from_ne_bytes(buffer.get(..4)?.try_into().ok()?);
+    let rest = &buffer[4..];
+    let path_end = rest.iter().position(|&byte| byte == 0)?;
+    let path = String::from_utf8_lossy(&rest[..path_end]).into_owned();
+    let args_start = path_end + rest[path_end..].iter().position(|&byte| byte != 0)?;
+    // Arguments can be empty, so only the padding is skipped
+    let args: Vec<String> = rest[args_start..]
+        .split(|&byte| byte == 0)
+        .take(argc.max(0) as usize)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    (args.len() == argc as usize).then_some((path, args))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_procargs() {
+        let mut buffer = 2i32.to_ne_bytes().to_vec();
+        buffer.extend(b"/usr/bin/caffeinate\0\0\0\0caffeinate\0-t3600\0HOME=/Users/me\0");
+        assert_eq!(
+            super::parse_procargs(&buffer),
+            Some((
+                String::from("/usr/bin/caffeinate"),
+                vec![String::from("caffeinate"), String::from("-t3600")]
+            ))
+        );
+        assert_eq!(super::parse_procargs(&buffer[..30]), None);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::proc_info;
+use crate::session::Session;
+use caffeinate2::i18n::format_duration;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the waited-for process is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tools that work through big files front to back, so how far into the file they are says
+/// something about how far along they are. Others (like xcodebuild) only get their read rate.
+const FILE_TOOLS: [&str; 4] = ["rsync", "cp", "tar", "ditto"];
+
+/// A regular file a process has open for reading, from `lsof`
+#[derive(Debug, PartialEq)]
+struct OpenFile {
+    path: String,
+    offset: u64,
+}
+
+/// Parse `lsof -F fatn -o` output: a line per field, with the field's letter first
+fn parse_lsof(output: &str) -> Vec<OpenFile> {
+    let mut files = Vec::new();
+    let (mut readable, mut regular, mut offset) = (false, false, None);
+    for line in output.lines() {
+        let (field, value) = line.split_at(line.len().min(1));
+        match field {
+            // A new file descriptor starts
+            "f" => (readable, regular, offset) = (false, false, None),
+            "a" => readable = value.contains('r') || value.contains('u'),
+            "t" => regular = value == "REG",
+            // Offsets are "0t123" in decimal or "0x7b" in hex
+            "o" => {
+                offset = value
+                    .strip_prefix("0t")
+                    .and_then(|decimal| decimal.parse().ok())
+                    .or_else(|| {
+                        value
+                            .strip_prefix("0x")
+                            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+                    })
+            }
+            "n" => {
+                if let (true, true, Some(offset)) = (readable, regular, offset) {
+                    files.push(OpenFile {
+                        path: value.to_string(),
+                        offset,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    files
+}
+
+fn command_name(pid: i32) -> Option<String> {
+    let output = process::Command::new("/bin/ps")
+        .args(["-o", "comm=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let name = path.rsplit('/').next()?.to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// The biggest regular file the process is reading, with how far into it it is and its size
+fn current_file(pid: i32) -> Option<(String, u64, u64)> {
+    let output = process::Command::new("/usr/sbin/lsof")
+        .args(["-w", "-o", "-F", "fatn", "-p", &pid.to_string()])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    parse_lsof(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .filter_map(|file| {
+            let size = std::fs::metadata(&file.path).ok()?.len();
+            Some((file.path, file.offset, size))
+        })
+        .filter(|(_, offset, size)| *size > 0 && offset <= size)
+        .max_by_key(|(_, _, size)| *size)
+}
+
+fn format_rate(bytes_per_second: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut rate = bytes_per_second;
+    let mut unit = 0;
+    while rate >= 1000.0 && unit < UNITS.len() - 1 {
+        rate /= 1000.0;
+        unit += 1;
+    }
+    format!("{rate:.0} {}/s", UNITS[unit])
+}
+
+/// One progress line, like "rsync: movie.mov 45%, about 3 minutes left (reading 120 MB/s)"
+fn describe(name: &str, file: Option<(String, u64, u64)>, read_rate: Option<f64>) -> String {
+    let mut description = name.to_string();
+    if let Some((path, offset, size)) = &file {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        description += &format!(": {file_name} {}%", offset * 100 / size);
+        if let Some(rate) = read_rate.filter(|rate| *rate > 0.0) {
+            let remaining = ((size - offset) as f64 / rate) as i64;
+            description += &format!(
+                ", about {} left",
+                format_duration(chrono::Duration::seconds(remaining))
+            );
+        }
+    }
+    match read_rate {
+        Some(rate) => format!("{description} (reading {})", format_rate(rate)),
+        None => description,
+    }
+}
+
+/// Sample a waited-for process and keep a progress estimate in our session file for `status`.
+/// Only tools known to work through files get a percentage; everything else gets its read rate.
+pub fn watch(target: i32, session_pid: i32, verbose: bool) {
+    thread::spawn(move || {
+        let Some(name) = command_name(target) else {
+            return;
+        };
+        let known_tool = FILE_TOOLS.contains(&name.as_str());
+        let mut last_sample: Option<(Instant, u64)> = None;
+        loop {
+            let now = Instant::now();
+            // Needs root for other users' processes, so it's fine for it to be missing
+            let bytes_read = proc_info::rusage(target).map(|usage| usage.diskio_bytesread);
+            let read_rate = match (last_sample, bytes_read) {
+                (Some((then, before)), Some(bytes_read)) => {
+                    Some(bytes_read.saturating_sub(before) as f64 / (now - then).as_secs_f64())
+                }
+                _ => None,
+            };
+            last_sample = bytes_read.map(|bytes_read| (now, bytes_read));
+
+            let file = known_tool.then(|| current_file(target)).flatten();
+            let progress = describe(&name, file, read_rate);
+            if verbose {
+                println!("Progress: {progress}");
+            }
+            if let Err(err) = Session::set_progress(session_pid, &progress) {
+                trace!("couldn't update progress: {err}");
+            }
+            thread::sleep(SAMPLE_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenFile;
+
+    #[test]
+    fn test_parse_lsof() {
+        let output = "p123\nfcwd\nar\ntDIR\no0t0\nn/Users/me\nf3\nar\ntREG\no0t1048576\nn/Volumes/Src/movie.mov\nf4\naw\ntREG\no0x10\nn/Volumes/Dst/movie.mov\n";
+        assert_eq!(
+            super::parse_lsof(output),
+            vec![OpenFile {
+                path: String::from("/Volumes/Src/movie.mov"),
+                offset: 1048576
+            }]
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        let file = Some((String::from("/Volumes/Src/movie.mov"), 250, 1000));
+        assert_eq!(
+            super::describe("rsync", file, Some(50.0)),
+            "rsync: movie.mov 25%, about 15 seconds left (reading 50 B/s)"
+        );
+        assert_eq!(super::describe("make", None, None), "make");
+    }
+}
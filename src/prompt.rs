@@ -0,0 +1,51 @@
+use crate::session::{Selector, Session};
+use caffeinate2::duration_parser::format_short_duration;
+use caffeinate2::prompt_format;
+use std::process;
+
+/// What --format uses unless told otherwise
+pub const DEFAULT_FORMAT: &str = "{icon} {remaining}";
+
+/// Print a prompt segment for the running sessions, or nothing if there aren't any.
+/// Placeholders: {icon}, {remaining} (empty if a session has no timeout), {types}, {count} and {names}.
+pub fn print(selector: &Selector, format: &str) {
+    let sessions = Session::select(selector);
+    let now = chrono::Local::now().timestamp();
+    // Awake until the last one ends
+    let remaining = sessions
+        .iter()
+        .map(|session| session.until.filter(|_| session.command.is_none()))
+        .collect::<Option<Vec<i64>>>()
+        .and_then(|until| until.into_iter().max())
+        .map(|until| format_short_duration(chrono::Duration::seconds((until - now).max(0))));
+    let value = |name: &str| {
+        Some(match name {
+            "icon" => String::from("☕️"),
+            "remaining" => remaining.clone().unwrap_or_default(),
+            "types" => {
+                let mut types: Vec<&str> = Vec::new();
+                for sleep_type in sessions.iter().flat_map(|session| &session.types) {
+                    if !types.contains(&sleep_type.as_str()) {
+                        types.push(sleep_type);
+                    }
+                }
+                types.join(", ")
+            }
+            "count" => sessions.len().to_string(),
+            "names" => sessions
+                .iter()
+                .filter_map(|session| session.name.as_deref())
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => return None,
+        })
+    };
+    // Checked even with nothing running, so a typo shows up right away
+    let line = prompt_format::render(format, value).unwrap_or_else(|err| {
+        eprintln!("Error: Invalid --format: {err}");
+        process::exit(1);
+    });
+    if !sessions.is_empty() {
+        println!("{}", line.trim_end());
+    }
+}
@@ -0,0 +1,60 @@
+//! Templates for `caffeinate2 prompt`, like "{icon} {remaining}".
+
+/// Fill in the `{placeholder}`s in a template with what `value` gives for them.
+/// `{{` and `}}` stand for literal braces.
+pub fn render(template: &str, value: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut output = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("Unclosed placeholder {{{name}")),
+                    }
+                }
+                match value(&name) {
+                    Some(value) => output += &value,
+                    None => return Err(format!("Unknown placeholder {{{name}}}")),
+                }
+            }
+            '}' => return Err(String::from("Unmatched } (write }} for a literal one)")),
+            c => output.push(c),
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_render() {
+        let value = |name: &str| match name {
+            "icon" => Some(String::from("☕️")),
+            "remaining" => Some(String::from("1h 5m")),
+            _ => None,
+        };
+        assert_eq!(
+            super::render("{icon} {remaining}", value),
+            Ok(String::from("☕️ 1h 5m"))
+        );
+        assert_eq!(
+            super::render("{{awake}} {remaining}", value),
+            Ok(String::from("{awake} 1h 5m"))
+        );
+        assert!(super::render("{nope}", value).is_err());
+        assert!(super::render("oops}", value).is_err());
+        assert!(super::render("{icon", value).is_err());
+    }
+}
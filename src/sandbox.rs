@@ -0,0 +1,52 @@
+//! Sandbox profiles for wrapped commands (see --sandbox), in the language `sandbox-exec` takes.
+//! The presets allow everything except what they're named after, so scripts keep working.
+
+use std::fs;
+
+/// The presets --sandbox accepts instead of a profile file
+pub const PRESETS: [&str; 3] = ["no-network", "read-only-home", "strict"];
+
+/// No connections over IP. Local sockets (like the ones system services listen on) still work.
+const NO_NETWORK: &str = "(deny network* (remote ip))\n(deny network* (local ip))\n";
+
+/// Nothing in the home folder can be changed. HOME is filled in with `sandbox-exec -D`.
+const READ_ONLY_HOME: &str = "(deny file-write* (subpath (param \"HOME\")))\n";
+
+/// The profile for a preset, or None if `name` isn't one
+pub fn preset(name: &str) -> Option<String> {
+    let rules = match name {
+        "no-network" => NO_NETWORK.to_string(),
+        "read-only-home" => READ_ONLY_HOME.to_string(),
+        "strict" => format!("{NO_NETWORK}{READ_ONLY_HOME}"),
+        _ => return None,
+    };
+    Some(format!("(version 1)\n(allow default)\n{rules}"))
+}
+
+/// The profile --sandbox asks for: a preset, or otherwise the contents of a profile file
+pub fn profile(spec: &str) -> Result<String, String> {
+    if let Some(profile) = preset(spec) {
+        return Ok(profile);
+    }
+    fs::read_to_string(spec).map_err(|err| {
+        format!(
+            "\"{spec}\" isn't a sandbox preset ({}) or a profile file ({err})",
+            PRESETS.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_profile() {
+        let strict = super::profile("strict").unwrap();
+        assert!(strict.starts_with("(version 1)\n(allow default)\n"));
+        assert!(strict.contains("(deny network* (remote ip))"));
+        assert!(strict.contains("(subpath (param \"HOME\"))"));
+        assert!(!super::profile("no-network").unwrap().contains("file-write"));
+
+        let err = super::profile("no-netwrok").unwrap_err();
+        assert!(err.contains("no-network, read-only-home, strict"));
+    }
+}
@@ -0,0 +1,103 @@
+use core_foundation::base::{CFRelease, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::{CFDictionaryGetValueIfPresent, CFDictionaryRef};
+use core_foundation::runloop::CFRunLoop;
+use core_foundation::string::{CFString, CFStringRef};
+use std::os::raw::c_void;
+use std::thread;
+
+/// Posted by loginwindow when the screen is locked and unlocked
+const LOCKED_NOTIFICATION: &str = "com.apple.screenIsLocked";
+const UNLOCKED_NOTIFICATION: &str = "com.apple.screenIsUnlocked";
+
+/// CFNotificationSuspensionBehaviorDeliverImmediately
+const DELIVER_IMMEDIATELY: isize = 4;
+
+type CFNotificationCallback = extern "C" fn(
+    center: *mut c_void,
+    observer: *mut c_void,
+    name: CFStringRef,
+    object: *const c_void,
+    user_info: CFDictionaryRef,
+);
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFNotificationCenterGetDistributedCenter() -> *mut c_void;
+    fn CFNotificationCenterAddObserver(
+        center: *mut c_void,
+        observer: *const c_void,
+        callback: CFNotificationCallback,
+        name: CFStringRef,
+        object: *const c_void,
+        suspension_behavior: isize,
+    );
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGSessionCopyCurrentDictionary() -> CFDictionaryRef;
+}
+
+/// Whether the screen is locked right now. False if we aren't in a GUI login session
+/// (like over SSH), since there's no screen to lock then.
+pub fn is_locked() -> bool {
+    let session = unsafe { CGSessionCopyCurrentDictionary() };
+    if session.is_null() {
+        trace!("CGSessionCopyCurrentDictionary() = null");
+        return false;
+    }
+    let key = CFString::new("CGSSessionScreenIsLocked");
+    let mut value = std::ptr::null();
+    let present = unsafe {
+        CFDictionaryGetValueIfPresent(
+            session,
+            key.as_concrete_TypeRef() as *const c_void,
+            &mut value,
+        )
+    } != 0;
+    let locked = present && unsafe { CFBoolean::wrap_under_get_rule(value as _) }.into();
+    trace!("CGSSessionScreenIsLocked = {}", locked);
+    unsafe { CFRelease(session as _) };
+    locked
+}
+
+type Callback = Box<dyn Fn(bool) + Send>;
+
+extern "C" fn lock_changed(
+    _center: *mut c_void,
+    observer: *mut c_void,
+    name: CFStringRef,
+    _object: *const c_void,
+    _user_info: CFDictionaryRef,
+) {
+    let callback = unsafe { &*(observer as *const Callback) };
+    let name = unsafe { CFString::wrap_under_get_rule(name) };
+    trace!("distributed notification {}", name);
+    callback(name == LOCKED_NOTIFICATION);
+}
+
+/// Call `callback` with whether the screen is locked whenever it's locked or unlocked.
+/// It runs on its own thread.
+pub fn watch(callback: impl Fn(bool) + Send + 'static) {
+    let callback: Callback = Box::new(callback);
+    thread::spawn(move || {
+        // Lives as long as the run loop, which is forever
+        let observer = Box::into_raw(Box::new(callback)) as *const c_void;
+        unsafe {
+            let center = CFNotificationCenterGetDistributedCenter();
+            for name in [LOCKED_NOTIFICATION, UNLOCKED_NOTIFICATION] {
+                let name = CFString::new(name);
+                CFNotificationCenterAddObserver(
+                    center,
+                    observer,
+                    lock_changed,
+                    name.as_concrete_TypeRef(),
+                    std::ptr::null(),
+                    DELIVER_IMMEDIATELY,
+                );
+            }
+        }
+        CFRunLoop::run_current();
+    });
+}
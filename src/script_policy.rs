@@ -0,0 +1,117 @@
+use crate::assertions::Kind;
+use crate::idle;
+use crate::power_source;
+use caffeinate2::policy_script::{Decision, Inputs, PolicyScript};
+use chrono::{Datelike, Timelike};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = process::Command::new(program)
+        .args(args)
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The charge from `pmset -g batt`, like "-InternalBattery-0 (id=1234)<tab>85%; discharging"
+fn parse_battery(output: &str) -> Option<i64> {
+    output.lines().find_map(|line| {
+        let (charge, _) = line.split_once("%;")?;
+        charge.rsplit(char::is_whitespace).next()?.parse().ok()
+    })
+}
+
+/// The value from `lsappinfo info -only name`, like "LSDisplayName"="Safari"
+fn parse_app_name(output: &str) -> Option<String> {
+    let (_, name) = output.trim().split_once('=')?;
+    let name = name.trim_matches('"');
+    (!name.is_empty() && name != "NULL").then(|| name.to_string())
+}
+
+fn frontmost_app() -> Option<String> {
+    let front = command_output("/usr/bin/lsappinfo", &["front"])?;
+    let info = command_output(
+        "/usr/bin/lsappinfo",
+        &["info", "-only", "name", front.trim()],
+    )?;
+    parse_app_name(&info)
+}
+
+/// What the Mac is doing right now
+pub fn inputs() -> Inputs {
+    let now = chrono::Local::now();
+    Inputs {
+        battery: command_output("/usr/bin/pmset", &["-g", "batt"])
+            .as_deref()
+            .and_then(parse_battery),
+        on_ac: power_source::on_ac_power(),
+        idle_seconds: idle::idle_time().map_or(0, |idle| idle.as_secs() as i64),
+        frontmost_app: frontmost_app(),
+        hour: now.hour().into(),
+        minute: now.minute().into(),
+        weekday: now.weekday().number_from_monday().into(),
+    }
+}
+
+/// Whether an assertion of this kind should be held
+pub fn wants(decision: &Decision, kind: Kind) -> bool {
+    match decision {
+        Decision::All(hold) => *hold,
+        Decision::Only(names) => names.iter().any(|name| kind.is_called(name)),
+    }
+}
+
+pub fn describe(decision: &Decision) -> String {
+    match decision {
+        Decision::All(true) => String::from("keep everything awake"),
+        Decision::All(false) => String::from("allow sleep"),
+        Decision::Only(names) if names.is_empty() => String::from("allow sleep"),
+        Decision::Only(names) => format!("only prevent {} sleep", names.join(", ")),
+    }
+}
+
+/// Run the script now, reporting errors. None if it failed.
+pub fn evaluate(script: &PolicyScript) -> Option<Decision> {
+    let inputs = inputs();
+    trace!("policy script inputs: {inputs:?}");
+    script
+        .evaluate(&inputs)
+        .inspect_err(|err| eprintln!("Warning: The policy script failed: {err}"))
+        .ok()
+}
+
+/// Run the script every `interval`, calling `callback` with what it decides
+pub fn watch(
+    script: PolicyScript,
+    interval: Duration,
+    callback: impl Fn(Decision) + Send + 'static,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Some(decision) = evaluate(&script) {
+            callback(decision);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_inputs() {
+        let batt = "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t85%; discharging; 5:12 remaining present: true\n";
+        assert_eq!(super::parse_battery(batt), Some(85));
+        assert_eq!(super::parse_battery("Now drawing from 'AC Power'\n"), None);
+
+        assert_eq!(
+            super::parse_app_name("\"LSDisplayName\"=\"Safari\"\n"),
+            Some(String::from("Safari"))
+        );
+        assert_eq!(super::parse_app_name("\"LSDisplayName\"=NULL\n"), None);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::apply::SleepType;
+use crate::ctl;
+use crate::lockdown::Lockdown;
+use crate::session::{Selector, Session};
+use caffeinate2::duration_parser::try_parse_duration;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+/// One request, like `{"cmd": "stop", "name": "backup"}`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    /// Running sessions, like `ctl status`
+    Status {
+        #[serde(flatten)]
+        selector: Selector,
+    },
+    /// Like `ctl stop`
+    Stop {
+        #[serde(flatten)]
+        selector: Selector,
+    },
+    /// Like `ctl extend`
+    Extend {
+        duration: String,
+        #[serde(flatten)]
+        selector: Selector,
+    },
+    /// Start a background session, like `toggle` does when nothing is running
+    Start {
+        duration: Option<String>,
+        name: Option<String>,
+    },
+}
+
+/// A request plus an optional `id`, which is copied into the response so clients can match them up
+#[derive(Deserialize, Debug)]
+struct Envelope {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    request: Request,
+}
+
+fn handle(request: Request) -> Result<Value, String> {
+    match request {
+        Request::Status { selector } => Ok(json!({ "sessions": Session::select(&selector) })),
+        Request::Stop { selector } => {
+            let mut stopped = Vec::new();
+            let mut failed = Vec::new();
+            for session in Session::select(&selector) {
+                match ctl::send_stop(session.pid) {
+                    Ok(()) => stopped.push(session.pid),
+                    Err(err) => {
+                        failed.push(json!({ "pid": session.pid, "error": err.to_string() }))
+                    }
+                }
+            }
+            Ok(json!({ "stopped": stopped, "failed": failed }))
+        }
+        Request::Extend { duration, selector } => {
+            let until = chrono::Local::now().timestamp() + try_parse_duration(&duration)?;
+            let lockdown = Lockdown::load();
+            let mut extended = Vec::new();
+            let mut failed = Vec::new();
+            for session in Session::select(&selector) {
+                let allowed = lockdown.as_ref().map_or(Ok(()), |lockdown| {
+                    lockdown.check_duration((until - session.started) * 1000)
+                });
+                match allowed.and_then(|()| ctl::extend_session(&session, until)) {
+                    Ok(()) => extended.push(session.pid),
+                    Err(err) => failed.push(json!({ "pid": session.pid, "error": err })),
+                }
+            }
+            Ok(json!({ "extended": extended, "failed": failed, "until": until }))
+        }
+        Request::Start { duration, name } => {
+            let mut args = Vec::new();
+            if let Some(name) = name {
+                args.extend([String::from("--name"), name]);
+            }
+            let seconds = duration.as_deref().map(try_parse_duration).transpose()?;
+            ctl::check_lockdown(&[SleepType::System], seconds.map(|seconds| seconds * 1000))?;
+            if let Some(duration) = duration {
+                args.extend([String::from("-t"), duration]);
+            }
+            let pid = ctl::spawn_background(&args).map_err(|err| err.to_string())?;
+            Ok(json!({ "pid": pid }))
+        }
+    }
+}
+
+/// Answer requests from stdin, one JSON object per line, until it's closed.
+/// Every response is one line too, with `"ok"` and either the results or an `"error"`.
+pub fn serve_stdio() {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        trace!("serve request: {line}");
+
+        let response = match serde_json::from_str::<Envelope>(&line) {
+            Ok(envelope) => {
+                let mut response = match handle(envelope.request) {
+                    Ok(mut results) => {
+                        results["ok"] = Value::Bool(true);
+                        results
+                    }
+                    Err(err) => json!({ "ok": false, "error": err }),
+                };
+                if !envelope.id.is_null() {
+                    response["id"] = envelope.id;
+                }
+                response
+            }
+            Err(err) => json!({ "ok": false, "error": format!("Invalid request: {err}") }),
+        };
+
+        // The client is gone if this fails, so there's nobody left to answer
+        if writeln!(stdout, "{response}")
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
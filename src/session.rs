@@ -0,0 +1,464 @@
+use crate::proc_info;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::signal;
+use nix::unistd::{self, AccessFlags, Pid, Uid, User};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Shared between users (sudo sessions included), so it lives in /tmp like a lockfile would
+const SESSION_DIR: &str = "/tmp/caffeinate2";
+
+/// Format version of session files. Bump it when a field is added that older files need filled in.
+pub const VERSION: u32 = 2;
+
+/// Files from before the format was versioned
+fn unversioned() -> u32 {
+    1
+}
+
+/// A running caffeinate2 instance, as recorded in the session directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    #[serde(default = "unversioned")]
+    pub version: u32,
+    pub pid: i32,
+    /// Label given with --name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Unix timestamp of when the session started
+    pub started: i64,
+    /// Unix timestamp of when the timeout expires, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<i64>,
+    /// Sleep types being prevented
+    pub types: Vec<String>,
+    /// Wrapped command, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// PID being waited for, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waitfor: Option<i32>,
+    /// Policy file that started it, if it came from `apply`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_by: Option<String>,
+    /// How the waited-for PID is getting on, with --progress. It's kept in its own file, which
+    /// the sampling thread can rewrite without clobbering changes to this one (like `until`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<String>,
+    /// User it belongs to (the one behind sudo, for sudo sessions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// That user's UID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    /// File the wrapped command's output goes to, so `attach` can show it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Arguments it was started with, so `ctl adopt` can start it again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Metadata given with --tag, like team=ios, saying who it's for on a shared Mac
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Session {
+    fn path(pid: i32) -> PathBuf {
+        dir().join(format!("{pid}.json"))
+    }
+
+    /// Unix timestamp of when it ends at the latest. None if that's not known, like for a
+    /// command (which can take however long) or without a timeout.
+    pub fn ends(&self) -> Option<i64> {
+        self.until.filter(|_| self.command.is_none())
+    }
+
+    /// Its tags, like "team=ios, ticket=OPS-12", or None without any
+    pub fn describe_tags(&self) -> Option<String> {
+        if self.tags.is_empty() {
+            return None;
+        }
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        Some(tags.join(", "))
+    }
+
+    /// Where a detached session keeps its command's output
+    pub fn output_path(pid: i32) -> PathBuf {
+        dir().join(format!("{pid}.out"))
+    }
+
+    /// Record how the waited-for PID is getting on, for `status`
+    pub fn set_progress(pid: i32, progress: &str) -> io::Result<()> {
+        create_dir()?;
+        write_atomically(&dir().join(format!("{pid}.progress")), progress.as_bytes())
+    }
+
+    /// Fill in the progress from the file next to its session file, if there is one
+    fn with_progress(mut self, session_file: &Path) -> Session {
+        if let Ok(progress) = fs::read_to_string(session_file.with_extension("progress")) {
+            self.progress = Some(progress);
+        }
+        self
+    }
+
+    /// Record this session so `status` and `ctl` can find it
+    pub fn register(&self) -> io::Result<()> {
+        create_dir()?;
+        trace!(
+            "writing session file {}: {:?}",
+            Self::path(self.pid).display(),
+            self
+        );
+        write_atomically(&Self::path(self.pid), &serde_json::to_vec(self)?)
+    }
+
+    /// The recorded state of a live session
+    pub fn load(pid: i32) -> Option<Session> {
+        // It might have had to record itself in the fallback directory
+        let (path, contents) = dirs().find_map(|dir| {
+            let path = dir.join(format!("{pid}.json"));
+            fs::read(&path).ok().map(|contents| (path, contents))
+        })?;
+        serde_json::from_slice::<Session>(&contents)
+            .ok()
+            .filter(|session| is_genuine(&path, session) && is_caffeinate2(session.pid))
+            .map(|session| session.with_progress(&path))
+    }
+
+    pub fn unregister(pid: i32) {
+        for dir in dirs() {
+            let result = fs::remove_file(dir.join(format!("{pid}.json")));
+            trace!(
+                "removing session file for {pid} in {}: {result:?}",
+                dir.display()
+            );
+            // Only there for detached sessions and --progress
+            let _ = fs::remove_file(dir.join(format!("{pid}.out")));
+            let _ = fs::remove_file(dir.join(format!("{pid}.progress")));
+        }
+    }
+
+    /// Bring a session recorded by an older version up to date. Returns whether anything changed.
+    fn migrate(&mut self) -> bool {
+        if self.version >= VERSION {
+            return false;
+        }
+        // Version 1 only had the user name
+        if self.uid.is_none() {
+            self.uid = self
+                .user
+                .as_deref()
+                .and_then(|name| User::from_name(name).ok().flatten())
+                .map(|user| user.uid.as_raw());
+        }
+        trace!(
+            "migrated session {} from version {}",
+            self.pid,
+            self.version
+        );
+        self.version = VERSION;
+        true
+    }
+
+    /// All live sessions, oldest first. Entries left behind by dead processes are cleaned up,
+    /// and ones recorded by older versions are migrated.
+    pub fn list() -> Vec<Session> {
+        let mut sessions: Vec<Session> = Vec::new();
+        let entries = dirs().filter_map(|dir| fs::read_dir(dir).ok()).flatten();
+
+        for entry in entries.flatten() {
+            // Being written right now
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(contents) = fs::read(entry.path()) else {
+                continue;
+            };
+            let Ok(mut session) = serde_json::from_slice::<Session>(&contents) else {
+                remove_if_abandoned(&entry.path());
+                continue;
+            };
+            if sessions.iter().any(|other| other.pid == session.pid) {
+                continue;
+            }
+            let migrated = session.migrate();
+            if !is_genuine(&entry.path(), &session) {
+                trace!(
+                    "ignoring {}, which isn't its session's",
+                    entry.path().display()
+                );
+                continue;
+            }
+            // A PID that was reused after the session crashed doesn't count
+            if is_alive(session.pid) && is_caffeinate2(session.pid) {
+                if migrated {
+                    // Other users' sessions can't be rewritten, which is fine
+                    let result =
+                        write_atomically(&entry.path(), &serde_json::to_vec(&session).unwrap());
+                    trace!("rewriting {}: {result:?}", entry.path().display());
+                }
+                sessions.push(session.with_progress(&entry.path()));
+            } else {
+                trace!("removing stale session file {}", entry.path().display());
+                Self::unregister(session.pid);
+            }
+        }
+
+        sessions.sort_by_key(|session| session.started);
+        sessions
+    }
+
+    /// Live sessions picked out by a selector
+    pub fn select(selector: &Selector) -> Vec<Session> {
+        Self::list()
+            .into_iter()
+            .filter(|session| selector.matches(session))
+            .collect()
+    }
+
+    /// The one live session a selector picks out, for subcommands that need exactly one
+    pub fn select_one(selector: &Selector) -> Result<Session, String> {
+        let mut sessions = Self::select(selector);
+        match sessions.len() {
+            0 => Err(format!("No {} running.", selector.describe())),
+            1 => Ok(sessions.remove(0)),
+            count => Err(format!(
+                "{count} sessions match, pick one with --pid or --name."
+            )),
+        }
+    }
+}
+
+/// Replace a file so readers see either the old contents or the new ones, never a torn write
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    // Other users can write to the session directory, so the temporary file gets a name they
+    // can't guess, and is only ever created fresh, never through a symlink planted there first
+    let (temp, mut file) = loop {
+        let random = RandomState::new().build_hasher().finish();
+        let temp = path.with_file_name(format!(".{file_name}.{random:016x}.tmp"));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .custom_flags(OFlag::O_NOFOLLOW.bits())
+            .open(&temp)
+        {
+            Ok(file) => break (temp, file),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    };
+    let result = file
+        .write_all(contents)
+        .and_then(|()| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Whether a session file was written by its session. Anyone can leave files in the session
+/// directory, so it has to be named after the session's PID and belong to its user (or root, for
+/// sudo sessions) before the PID in it gets signaled.
+fn is_genuine(path: &Path, session: &Session) -> bool {
+    let named_after =
+        path.file_stem().and_then(|stem| stem.to_str()) == Some(session.pid.to_string().as_str());
+    let owner = fs::metadata(path).map(|metadata| metadata.uid());
+    named_after && owner.is_ok_and(|owner| owner == 0 || Some(owner) == session.uid)
+}
+
+/// Whether a process is running the same program as us
+fn is_caffeinate2(pid: i32) -> bool {
+    let command = |pid| proc_info::bsd_info(pid).map(|info| info.comm);
+    command(pid).is_some_and(|theirs| Some(theirs) == command(std::process::id() as i32))
+}
+
+/// Remove a session file that can't be read if its process is gone. Torn writes from before
+/// they were atomic, or ones left by a crash, would otherwise stay forever.
+fn remove_if_abandoned(path: &Path) {
+    let pid = path
+        .file_name()
+        .and_then(|name| name.to_str()?.strip_suffix(".json")?.parse().ok());
+    match pid {
+        Some(pid) if !is_alive(pid) => {
+            let result = fs::remove_file(path);
+            trace!(
+                "removing unreadable session file {}: {result:?}",
+                path.display()
+            );
+        }
+        Some(pid) => trace!("session file for live PID {pid} is unreadable"),
+        // Not a session file (like start history or a temporary file)
+        None => {}
+    }
+}
+
+/// Which sessions a subcommand applies to. Matches every session if empty.
+#[derive(clap::Args, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Selector {
+    /// Only the session with this PID
+    #[arg(long)]
+    pub pid: Option<i32>,
+
+    /// Only sessions started with this --name
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Only sessions started by you
+    #[arg(long)]
+    pub mine: bool,
+}
+
+impl Selector {
+    pub fn matches(&self, session: &Session) -> bool {
+        self.pid.is_none_or(|pid| session.pid == pid)
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|name| session.name.as_ref() == Some(name))
+            && (!self.mine || session.uid == Some(owner_uid()))
+    }
+
+    /// Human-readable description, for "no sessions found" errors
+    pub fn describe(&self) -> String {
+        let sessions = match (self.pid, &self.name) {
+            (Some(pid), Some(name)) => {
+                format!("caffeinate2 session named \"{name}\" with PID {pid}")
+            }
+            (Some(pid), None) => format!("caffeinate2 session with PID {pid}"),
+            (None, Some(name)) => format!("caffeinate2 session named \"{name}\""),
+            (None, None) => String::from("caffeinate2 sessions"),
+        };
+        if self.mine {
+            format!("{sessions} of yours")
+        } else {
+            sessions
+        }
+    }
+}
+
+/// Parse a --tag like "ticket=OPS-12"
+pub fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("\"{tag}\" isn't a tag like \"team=ios\"")),
+    }
+}
+
+/// The user a session started by us belongs to: whoever ran sudo, if it's a sudo session
+pub fn owner_uid() -> u32 {
+    std::env::var("SUDO_UID")
+        .ok()
+        .and_then(|uid| uid.parse().ok())
+        .unwrap_or_else(|| unistd::getuid().as_raw())
+}
+
+pub fn user_name(uid: u32) -> Option<String> {
+    User::from_uid(Uid::from_raw(uid))
+        .ok()
+        .flatten()
+        .map(|user| user.name)
+}
+
+/// Used when the session directory can't be written (like on a hardened Mac with a read-only
+/// /tmp), so sessions still work, but are only seen by the same user
+fn fallback_dir() -> PathBuf {
+    std::env::temp_dir().join("caffeinate2")
+}
+
+/// Every directory sessions might be recorded in
+fn dirs() -> impl Iterator<Item = PathBuf> {
+    let fallback = Some(fallback_dir()).filter(|fallback| fallback != Path::new(SESSION_DIR));
+    [PathBuf::from(SESSION_DIR)].into_iter().chain(fallback)
+}
+
+/// Where sessions are recorded: the session directory, or the fallback if it can't be written
+pub fn dir() -> PathBuf {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        // It's created on first use, so /tmp has to be writable for that
+        let writable = |dir: &str| unistd::access(dir, AccessFlags::W_OK).is_ok();
+        let usable = match fs::symlink_metadata(SESSION_DIR) {
+            Ok(metadata) => writable(SESSION_DIR) && is_trusted(&metadata),
+            Err(_) => writable("/tmp"),
+        };
+        if usable {
+            return PathBuf::from(SESSION_DIR);
+        }
+        let fallback = fallback_dir();
+        trace!("{SESSION_DIR} isn't usable, using {}", fallback.display());
+        fallback
+    })
+    .clone()
+}
+
+/// Whether the shared session directory can be trusted with our files. Anyone could have created
+/// it first, so it has to be a real directory that belongs to root or us, and if others can write
+/// to it, it has to be sticky like /tmp so they can't replace our files.
+fn is_trusted(metadata: &fs::Metadata) -> bool {
+    let owner = metadata.uid();
+    let mode = metadata.mode();
+    metadata.is_dir()
+        && (owner == 0 || owner == unistd::geteuid().as_raw())
+        && (mode & 0o022 == 0 || mode & 0o1000 != 0)
+}
+
+/// Create the session directory if it isn't there yet
+pub fn create_dir() -> io::Result<()> {
+    let dir = dir();
+    if dir != Path::new(SESSION_DIR) {
+        return fs::create_dir_all(dir);
+    }
+    if fs::create_dir(&dir).is_ok() {
+        // Let every user register sessions, like /tmp itself
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o1777))?;
+    }
+    // Someone else may have created it since dir() looked
+    if !is_trusted(&fs::symlink_metadata(&dir)?) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{SESSION_DIR} could be tampered with by another user"),
+        ));
+    }
+    Ok(())
+}
+
+pub fn is_alive(pid: i32) -> bool {
+    // EPERM means the process exists but belongs to someone else (e.g. a sudo session)
+    matches!(
+        signal::kill(Pid::from_raw(pid), None),
+        Ok(()) | Err(Errno::EPERM)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(
+            super::parse_tag("ticket=OPS-12"),
+            Ok((String::from("ticket"), String::from("OPS-12")))
+        );
+        assert_eq!(
+            super::parse_tag("note=a=b"),
+            Ok((String::from("note"), String::from("a=b")))
+        );
+        assert!(super::parse_tag("team").is_err());
+        assert!(super::parse_tag("=ios").is_err());
+    }
+}
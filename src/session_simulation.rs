@@ -0,0 +1,254 @@
+//! `--dry-run --simulate`: how a session would play out, stepped a minute at a time on a virtual
+//! clock. The timeout and --repeat rounds are known ahead of time; the battery is assumed to keep
+//! draining at its current rate (or to stay on AC), and condition monitors are assumed to never
+//! be met, since there's no telling when a host comes back or a URL changes.
+
+use chrono::TimeDelta;
+
+/// Roughly where macOS shows its low battery warnings, in percent. It really goes by the time
+/// left, which is about this at a typical drain.
+pub const EARLY_WARNING_CHARGE: f64 = 10.0;
+pub const FINAL_WARNING_CHARGE: f64 = 5.0;
+
+/// The battery at the start of the simulation
+#[derive(Debug, Clone, Copy)]
+pub struct Battery {
+    /// Charge in percent
+    pub charge: f64,
+    pub on_ac: bool,
+    /// Percent lost per hour while not on AC
+    pub drain_per_hour: f64,
+}
+
+/// What the session was started with, as far as the simulation's concerned
+#[derive(Debug, Clone)]
+pub struct Plan {
+    /// How long to simulate
+    pub span: TimeDelta,
+    /// --timeout, if any. Without it the session runs until it's stopped.
+    pub timeout: Option<TimeDelta>,
+    /// --repeat rounds (1 without it), with --break between them
+    pub rounds: u32,
+    pub break_duration: TimeDelta,
+    /// None on Macs without a battery
+    pub battery: Option<Battery>,
+    /// --system-on-ac, which only holds its assertion on AC
+    pub only_on_ac: bool,
+    /// What a condition monitor waits for (like "until example.com is reachable"), and how
+    /// often it checks
+    pub monitor: Option<(String, TimeDelta)>,
+}
+
+/// Why the assertions are released
+#[derive(Debug, Clone, PartialEq)]
+pub enum Release {
+    Break { round: u32 },
+    OnBattery,
+    BatteryCritical,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The monitor starts checking, and is assumed to never be met
+    Monitoring {
+        what: String,
+        every: TimeDelta,
+    },
+    /// A work round starts (with --repeat)
+    Round {
+        round: u32,
+        of: u32,
+    },
+    Held,
+    Released(Release),
+    /// The battery reaches a warning level, as a percentage
+    BatteryEarly(f64),
+    BatteryFinal(f64),
+    /// The timeout (or the last round) runs out, ending the session
+    TimedOut,
+    /// The battery is empty, so the Mac goes to sleep whatever the session wants
+    BatteryEmpty,
+}
+
+/// Which round the session is in at `elapsed`, or the round a break follows (as Err), or None
+/// once it's over
+fn round_at(plan: &Plan, elapsed: TimeDelta) -> Option<Result<u32, u32>> {
+    let Some(timeout) = plan.timeout else {
+        return Some(Ok(1));
+    };
+    let cycle = timeout + plan.break_duration;
+    let mut start = TimeDelta::zero();
+    for round in 1..=plan.rounds {
+        if elapsed < start + timeout {
+            return Some(Ok(round));
+        }
+        if round < plan.rounds && elapsed < start + cycle {
+            return Some(Err(round));
+        }
+        start += cycle;
+    }
+    None
+}
+
+/// Events with how long after the start they happen, in order
+pub fn simulate(plan: &Plan) -> Vec<(TimeDelta, Event)> {
+    let mut events = Vec::new();
+    if let Some((what, every)) = &plan.monitor {
+        events.push((
+            TimeDelta::zero(),
+            Event::Monitoring {
+                what: what.clone(),
+                every: *every,
+            },
+        ));
+    }
+
+    let step = TimeDelta::minutes(1);
+    let (mut held, mut round, mut warned_early, mut warned_final) = (false, 0, false, false);
+    let mut elapsed = TimeDelta::zero();
+    while elapsed <= plan.span {
+        let charge = plan.battery.map(|battery| {
+            if battery.on_ac {
+                battery.charge
+            } else {
+                let hours = elapsed.num_minutes() as f64 / 60.0;
+                (battery.charge - battery.drain_per_hour * hours).max(0.0)
+            }
+        });
+        if let Some(charge) = charge {
+            if charge <= 0.0 {
+                events.push((elapsed, Event::BatteryEmpty));
+                break;
+            }
+            if charge <= EARLY_WARNING_CHARGE && !warned_early {
+                warned_early = true;
+                events.push((elapsed, Event::BatteryEarly(charge)));
+            }
+            if charge <= FINAL_WARNING_CHARGE && !warned_final {
+                warned_final = true;
+                events.push((elapsed, Event::BatteryFinal(charge)));
+            }
+        }
+
+        let Some(current) = round_at(plan, elapsed) else {
+            events.push((elapsed, Event::TimedOut));
+            break;
+        };
+        if let (Ok(now_in), true) = (current, plan.rounds > 1) {
+            if now_in != round {
+                round = now_in;
+                events.push((
+                    elapsed,
+                    Event::Round {
+                        round,
+                        of: plan.rounds,
+                    },
+                ));
+            }
+        }
+        let on_ac = plan.battery.is_none_or(|battery| battery.on_ac);
+        let release = match current {
+            Err(round) => Some(Release::Break { round }),
+            Ok(_) if warned_final => Some(Release::BatteryCritical),
+            Ok(_) if plan.only_on_ac && !on_ac => Some(Release::OnBattery),
+            Ok(_) => None,
+        };
+        match (release, held) {
+            (None, false) => {
+                held = true;
+                events.push((elapsed, Event::Held));
+            }
+            (Some(release), true) => {
+                held = false;
+                events.push((elapsed, Event::Released(release)));
+            }
+            // Released from the start
+            (Some(release), false) if elapsed.is_zero() => {
+                events.push((elapsed, Event::Released(release)));
+            }
+            _ => {}
+        }
+        elapsed += step;
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Battery, Event, Plan, Release};
+    use chrono::TimeDelta;
+
+    fn plan() -> Plan {
+        Plan {
+            span: TimeDelta::hours(24),
+            timeout: None,
+            rounds: 1,
+            break_duration: TimeDelta::minutes(5),
+            battery: None,
+            only_on_ac: false,
+            monitor: None,
+        }
+    }
+
+    #[test]
+    fn test_rounds() {
+        let plan = Plan {
+            timeout: Some(TimeDelta::minutes(25)),
+            rounds: 2,
+            ..plan()
+        };
+        let minutes = |minutes| TimeDelta::minutes(minutes);
+        assert_eq!(
+            super::simulate(&plan),
+            vec![
+                (minutes(0), Event::Round { round: 1, of: 2 }),
+                (minutes(0), Event::Held),
+                (minutes(25), Event::Released(Release::Break { round: 1 })),
+                (minutes(30), Event::Round { round: 2, of: 2 }),
+                (minutes(30), Event::Held),
+                (minutes(55), Event::TimedOut),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_battery() {
+        // 30% at 10% an hour: warnings at 2 and 2.5 hours, empty at 3
+        let plan = Plan {
+            battery: Some(Battery {
+                charge: 30.0,
+                on_ac: false,
+                drain_per_hour: 10.0,
+            }),
+            monitor: Some((
+                String::from("until nas is reachable"),
+                TimeDelta::seconds(30),
+            )),
+            ..plan()
+        };
+        let events = super::simulate(&plan);
+        assert!(matches!(events[0].1, Event::Monitoring { .. }));
+        assert_eq!(events[1], (TimeDelta::zero(), Event::Held));
+        assert_eq!(events[2], (TimeDelta::hours(2), Event::BatteryEarly(10.0)));
+        assert_eq!(events[3].0, TimeDelta::minutes(150));
+        assert_eq!(
+            events[4],
+            (
+                TimeDelta::minutes(150),
+                Event::Released(Release::BatteryCritical)
+            )
+        );
+        assert_eq!(events[5], (TimeDelta::hours(3), Event::BatteryEmpty));
+
+        // --system-on-ac on battery never holds anything
+        let plan = Plan {
+            only_on_ac: true,
+            monitor: None,
+            ..plan
+        };
+        assert_eq!(
+            super::simulate(&plan)[0],
+            (TimeDelta::zero(), Event::Released(Release::OnBattery))
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Reference counts for system-wide settings that several sessions can change at once, like
+//! SleepDisabled. The first session to want one changes it, and only the last one to let go puts
+//! it back, so one session ending doesn't pull the rug out from under another.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A system-wide setting sessions share
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Shared {
+    /// Sleep disabled entirely (`-e`)
+    SleepDisabled,
+    /// Low Power Mode turned off (`--disable-lpm`)
+    LowPowerModeOff,
+    /// Standby settings changed (`--no-standby` and `--maintain-wake-on-lan`)
+    StandbySettingsChanged,
+}
+
+/// What has to happen to a setting after an owner came or went
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    /// It gained its first owner, so it has to be changed
+    Apply,
+    /// Its last owner let go, so it has to be put back
+    Restore,
+    /// Other owners still want it as it is
+    Keep,
+}
+
+/// Which sessions (by PID) own each setting
+#[derive(Debug, Default)]
+pub struct Owners(BTreeMap<Shared, BTreeSet<i32>>);
+
+impl Owners {
+    pub fn add(&mut self, setting: Shared, pid: i32) -> Change {
+        let owners = self.0.entry(setting).or_default();
+        let first = owners.is_empty();
+        owners.insert(pid);
+        if first {
+            Change::Apply
+        } else {
+            Change::Keep
+        }
+    }
+
+    /// Let go of a setting. It's put back once nobody owns it, even if `pid` never did.
+    pub fn remove(&mut self, setting: Shared, pid: i32) -> Change {
+        let owners = self.0.entry(setting).or_default();
+        owners.remove(&pid);
+        if owners.is_empty() {
+            Change::Restore
+        } else {
+            Change::Keep
+        }
+    }
+
+    pub fn count(&self, setting: Shared) -> usize {
+        self.0.get(&setting).map_or(0, BTreeSet::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, Owners, Shared};
+
+    #[test]
+    fn test_owners() {
+        let mut owners = Owners::default();
+        assert_eq!(owners.add(Shared::SleepDisabled, 1), Change::Apply);
+        assert_eq!(owners.add(Shared::SleepDisabled, 2), Change::Keep);
+        assert_eq!(owners.add(Shared::LowPowerModeOff, 2), Change::Apply);
+        assert_eq!(owners.count(Shared::SleepDisabled), 2);
+
+        assert_eq!(owners.remove(Shared::SleepDisabled, 1), Change::Keep);
+        assert_eq!(owners.remove(Shared::SleepDisabled, 2), Change::Restore);
+        assert_eq!(owners.count(Shared::SleepDisabled), 0);
+        assert_eq!(owners.count(Shared::LowPowerModeOff), 1);
+
+        // Nobody else owns it, so whoever asks puts it back
+        assert_eq!(owners.remove(Shared::SleepDisabled, 3), Change::Restore);
+    }
+}
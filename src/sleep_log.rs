@@ -0,0 +1,74 @@
+use caffeinate2::i18n::format_duration;
+use caffeinate2::power_log::{self, PowerEvent};
+use std::io;
+use std::process;
+use std::time::Duration;
+
+const LOG_TIME_FMT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The reason powerd gave for the last sleep since `since`, from the unified log.
+/// Falls back to `pmset -g log` when the unified log has nothing (it can be turned down by profiles).
+pub fn last_sleep_reason(since: chrono::DateTime<chrono::Local>) -> Option<String> {
+    let output = process::Command::new("/usr/bin/log")
+        .args(["show", "--style", "compact", "--start"])
+        .arg(since.format(LOG_TIME_FMT).to_string())
+        .args([
+            "--predicate",
+            "process == \"powerd\" AND eventMessage CONTAINS \"Entering Sleep state\"",
+        ])
+        .stderr(process::Stdio::null())
+        .output();
+    trace!(
+        "log show since {since}: {:?}",
+        output.as_ref().map(|output| output.status)
+    );
+    let from_log = output.ok().and_then(|output| {
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .rev()
+            .find_map(power_log::sleep_reason)
+            .map(String::from)
+    });
+    from_log.or_else(|| {
+        pmset_events()
+            .ok()?
+            .iter()
+            .rev()
+            .filter(|event| event.time >= since)
+            .find_map(|event| power_log::sleep_reason(&event.message))
+            .map(String::from)
+    })
+}
+
+/// Everything in `pmset -g log`, oldest first
+pub fn pmset_events() -> io::Result<Vec<PowerEvent>> {
+    let output = process::Command::new("/usr/bin/pmset")
+        .args(["-g", "log"])
+        .stderr(process::Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "pmset exited with {}",
+            output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(power_log::parse_pmset_line)
+        .collect())
+}
+
+/// Tell the user why the Mac slept for `slept` even though sleep was being prevented
+pub fn report_sleep(slept: Duration) {
+    let slept = chrono::Duration::seconds(slept.as_secs() as i64);
+    // The sleep was logged a little before we noticed the gap
+    let since = chrono::Local::now() - slept - chrono::Duration::minutes(1);
+    let slept_str = format_duration(slept);
+    match last_sleep_reason(since) {
+        Some(reason) => println!(
+            "The Mac slept for {slept_str} anyway, because {} ({reason}).",
+            power_log::explain(&reason)
+        ),
+        None => println!("The Mac slept for {slept_str} anyway, but powerd didn't record why."),
+    }
+}
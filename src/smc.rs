@@ -0,0 +1,215 @@
+use caffeinate2::thermal;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::thread;
+use std::time::Duration;
+
+/// kSMCHandleYPCEvent, the AppleSMC user client method everything goes through
+const SMC_HANDLE_YPC_EVENT: u32 = 2;
+/// kSMCReadKey and kSMCGetKeyInfo
+const SMC_READ_KEY: u8 = 5;
+const SMC_GET_KEY_INFO: u8 = 9;
+
+/// Battery temperature sensors
+const BATTERY_TEMPERATURE_KEYS: [&str; 2] = ["TB0T", "TB1T"];
+
+/// Posted by the kernel with the thermal pressure level as its state
+const THERMAL_PRESSURE_NOTIFICATION: &str = "com.apple.system.thermalpressurelevel";
+
+/// How often the temperature is checked
+const INTERVAL: Duration = Duration::from_secs(10);
+
+/// CPU temperature sensors: Intel's CPU proximity and die sensors, then the Apple silicon
+/// SoC sensors (which ones exist depends on the chip)
+const CPU_TEMPERATURE_KEYS: [&str; 14] = [
+    "TC0P", "TC0D", "TC0E", "Tp01", "Tp05", "Tp09", "Tp0D", "Tp0T", "Tp0b", "Tp0f", "Tp1h", "Tp1t",
+    "Tf04", "Tf09",
+];
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct Version {
+    major: u8,
+    minor: u8,
+    build: u8,
+    reserved: u8,
+    release: u16,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct PowerLimits {
+    version: u16,
+    length: u16,
+    cpu_limit: u32,
+    gpu_limit: u32,
+    memory_limit: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct KeyInfo {
+    data_size: u32,
+    data_type: u32,
+    data_attributes: u8,
+}
+
+/// SMCKeyData_t, what goes in and out of the SMC
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct KeyData {
+    key: u32,
+    version: Version,
+    power_limits: PowerLimits,
+    key_info: KeyInfo,
+    result: u8,
+    status: u8,
+    data8: u8,
+    data32: u32,
+    bytes: [u8; 32],
+}
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOServiceMatching(name: *const c_char) -> *mut c_void;
+    fn IOServiceGetMatchingService(main_port: u32, matching: *mut c_void) -> u32;
+    fn IOServiceOpen(service: u32, owning_task: u32, kind: u32, connection: *mut u32) -> i32;
+    fn IOServiceClose(connection: u32) -> i32;
+    fn IOObjectRelease(object: u32) -> i32;
+    fn IOConnectCallStructMethod(
+        connection: u32,
+        selector: u32,
+        input: *const c_void,
+        input_size: usize,
+        output: *mut c_void,
+        output_size: *mut usize,
+    ) -> i32;
+}
+
+extern "C" {
+    static mach_task_self_: u32;
+    // notify(3), in libSystem
+    fn notify_register_check(name: *const c_char, token: *mut i32) -> u32;
+    fn notify_get_state(token: i32, state: *mut u64) -> u32;
+}
+
+/// The current thermal pressure level (see `thermal::pressure_name`)
+pub fn thermal_pressure() -> Option<u64> {
+    let name = CString::new(THERMAL_PRESSURE_NOTIFICATION).unwrap();
+    let mut token = 0;
+    let mut state = 0;
+    let result = unsafe { notify_register_check(name.as_ptr(), &mut token) };
+    if result != 0 {
+        trace!("notify_register_check({THERMAL_PRESSURE_NOTIFICATION}) = {result}");
+        return None;
+    }
+    let result = unsafe { notify_get_state(token, &mut state) };
+    trace!("notify_get_state({THERMAL_PRESSURE_NOTIFICATION}) = {result}, state {state}");
+    (result == 0).then_some(state)
+}
+
+/// Everything there is to know about how the machine is holding up. Without the SMC (like in
+/// a VM), that's just the thermal pressure.
+pub fn readings(smc: Option<&Smc>) -> thermal::Readings {
+    thermal::Readings {
+        pressure: thermal_pressure(),
+        cpu: smc.and_then(Smc::cpu_temperature),
+        fans: smc.map(Smc::fan_speeds).unwrap_or_default(),
+        battery: smc.and_then(Smc::battery_temperature),
+    }
+}
+
+/// A connection to the SMC
+pub struct Smc(u32);
+
+impl Smc {
+    pub fn open() -> Option<Smc> {
+        let name = CString::new("AppleSMC").unwrap();
+        let service = unsafe { IOServiceGetMatchingService(0, IOServiceMatching(name.as_ptr())) };
+        if service == 0 {
+            trace!("IOServiceGetMatchingService(AppleSMC) = 0");
+            return None;
+        }
+        let mut connection = 0;
+        let result = unsafe { IOServiceOpen(service, mach_task_self_, 0, &mut connection) };
+        unsafe { IOObjectRelease(service) };
+        trace!("IOServiceOpen(AppleSMC) = {result:#X}");
+        (result == 0).then_some(Smc(connection))
+    }
+
+    fn call(&self, input: &KeyData) -> Option<KeyData> {
+        let mut output = KeyData::default();
+        let mut output_size = std::mem::size_of::<KeyData>();
+        let result = unsafe {
+            IOConnectCallStructMethod(
+                self.0,
+                SMC_HANDLE_YPC_EVENT,
+                input as *const KeyData as *const c_void,
+                std::mem::size_of::<KeyData>(),
+                &mut output as *mut KeyData as *mut c_void,
+                &mut output_size,
+            )
+        };
+        // A nonzero result byte means the SMC doesn't know the key
+        (result == 0 && output.result == 0).then_some(output)
+    }
+
+    /// Read a key as a number. None if there's no such key or its type isn't a number.
+    fn read(&self, key: &str) -> Option<f64> {
+        let mut input = KeyData {
+            key: thermal::fourcc(key),
+            data8: SMC_GET_KEY_INFO,
+            ..KeyData::default()
+        };
+        let info = self.call(&input)?.key_info;
+        input.key_info.data_size = info.data_size;
+        input.data8 = SMC_READ_KEY;
+        let output = self.call(&input)?;
+        let size = (info.data_size as usize).min(output.bytes.len());
+        let value = thermal::decode(info.data_type, &output.bytes[..size]);
+        trace!("SMC {key} = {value:?}");
+        value
+    }
+
+    /// The hottest of `keys` that reads as a real temperature
+    fn hottest(&self, keys: &[&str]) -> Option<f64> {
+        keys.iter()
+            .filter_map(|key| self.read(key))
+            .filter(|celsius| thermal::is_plausible(*celsius))
+            .max_by(f64::total_cmp)
+    }
+
+    /// CPU (or SoC) temperature in °C
+    pub fn cpu_temperature(&self) -> Option<f64> {
+        self.hottest(&CPU_TEMPERATURE_KEYS)
+    }
+
+    /// Battery temperature in °C, on Macs with a battery
+    pub fn battery_temperature(&self) -> Option<f64> {
+        self.hottest(&BATTERY_TEMPERATURE_KEYS)
+    }
+
+    /// Current speed of each fan in RPM. Empty on fanless Macs.
+    pub fn fan_speeds(&self) -> Vec<f64> {
+        let count = self.read("FNum").unwrap_or(0.0) as usize;
+        (0..count)
+            .filter_map(|fan| self.read(&format!("F{fan}Ac")))
+            .collect()
+    }
+
+    /// Call `callback` with the CPU temperature every few seconds, on its own thread
+    pub fn watch_cpu_temperature(self, callback: impl Fn(f64) + Send + 'static) {
+        thread::spawn(move || loop {
+            thread::sleep(INTERVAL);
+            if let Some(celsius) = self.cpu_temperature() {
+                callback(celsius);
+            }
+        });
+    }
+}
+
+impl Drop for Smc {
+    fn drop(&mut self) {
+        unsafe { IOServiceClose(self.0) };
+    }
+}
@@ -0,0 +1,150 @@
+//! Standby and hibernation settings, and what else decides how reachable a sleeping Mac is. A
+//! Mac that's been asleep for the standby delay writes memory to disk and powers down, which
+//! drops connections an ordinary sleep would keep (see --no-standby and --maintain-wake-on-lan).
+
+use crate::duration_parser::format_short_duration;
+use serde::{Deserialize, Serialize};
+
+/// How long a Mac sleeps before going into standby. The low and high delays are for below and
+/// above `highstandbythreshold` percent battery.
+pub const DELAY_KEYS: [&str; 3] = ["standbydelay", "standbydelaylow", "standbydelayhigh"];
+
+/// Wake for network access
+pub const WAKE_ON_LAN_KEY: &str = "womp";
+
+/// Everything `describe` shows
+const KEYS: [&str; 6] = [
+    WAKE_ON_LAN_KEY,
+    "hibernatemode",
+    "standby",
+    "standbydelay",
+    "standbydelaylow",
+    "standbydelayhigh",
+];
+
+/// A power source's standby or hibernation setting, as `pmset -g custom` shows it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Setting {
+    /// pmset's flag for the power source, like "-b" for battery
+    pub source: String,
+    pub key: String,
+    pub value: u32,
+}
+
+impl Setting {
+    fn is_delay(&self) -> bool {
+        DELAY_KEYS.contains(&self.key.as_str())
+    }
+}
+
+/// The standby and hibernation settings per power source, from `pmset -g custom`
+pub fn parse_custom(output: &str) -> Vec<Setting> {
+    let mut settings = Vec::new();
+    let mut source = None;
+    for line in output.lines() {
+        match line.trim_end() {
+            "Battery Power:" => source = Some("-b"),
+            "AC Power:" => source = Some("-c"),
+            "UPS Power:" => source = Some("-u"),
+            line => {
+                let mut fields = line.split_whitespace();
+                let (Some(source), Some(key), Some(value)) = (source, fields.next(), fields.next())
+                else {
+                    continue;
+                };
+                if let (true, Ok(value)) = (KEYS.contains(&key), value.parse()) {
+                    settings.push(Setting {
+                        source: source.to_string(),
+                        key: key.to_string(),
+                        value,
+                    });
+                }
+            }
+        }
+    }
+    settings
+}
+
+/// The standby delays shorter than `seconds`, which --no-standby raises
+pub fn too_short(settings: &[Setting], seconds: u32) -> Vec<Setting> {
+    settings
+        .iter()
+        .filter(|setting| setting.is_delay() && setting.value < seconds)
+        .cloned()
+        .collect()
+}
+
+/// The power sources Wake for network access is off for, which --maintain-wake-on-lan turns on
+pub fn wake_on_lan_off(settings: &[Setting]) -> Vec<Setting> {
+    settings
+        .iter()
+        .filter(|setting| setting.key == WAKE_ON_LAN_KEY && setting.value == 0)
+        .cloned()
+        .collect()
+}
+
+/// One line per power source, like "Battery Power: hibernatemode 3, standby on, standbydelaylow 3h"
+pub fn describe(settings: &[Setting]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (flag, name) in [
+        ("-b", "Battery Power"),
+        ("-c", "AC Power"),
+        ("-u", "UPS Power"),
+    ] {
+        let parts: Vec<String> = settings
+            .iter()
+            .filter(|setting| setting.source == flag)
+            .map(|setting| match setting.key.as_str() {
+                "standby" if setting.value == 0 => String::from("standby off"),
+                "standby" => String::from("standby on"),
+                WAKE_ON_LAN_KEY if setting.value == 0 => {
+                    String::from("wake for network access off")
+                }
+                WAKE_ON_LAN_KEY => String::from("wake for network access on"),
+                _ if setting.is_delay() => format!(
+                    "{} {}",
+                    setting.key,
+                    format_short_duration(chrono::Duration::seconds(setting.value as i64))
+                ),
+                _ => format!("{} {}", setting.key, setting.value),
+            })
+            .collect();
+        if !parts.is_empty() {
+            lines.push(format!("{name}: {}", parts.join(", ")));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Setting;
+
+    const OUTPUT: &str = "Battery Power:\n standbydelaylow     10800\n standby              1\n hibernatemode        3\n sleep                1\nAC Power:\n standby              0\n standbydelayhigh     86400\n womp                 0\n";
+
+    #[test]
+    fn test_parse_custom() {
+        let settings = super::parse_custom(OUTPUT);
+        assert_eq!(settings.len(), 6);
+        assert_eq!(
+            settings[0],
+            Setting {
+                source: String::from("-b"),
+                key: String::from("standbydelaylow"),
+                value: 10800
+            }
+        );
+        assert_eq!(
+            super::too_short(&settings, 4 * 3600),
+            vec![settings[0].clone()]
+        );
+        assert_eq!(super::wake_on_lan_off(&settings), vec![settings[5].clone()]);
+        assert_eq!(
+            super::describe(&settings),
+            vec![
+                "Battery Power: standbydelaylow 3h, standby on, hibernatemode 3",
+                "AC Power: standby off, standbydelayhigh 1d, wake for network access off",
+            ]
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use crate::low_power;
+use caffeinate2::standby::{self, Setting};
+use std::io;
+
+/// What --no-standby raises the standby delay to for sessions without a timeout, in seconds
+pub const DEFAULT_DELAY: u32 = 24 * 60 * 60;
+
+/// The current standby and hibernation settings of every power source
+pub fn settings() -> io::Result<Vec<Setting>> {
+    Ok(standby::parse_custom(&low_power::pmset(&["-g", "custom"])?))
+}
+
+/// Set these settings to `value` (which needs root)
+pub fn set(settings: &[Setting], value: u32) -> io::Result<()> {
+    for setting in settings {
+        low_power::pmset(&[&setting.source, &setting.key, &value.to_string()])?;
+    }
+    Ok(())
+}
+
+/// Put back what `set` changed. Returns whether all of it was.
+pub fn restore(settings: &[Setting]) -> bool {
+    let mut restored = true;
+    for setting in settings {
+        let value = setting.value.to_string();
+        if let Err(err) = low_power::pmset(&[&setting.source, &setting.key, &value]) {
+            eprintln!(
+                "Error: Couldn't put {} back ({err}). Run `sudo pmset {} {} {}` to fix it.",
+                setting.key, setting.source, setting.key, setting.value
+            );
+            restored = false;
+        }
+    }
+    restored
+}
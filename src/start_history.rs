@@ -0,0 +1,46 @@
+use crate::session;
+use std::fs;
+use std::path::PathBuf;
+
+/// How far back starts are counted, in milliseconds
+const WINDOW: i64 = 60_000;
+
+/// More starts than this within a minute looks like a script starting us in a loop
+pub const BURST: usize = 10;
+
+/// Recent start times (Unix milliseconds) for this user
+fn path() -> PathBuf {
+    session::dir().join(format!("starts-{}", session::owner_uid()))
+}
+
+/// The start times still in the window, oldest first
+fn recent(contents: &str, now: i64) -> Vec<i64> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .filter(|time| now - time < WINDOW)
+        .collect()
+}
+
+/// Record that a session is starting. Returns how many started in the last minute, this one included.
+pub fn record() -> usize {
+    let now = chrono::Local::now().timestamp_millis();
+    let mut starts = recent(&fs::read_to_string(path()).unwrap_or_default(), now);
+    starts.push(now);
+    let contents: String = starts.iter().map(|time| format!("{time}\n")).collect();
+    // Only used for a warning, so it's fine if this fails
+    let result = session::create_dir()
+        .and_then(|()| session::write_atomically(&path(), contents.as_bytes()));
+    trace!("recording start in {}: {result:?}", path().display());
+    starts.len()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_recent() {
+        let contents = "1000\n50000\n\n70000\njunk\n";
+        assert_eq!(super::recent(contents, 100_000), vec![50000, 70000]);
+        assert!(super::recent("", 0).is_empty());
+    }
+}
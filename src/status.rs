@@ -0,0 +1,261 @@
+use crate::console_user;
+use crate::other_assertions;
+use crate::power_source;
+use crate::session::{Selector, Session};
+use crate::standby_override;
+use caffeinate2::duration_parser::format_short_duration;
+use caffeinate2::overlap;
+use caffeinate2::standby;
+use caffeinate2::time_format;
+use chrono::{Local, TimeZone};
+
+/// Durations offered in the xbar menu when nothing is running
+const XBAR_DURATIONS: [(&str, &str); 3] =
+    [("30 minutes", "30m"), ("1 hour", "1h"), ("2 hours", "2h")];
+
+pub fn describe(session: &Session) -> String {
+    let mut description = match &session.name {
+        Some(name) => format!("\"{name}\" (PID {})", session.pid),
+        None => format!("PID {}", session.pid),
+    };
+    description += &format!(": preventing {} sleep", session.types.join(", "));
+    if let Some(command) = &session.command {
+        description += &format!(" until `{command}` finishes");
+    } else {
+        if let Some(until) = session.until {
+            let end_time = Local.timestamp_opt(until, 0).unwrap();
+            description += &format!(" until {}", time_format::current().time(&end_time));
+        }
+        if let Some(pid) = session.waitfor {
+            if session.until.is_some() {
+                description += " or";
+            }
+            description += &format!(" until PID {pid} finishes");
+        }
+    }
+    if let Some(progress) = &session.progress {
+        description += &format!(" [{progress}]");
+    }
+    if let Some(tags) = session.describe_tags() {
+        description += &format!(" ({tags})");
+    }
+    if let (Some(user), Some((console, _))) = (&session.user, console_user::current()) {
+        // With fast user switching, say whose it is when they're not the one on screen
+        if *user != console {
+            description += &format!(" [{user}'s, switched out]");
+        }
+    }
+    description
+}
+
+pub fn print_status(selector: &Selector) {
+    let sessions = Session::select(selector);
+    if sessions.is_empty() {
+        println!("No {} running.", selector.describe());
+    }
+    for session in &sessions {
+        println!("{}", describe(session));
+    }
+    if sessions.len() > 1 {
+        let ends: Vec<Option<i64>> = sessions.iter().map(Session::ends).collect();
+        println!(
+            "Sleep is prevented{} by {} sessions.",
+            until(overlap::union_deadline(&ends)),
+            sessions.len()
+        );
+    }
+    if sessions
+        .iter()
+        .any(|session| session.types.iter().any(|name| name == "network client"))
+    {
+        println!("Network client sessions keep the Mac awake for services running on it (like file sharing), not for someone using it, so the display can still sleep.");
+    }
+
+    // Desktops have no battery to show
+    if let Some(charge) = power_source::battery_charge() {
+        let state = if power_source::on_ac_power() {
+            String::from("on AC power")
+        } else if let Some(minutes) = power_source::time_to_empty() {
+            format!(
+                "about {} left",
+                format_short_duration(chrono::Duration::minutes(minutes))
+            )
+        } else {
+            String::from("on battery")
+        };
+        println!("\nBattery: {charge}%, {state}");
+    }
+
+    // What else keeps the Mac awake, and whether it'll stop by itself
+    let ours: Vec<i32> = Session::list().iter().map(|session| session.pid).collect();
+    let others = other_assertions::list(&ours);
+    if !others.is_empty() {
+        println!("\nOther assertions:");
+        for assertion in &others {
+            println!("  {}", assertion.describe());
+        }
+    }
+
+    // Assertions don't stop a Mac that's asleep anyway from going into standby
+    let standby = standby_override::settings()
+        .map(|settings| standby::describe(&settings))
+        .unwrap_or_default();
+    if !standby.is_empty() {
+        println!("\nStandby and hibernation:");
+        for line in &standby {
+            println!("  {line}");
+        }
+    }
+}
+
+/// " until 18:00", or nothing without a deadline
+fn until(deadline: Option<i64>) -> String {
+    deadline.map_or(String::new(), |deadline| {
+        let time = Local.timestamp_opt(deadline, 0).unwrap();
+        format!(" until {}", time_format::current().time(&time))
+    })
+}
+
+/// The user's other running sessions
+fn others(pid: i32, uid: Option<u32>) -> Vec<Session> {
+    Session::list()
+        .into_iter()
+        .filter(|session| session.pid != pid && session.uid == uid)
+        .collect()
+}
+
+/// Like "Sleep is prevented until 18:00 by 3 sessions; yours ends at 17:00.", if the user's
+/// other sessions keep it going after `own` ends
+pub fn overlap(own: &Session) -> Option<String> {
+    let others = others(own.pid, own.uid);
+    let mut ends: Vec<Option<i64>> = others.iter().map(Session::ends).collect();
+    ends.push(own.ends());
+    let union = overlap::union_deadline(&ends);
+    if others.is_empty() || !overlap::outlasts(union, own.ends()) {
+        return None;
+    }
+    let own_end = Local.timestamp_opt(own.ends()?, 0).unwrap();
+    Some(format!(
+        "Sleep is prevented{} by {} sessions; yours ends at {}.",
+        until(union),
+        ends.len(),
+        time_format::current().time(&own_end)
+    ))
+}
+
+/// Like "Sleep is still prevented until 18:00 by 2 other sessions.", once session `pid` has ended
+pub fn still_prevented(pid: i32, uid: Option<u32>) -> Option<String> {
+    let others = others(pid, uid);
+    if others.is_empty() {
+        return None;
+    }
+    let ends: Vec<Option<i64>> = others.iter().map(Session::ends).collect();
+    let noun = if others.len() == 1 {
+        "session"
+    } else {
+        "sessions"
+    };
+    Some(format!(
+        "Sleep is still prevented{} by {} other {noun}.",
+        until(overlap::union_deadline(&ends)),
+        others.len()
+    ))
+}
+
+/// A sleep type as a single word, like "system-on-ac" for "system on AC"
+fn short_type(sleep_type: &str) -> String {
+    sleep_type
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// One line for scripts and shell prompts, like `active system,display until=2024-06-01T18:00 pid=123`
+/// or `inactive`. `until` is left out if any session has no timeout.
+/// Only the session files are read, so it's cheap enough to run on every prompt.
+pub fn print_short(selector: &Selector) {
+    let sessions = Session::select(selector);
+    if sessions.is_empty() {
+        println!("inactive");
+        return;
+    }
+    let mut types: Vec<String> = Vec::new();
+    for sleep_type in sessions.iter().flat_map(|session| &session.types) {
+        let sleep_type = short_type(sleep_type);
+        if !types.contains(&sleep_type) {
+            types.push(sleep_type);
+        }
+    }
+    let mut line = format!("active {}", types.join(","));
+    let ends: Vec<Option<i64>> = sessions.iter().map(Session::ends).collect();
+    if let Some(until) = overlap::union_deadline(&ends) {
+        let until = Local.timestamp_opt(until, 0).unwrap();
+        line += &format!(" until={}", until.format("%Y-%m-%dT%H:%M"));
+    }
+    let pids: Vec<String> = sessions
+        .iter()
+        .map(|session| session.pid.to_string())
+        .collect();
+    line += &format!(" pid={}", pids.join(","));
+    println!("{line}");
+}
+
+/// Machine-readable output formats for `ctl status`
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Format {
+    Json,
+    /// XML property list, which AppleScript and Shortcuts can read natively
+    Plist,
+}
+
+/// Structured status for scripts. Optional fields are left out when unset.
+pub fn print_structured(selector: &Selector, format: Format) {
+    let sessions = Session::select(selector);
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&sessions).unwrap()),
+        Format::Plist => {
+            plist::to_writer_xml(std::io::stdout(), &sessions).unwrap();
+            println!();
+        }
+    }
+}
+
+/// Output in the xbar/SwiftBar plugin format.
+/// See https://github.com/matryer/xbar-plugins/blob/main/CONTRIBUTING.md
+pub fn print_xbar(selector: &Selector) {
+    let exe = std::env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| String::from("caffeinate2"));
+    let sessions = Session::select(selector);
+
+    if sessions.is_empty() {
+        println!("💤");
+        println!("---");
+        println!("Sleep allowed");
+        for (label, duration) in XBAR_DURATIONS {
+            println!(
+                "Prevent sleep for {label} | bash=\"{exe}\" param1=-t param2={duration} param3=--detach terminal=false refresh=true"
+            );
+        }
+        // In the background, since xbar waits for the command before it refreshes
+        println!(
+            "Prevent sleep until stopped | bash=\"{exe}\" param1=--detach terminal=false refresh=true"
+        );
+        return;
+    }
+
+    println!("☕️");
+    println!("---");
+    for session in &sessions {
+        // A | would start the line's parameters
+        println!("{}", describe(session).replace('|', "¦"));
+        println!(
+            "--Stop | bash=\"{exe}\" param1=ctl param2=stop param3=--pid param4={} terminal=false refresh=true",
+            session.pid
+        );
+    }
+    println!("---");
+    println!("Stop all | bash=\"{exe}\" param1=ctl param2=stop terminal=false refresh=true");
+}
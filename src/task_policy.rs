@@ -0,0 +1,78 @@
+//! Scheduling for wrapped commands, so a long job can keep the Mac awake without making it
+//! sluggish to use in the meantime
+
+use std::io;
+
+/// PRIO_PROCESS from sys/resource.h
+const PRIO_PROCESS: i32 = 0;
+/// PRIO_DARWIN_PROCESS and PRIO_DARWIN_BG from sys/resource.h, which `taskpolicy -b` uses
+const PRIO_DARWIN_PROCESS: i32 = 4;
+const PRIO_DARWIN_BG: i32 = 0x1000;
+
+extern "C" {
+    // In libSystem
+    fn setpriority(which: i32, who: u32, priority: i32) -> i32;
+    fn pthread_set_qos_class_self_np(qos_class: u32, relative_priority: i32) -> i32;
+}
+
+/// Quality of service classes from sys/qos.h, highest last
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Qos {
+    /// Maintenance work nobody's waiting for, like indexing or backups
+    Background,
+    /// Long-running work someone's keeping an eye on, like a download or a build
+    Utility,
+    /// Work someone started and is waiting for
+    UserInitiated,
+}
+
+impl Qos {
+    fn class(self) -> u32 {
+        match self {
+            Qos::Background => 0x09,
+            Qos::Utility => 0x11,
+            Qos::UserInitiated => 0x19,
+        }
+    }
+}
+
+/// How to schedule a wrapped command
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Policy {
+    /// Nice value, from -20 (most favored) to 20
+    pub nice: Option<i32>,
+    pub qos: Option<Qos>,
+    /// Background scheduling and throttled disk and network I/O, like `taskpolicy -b`
+    pub background: bool,
+}
+
+impl Policy {
+    /// Apply it to the current process. Meant for the child between fork and exec, so it
+    /// mustn't allocate.
+    pub fn apply(self) -> io::Result<()> {
+        if let Some(nice) = self.nice {
+            if unsafe { setpriority(PRIO_PROCESS, 0, nice) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if self.background {
+            // Unlike the thread's QoS, this is the process's and outlives exec
+            if unsafe { setpriority(PRIO_DARWIN_PROCESS, 0, PRIO_DARWIN_BG) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(qos) = self.qos {
+            // The command runs on this thread once it's exec'd, and its threads start out with
+            // its QoS
+            let result = unsafe { pthread_set_qos_class_self_np(qos.class(), 0) };
+            if result != 0 {
+                return Err(io::Error::from_raw_os_error(result));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_default(self) -> bool {
+        self.nice.is_none() && self.qos.is_none() && !self.background
+    }
+}
@@ -0,0 +1,184 @@
+//! Temperatures from the SMC (System Management Controller), and the --max-wake-temp cutoff.
+
+/// How far below the limit it has to cool down before sleep is prevented again, in °C
+const HYSTERESIS: f64 = 5.0;
+
+/// A four-character SMC key or data type, like "TC0P", as the SMC wants it
+pub fn fourcc(code: &str) -> u32 {
+    code.bytes()
+        .fold(0, |value, byte| (value << 8) | byte as u32)
+}
+
+/// Decode an SMC value by its data type. Temperatures are "sp78" (Intel) or "flt " (Apple
+/// silicon), and fan speeds "fpe2" (Intel) or "flt ".
+pub fn decode(data_type: u32, bytes: &[u8]) -> Option<f64> {
+    let type_bytes = data_type.to_be_bytes();
+    match &type_bytes {
+        b"sp78" => Some(i16::from_be_bytes(bytes.get(..2)?.try_into().ok()?) as f64 / 256.0),
+        b"fpe2" => Some(u16::from_be_bytes(bytes.get(..2)?.try_into().ok()?) as f64 / 4.0),
+        b"flt " => Some(f32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as f64),
+        b"ui8 " => Some(*bytes.first()? as f64),
+        b"ui16" => Some(u16::from_be_bytes(bytes.get(..2)?.try_into().ok()?) as f64),
+        _ => None,
+    }
+}
+
+/// Whether a reading looks like a real temperature. Missing sensors read as 0 or garbage.
+pub fn is_plausible(celsius: f64) -> bool {
+    (1.0..150.0).contains(&celsius)
+}
+
+/// The name of a thermal pressure level, as posted to com.apple.system.thermalpressurelevel
+pub fn pressure_name(level: u64) -> &'static str {
+    match level {
+        0 => "nominal",
+        1 => "moderate",
+        2 => "heavy",
+        3 => "trapping",
+        4 => "sleeping",
+        _ => "unknown",
+    }
+}
+
+/// What the machine's health looks like, for people watching a long session
+#[derive(Debug, Default)]
+pub struct Readings {
+    pub pressure: Option<u64>,
+    pub cpu: Option<f64>,
+    /// RPM of each fan
+    pub fans: Vec<f64>,
+    pub battery: Option<f64>,
+}
+
+impl Readings {
+    /// Like "thermal pressure nominal, CPU 54°C, fans 1800/1750 RPM, battery 31°C", leaving
+    /// out whatever this Mac doesn't have
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(level) = self.pressure {
+            parts.push(format!("thermal pressure {}", pressure_name(level)));
+        }
+        if let Some(cpu) = self.cpu {
+            parts.push(format!("CPU {cpu:.0}°C"));
+        }
+        if !self.fans.is_empty() {
+            let fans: Vec<String> = self.fans.iter().map(|rpm| format!("{rpm:.0}")).collect();
+            let noun = if fans.len() == 1 { "fan" } else { "fans" };
+            parts.push(format!("{noun} {} RPM", fans.join("/")));
+        }
+        if let Some(battery) = self.battery {
+            parts.push(format!("battery {battery:.0}°C"));
+        }
+        parts.join(", ")
+    }
+}
+
+/// What changed after a reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    /// It's been too hot for long enough, so sleep should be allowed
+    TooHot,
+    /// It's cooled down for long enough, so sleep can be prevented again
+    CooledDown,
+}
+
+/// Trips once the temperature has been at or over `max` for `period` seconds, and resets once
+/// it's been a few degrees under it for as long, so a brief spike (or dip) doesn't count
+#[derive(Debug)]
+pub struct Cutoff {
+    max: f64,
+    period: i64,
+    tripped: bool,
+    /// When the readings started being on the other side of the limit
+    since: Option<i64>,
+}
+
+impl Cutoff {
+    pub fn new(max: f64, period: i64) -> Cutoff {
+        Cutoff {
+            max,
+            period,
+            tripped: false,
+            since: None,
+        }
+    }
+
+    /// Take a reading (in °C) at `now` (Unix seconds)
+    pub fn observe(&mut self, celsius: f64, now: i64) -> Option<Change> {
+        let crossing = if self.tripped {
+            celsius < self.max - HYSTERESIS
+        } else {
+            celsius >= self.max
+        };
+        if !crossing {
+            self.since = None;
+            return None;
+        }
+        let since = *self.since.get_or_insert(now);
+        if now - since < self.period {
+            return None;
+        }
+        self.tripped = !self.tripped;
+        self.since = None;
+        Some(if self.tripped {
+            Change::TooHot
+        } else {
+            Change::CooledDown
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Change;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(super::fourcc("TC0P"), 0x5443_3050);
+        assert_eq!(
+            super::decode(super::fourcc("sp78"), &[0x3C, 0x80]),
+            Some(60.5)
+        );
+        assert_eq!(
+            super::decode(super::fourcc("fpe2"), &[0x1F, 0x40]),
+            Some(2000.0)
+        );
+        assert_eq!(
+            super::decode(super::fourcc("flt "), &42.5f32.to_le_bytes()),
+            Some(42.5)
+        );
+        assert_eq!(super::decode(super::fourcc("ch8*"), &[0; 4]), None);
+        assert_eq!(super::decode(super::fourcc("sp78"), &[0x3C]), None);
+    }
+
+    #[test]
+    fn test_readings() {
+        let readings = super::Readings {
+            pressure: Some(1),
+            cpu: Some(54.4),
+            fans: vec![1800.0, 1750.2],
+            battery: Some(31.0),
+        };
+        assert_eq!(
+            readings.describe(),
+            "thermal pressure moderate, CPU 54°C, fans 1800/1750 RPM, battery 31°C"
+        );
+        assert_eq!(super::Readings::default().describe(), "");
+    }
+
+    #[test]
+    fn test_cutoff() {
+        let mut cutoff = super::Cutoff::new(90.0, 60);
+        // A short spike
+        assert_eq!(cutoff.observe(95.0, 0), None);
+        assert_eq!(cutoff.observe(80.0, 30), None);
+        assert_eq!(cutoff.observe(95.0, 40), None);
+        assert_eq!(cutoff.observe(95.0, 90), None);
+        assert_eq!(cutoff.observe(92.0, 100), Some(Change::TooHot));
+        // Not cool enough yet
+        assert_eq!(cutoff.observe(88.0, 110), None);
+        assert_eq!(cutoff.observe(88.0, 200), None);
+        assert_eq!(cutoff.observe(80.0, 210), None);
+        assert_eq!(cutoff.observe(80.0, 270), Some(Change::CooledDown));
+    }
+}
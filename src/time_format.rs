@@ -0,0 +1,140 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, TimeZone};
+use once_cell::sync::OnceCell;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Regions where clocks are usually 12-hour. Everywhere else defaults to 24-hour.
+const TWELVE_HOUR_REGIONS: [&str; 10] =
+    ["US", "CA", "AU", "NZ", "IN", "PH", "PK", "BD", "EG", "SA"];
+
+/// How times of day are shown, chosen with --time-format
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeFormat {
+    TwelveHour,
+    TwentyFourHour,
+    /// A strftime pattern, like "%H:%M"
+    Custom(String),
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<TimeFormat, String> {
+        match format {
+            "12h" => Ok(TimeFormat::TwelveHour),
+            "24h" => Ok(TimeFormat::TwentyFourHour),
+            _ if !format.contains('%') => Err(String::from(
+                "expected 12h, 24h or a strftime pattern like \"%H:%M\"",
+            )),
+            _ if StrftimeItems::new(format).any(|item| item == Item::Error) => {
+                Err(format!("invalid strftime pattern \"{format}\""))
+            }
+            _ => Ok(TimeFormat::Custom(format.to_string())),
+        }
+    }
+}
+
+impl TimeFormat {
+    /// The usual clock for a POSIX locale like "de_DE.UTF-8".
+    /// Without a locale (or with "C"), it's 12-hour like it always has been.
+    pub fn from_locale(locale: Option<&str>) -> TimeFormat {
+        let Some(locale) = locale.and_then(crate::i18n::parse_locale) else {
+            return TimeFormat::TwelveHour;
+        };
+        let twelve_hour = match locale.region {
+            Some(region) => TWELVE_HOUR_REGIONS.contains(&region.as_str()),
+            None => locale.language.as_str() == "en",
+        };
+        if twelve_hour {
+            TimeFormat::TwelveHour
+        } else {
+            TimeFormat::TwentyFourHour
+        }
+    }
+
+    fn pattern(&self) -> &str {
+        match self {
+            TimeFormat::TwelveHour => "%-I:%M:%S %p",
+            TimeFormat::TwentyFourHour => "%H:%M:%S",
+            TimeFormat::Custom(pattern) => pattern,
+        }
+    }
+
+    /// Format just the time of day
+    pub fn time<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        time.format(self.pattern()).to_string()
+    }
+}
+
+static FORMAT: OnceCell<TimeFormat> = OnceCell::new();
+
+/// The time locale from the environment, in the order POSIX uses
+fn env_locale() -> Option<String> {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Set the format from --time-format, or the locale if it wasn't given.
+/// Only the first call has any effect.
+pub fn init(format: Option<TimeFormat>) {
+    FORMAT
+        .get_or_init(|| format.unwrap_or_else(|| TimeFormat::from_locale(env_locale().as_deref())));
+}
+
+/// The format chosen by `init`
+pub fn current() -> &'static TimeFormat {
+    FORMAT.get_or_init(|| TimeFormat::from_locale(env_locale().as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeFormat;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_time_format() {
+        assert_eq!("12h".parse(), Ok(TimeFormat::TwelveHour));
+        assert_eq!("24h".parse(), Ok(TimeFormat::TwentyFourHour));
+        assert_eq!(
+            "%H:%M".parse(),
+            Ok(TimeFormat::Custom(String::from("%H:%M")))
+        );
+        assert!("36h".parse::<TimeFormat>().is_err());
+        assert!("%Q".parse::<TimeFormat>().is_err());
+    }
+
+    #[test]
+    fn test_locale_default() {
+        assert_eq!(TimeFormat::from_locale(None), TimeFormat::TwelveHour);
+        assert_eq!(TimeFormat::from_locale(Some("C")), TimeFormat::TwelveHour);
+        assert_eq!(
+            TimeFormat::from_locale(Some("en_US.UTF-8")),
+            TimeFormat::TwelveHour
+        );
+        assert_eq!(
+            TimeFormat::from_locale(Some("en_GB.UTF-8")),
+            TimeFormat::TwentyFourHour
+        );
+        assert_eq!(
+            TimeFormat::from_locale(Some("de_DE.UTF-8")),
+            TimeFormat::TwentyFourHour
+        );
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = chrono::Utc.with_ymd_and_hms(2024, 3, 1, 17, 5, 9).unwrap();
+        assert_eq!(TimeFormat::TwelveHour.time(&time), "5:05:09 PM");
+        assert_eq!(TimeFormat::TwentyFourHour.time(&time), "17:05:09");
+        assert_eq!(
+            TimeFormat::Custom(String::from("%H.%M")).time(&time),
+            "17.05"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Waiting on the wall clock. `thread::sleep` counts time on a clock that stops while the Mac
+//! sleeps and doesn't follow NTP adjustments, so long waits are split into short slices that
+//! each check the time again.
+
+use std::thread;
+use std::time::Duration;
+
+/// The longest single sleep, so clock changes and moved deadlines are noticed within a second
+const SLICE: Duration = Duration::from_secs(1);
+
+/// The current Unix time in milliseconds
+fn now() -> i64 {
+    chrono::Local::now().timestamp_millis()
+}
+
+/// Sleep until the Unix time (in milliseconds) that `deadline` returns has passed.
+/// It's called again after every slice, so the deadline can be moved (like by `ctl extend`) while we wait.
+pub fn sleep_until(deadline: impl Fn() -> i64) {
+    loop {
+        let remaining = deadline() - now();
+        if remaining <= 0 {
+            break;
+        }
+        thread::sleep(SLICE.min(Duration::from_millis(remaining as u64)));
+    }
+}
+
+/// Sleep for `duration` of wall clock time
+pub fn sleep_for(duration: Duration) {
+    let deadline = now().saturating_add(duration.as_millis().try_into().unwrap_or(i64::MAX));
+    sleep_until(|| deadline);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_sleep_until() {
+        let started = Instant::now();
+        super::sleep_until(|| 0);
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        // Moving the deadline while waiting is noticed
+        let deadline = super::now() + 100;
+        let calls = Cell::new(0);
+        super::sleep_until(|| {
+            calls.set(calls.get() + 1);
+            if calls.get() == 1 {
+                deadline + 50
+            } else {
+                deadline - 100
+            }
+        });
+        assert!(started.elapsed() < Duration::from_millis(1100));
+        assert_eq!(calls.get(), 2);
+
+        let started = Instant::now();
+        super::sleep_for(Duration::from_millis(50));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
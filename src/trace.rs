@@ -0,0 +1,24 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How many times -v was passed
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+/// Verbosity at which every FFI call, kevent and session file change is logged
+pub const TRACE_LEVEL: u8 = 3;
+
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= TRACE_LEVEL
+}
+
+/// Print a trace line to stderr when running with -vvv
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::trace::enabled() {
+            eprintln!("[trace {}] {}", chrono::Local::now().format("%H:%M:%S%.3f"), format_args!($($arg)*));
+        }
+    };
+}
@@ -0,0 +1,51 @@
+use nix::sys::event;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+
+/// Call `callback` with whether the file exists, whenever it's created or removed.
+/// The file may not exist yet, so this watches its directory.
+pub fn watch(path: PathBuf, callback: impl Fn(bool) + Send + 'static) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    watch_dir(&dir, move || callback(exists(&path)));
+}
+
+/// Call `callback` whenever an entry in the directory is created, removed or renamed over,
+/// using kqueue
+pub fn watch_dir(dir: &Path, callback: impl Fn() + Send + 'static) {
+    let dir_handle = File::open(dir).unwrap_or_else(|err| {
+        eprintln!("Error: Couldn't watch {}: {}", dir.display(), err);
+        process::exit(1);
+    });
+
+    thread::spawn(move || {
+        let kq = event::Kqueue::new().unwrap();
+        let kev = event::KEvent::new(
+            dir_handle.as_raw_fd() as usize,
+            event::EventFilter::EVFILT_VNODE,
+            event::EventFlag::EV_ADD | event::EventFlag::EV_CLEAR,
+            // Creating, removing or renaming an entry writes to the directory
+            event::FilterFlag::NOTE_WRITE,
+            0,
+            0,
+        );
+        trace!("kevent registered: {:?}", kev);
+        kq.kevent(&[kev], &mut [], None).unwrap();
+
+        let mut eventlist = [kev];
+        loop {
+            let fired = kq.kevent(&[], &mut eventlist, None).unwrap();
+            trace!("kevent returned {} event(s): {:?}", fired, eventlist[0]);
+            callback();
+        }
+    });
+}
+
+pub fn exists(path: &Path) -> bool {
+    path.try_exists().unwrap_or(false)
+}
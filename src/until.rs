@@ -0,0 +1,147 @@
+use caffeinate2::timer;
+use caffeinate2::tr;
+use std::process;
+use std::time::Duration;
+
+/// Something that ends the session once it's true, checked every --interval
+#[derive(Debug)]
+pub enum Condition {
+    Reachable(String),
+    Unreachable(String),
+    /// Until the response matches (or while it does, if `while_matching`)
+    Http {
+        url: String,
+        expect: HttpExpectation,
+        while_matching: bool,
+    },
+}
+
+/// What counts as the expected response from --until-http or --while-http
+#[derive(Debug)]
+pub struct HttpExpectation {
+    pub status: u16,
+    /// Text the body has to contain
+    pub body: Option<String>,
+}
+
+impl HttpExpectation {
+    fn matches(&self, response: Option<(u16, &str)>) -> bool {
+        response.is_some_and(|(status, body)| {
+            status == self.status
+                && self
+                    .body
+                    .as_ref()
+                    .is_none_or(|expected| body.contains(expected.as_str()))
+        })
+    }
+}
+
+/// Split curl's output (the body, then the status code written by `-w`) into status and body
+fn parse_curl_output(output: &str) -> Option<(u16, &str)> {
+    let (body, status) = output.rsplit_once('\n')?;
+    Some((status.trim().parse().ok()?, body))
+}
+
+/// Whether a URL gives the expected response. A failed request (like a timeout) never does.
+fn http_matches(url: &str, expect: &HttpExpectation) -> bool {
+    let output = process::Command::new("/usr/bin/curl")
+        .args(["-sS", "-L", "--max-time", "10", "-w", "\n%{http_code}", url])
+        .stderr(process::Stdio::null())
+        .output();
+    trace!(
+        "curl {url}: {:?}",
+        output.as_ref().map(|output| output.status)
+    );
+    let Ok(output) = output else {
+        return false;
+    };
+    let output = String::from_utf8_lossy(&output.stdout);
+    expect.matches(parse_curl_output(&output).filter(|(status, _)| *status != 0))
+}
+
+/// Whether a host answers a ping within a couple of seconds.
+/// A route to it (what SystemConfiguration reachability reports) isn't enough, since a NAS on
+/// the local network stays "reachable" after it's turned off.
+fn is_reachable(host: &str) -> bool {
+    let status = process::Command::new("/sbin/ping")
+        .args(["-c", "1", "-t", "2", "-q", host])
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .status();
+    trace!("ping {host}: {status:?}");
+    status.is_ok_and(|status| status.success())
+}
+
+impl Condition {
+    pub fn is_met(&self) -> bool {
+        match self {
+            Condition::Reachable(host) => is_reachable(host),
+            Condition::Unreachable(host) => !is_reachable(host),
+            Condition::Http {
+                url,
+                expect,
+                while_matching,
+            } => http_matches(url, expect) != *while_matching,
+        }
+    }
+
+    /// The end of the session banner, like "until nas.local is unreachable."
+    pub fn banner(&self) -> String {
+        match self {
+            Condition::Reachable(host) => tr!("until-reachable", host = host.clone()),
+            Condition::Unreachable(host) => tr!("until-unreachable", host = host.clone()),
+            Condition::Http {
+                url,
+                while_matching: false,
+                ..
+            } => tr!("until-http", url = url.clone()),
+            Condition::Http { url, .. } => tr!("while-http", url = url.clone()),
+        }
+    }
+
+    pub fn met_message(&self) -> String {
+        match self {
+            Condition::Reachable(host) => format!("{host} is reachable now."),
+            Condition::Unreachable(host) => format!("{host} is unreachable now."),
+            Condition::Http {
+                url,
+                while_matching: false,
+                ..
+            } => format!("{url} gave the expected response."),
+            Condition::Http { url, .. } => format!("{url} stopped giving the expected response."),
+        }
+    }
+
+    /// Block until the condition is met
+    pub fn wait(&self, interval: Duration) {
+        while !self.is_met() {
+            timer::sleep_for(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpExpectation;
+
+    #[test]
+    fn test_http_expectation() {
+        let response = super::parse_curl_output("{\"state\": \"done\"}\n200");
+        assert_eq!(response, Some((200, "{\"state\": \"done\"}")));
+
+        let status_only = HttpExpectation {
+            status: 200,
+            body: None,
+        };
+        assert!(status_only.matches(response));
+        assert!(!status_only.matches(Some((503, ""))));
+        assert!(!status_only.matches(None));
+
+        let with_body = HttpExpectation {
+            status: 200,
+            body: Some(String::from("\"done\"")),
+        };
+        assert!(with_body.matches(response));
+        assert!(!with_body.matches(Some((200, "{\"state\": \"running\"}"))));
+    }
+}
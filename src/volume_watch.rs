@@ -0,0 +1,120 @@
+use core_foundation::array::CFArrayRef;
+use core_foundation::base::{kCFAllocatorDefault, CFAllocatorRef, TCFType};
+use core_foundation::dictionary::CFDictionaryRef;
+use core_foundation::runloop::{kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopRef};
+use core_foundation::string::CFStringRef;
+use std::fs;
+use std::os::raw::c_void;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+type DASessionRef = *mut c_void;
+type DADiskRef = *mut c_void;
+type DADiskAppearedCallback = extern "C" fn(disk: DADiskRef, context: *mut c_void);
+type DADiskDescriptionChangedCallback =
+    extern "C" fn(disk: DADiskRef, keys: CFArrayRef, context: *mut c_void);
+
+#[link(name = "DiskArbitration", kind = "framework")]
+extern "C" {
+    fn DASessionCreate(allocator: CFAllocatorRef) -> DASessionRef;
+    fn DASessionScheduleWithRunLoop(
+        session: DASessionRef,
+        run_loop: CFRunLoopRef,
+        run_loop_mode: CFStringRef,
+    );
+    fn DARegisterDiskAppearedCallback(
+        session: DASessionRef,
+        matching: CFDictionaryRef,
+        callback: DADiskAppearedCallback,
+        context: *mut c_void,
+    );
+    fn DARegisterDiskDisappearedCallback(
+        session: DASessionRef,
+        matching: CFDictionaryRef,
+        callback: DADiskAppearedCallback,
+        context: *mut c_void,
+    );
+    fn DARegisterDiskDescriptionChangedCallback(
+        session: DASessionRef,
+        matching: CFDictionaryRef,
+        watch: CFArrayRef,
+        callback: DADiskDescriptionChangedCallback,
+        context: *mut c_void,
+    );
+}
+
+/// Turn a volume name ("Backup") or path ("/Volumes/Backup") into its mount point
+pub fn volume_path(volume: &str) -> PathBuf {
+    if volume.contains('/') {
+        PathBuf::from(volume)
+    } else {
+        Path::new("/Volumes").join(volume)
+    }
+}
+
+/// Whether something is mounted at the path, i.e. it's on a different device than its parent
+pub fn is_mounted(path: &Path) -> bool {
+    let (Ok(metadata), Some(parent)) = (fs::metadata(path), path.parent()) else {
+        return false;
+    };
+    fs::metadata(parent).is_ok_and(|parent| parent.dev() != metadata.dev())
+}
+
+struct Watch {
+    path: PathBuf,
+    callback: Box<dyn Fn(bool) + Send>,
+}
+
+fn check(context: *mut c_void) {
+    let watch = unsafe { &*(context as *const Watch) };
+    let mounted = is_mounted(&watch.path);
+    trace!(
+        "disk arbitration event, {} mounted: {}",
+        watch.path.display(),
+        mounted
+    );
+    (watch.callback)(mounted);
+}
+
+extern "C" fn disk_appeared(_disk: DADiskRef, context: *mut c_void) {
+    check(context);
+}
+
+extern "C" fn disk_changed(_disk: DADiskRef, _keys: CFArrayRef, context: *mut c_void) {
+    check(context);
+}
+
+/// Call `callback` with whether the volume is mounted, whenever disks come, go or get
+/// (un)mounted. It runs on its own thread and can repeat the same state.
+pub fn watch(path: PathBuf, callback: impl Fn(bool) + Send + 'static) {
+    let watch = Watch {
+        path,
+        callback: Box::new(callback),
+    };
+    thread::spawn(move || {
+        // Lives as long as the run loop, which is forever
+        let context = Box::into_raw(Box::new(watch)) as *mut c_void;
+        unsafe {
+            let session = DASessionCreate(kCFAllocatorDefault);
+            let all_disks = std::ptr::null();
+            DARegisterDiskAppearedCallback(session, all_disks, disk_appeared, context);
+            DARegisterDiskDisappearedCallback(session, all_disks, disk_appeared, context);
+            // Mounting and unmounting change the volume path in the description
+            let all_keys = std::ptr::null();
+            DARegisterDiskDescriptionChangedCallback(
+                session,
+                all_disks,
+                all_keys,
+                disk_changed,
+                context,
+            );
+            DASessionScheduleWithRunLoop(
+                session,
+                CFRunLoop::get_current().as_concrete_TypeRef(),
+                kCFRunLoopDefaultMode,
+            );
+        }
+        CFRunLoop::run_current();
+    });
+}
@@ -0,0 +1,23 @@
+use crate::low_power;
+use caffeinate2::preflight;
+use chrono::NaiveDateTime;
+use std::io;
+
+/// Who scheduled wakes are scheduled by, as `pmset -g sched` shows it
+const OWNER: &str = "caffeinate2";
+
+/// Wake the Mac at `at` (which needs root), unless a wake is already scheduled for then.
+/// Returns whether one was scheduled.
+pub fn schedule_wake(at: NaiveDateTime) -> io::Result<bool> {
+    let now = chrono::Local::now().naive_local();
+    let schedule = low_power::pmset(&["-g", "sched"])?;
+    if preflight::scheduled_events(&schedule, now, &["wake", "wakeorpoweron"])
+        .iter()
+        .any(|event| event.at == at)
+    {
+        return Ok(false);
+    }
+    let date = at.format("%m/%d/%y %H:%M:%S").to_string();
+    low_power::pmset(&["schedule", "wake", &date, OWNER])?;
+    Ok(true)
+}
@@ -0,0 +1,23 @@
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often to check the clock. A check that comes much later than this means we were asleep.
+const TICK: Duration = Duration::from_secs(5);
+
+/// Call `callback` with how long the Mac slept, every time it wakes up
+pub fn watch(callback: impl Fn(Duration) + Send + 'static) {
+    thread::spawn(move || loop {
+        // The wall clock, since Instant (mach_absolute_time) stops while the Mac is asleep
+        let before = SystemTime::now();
+        thread::sleep(TICK);
+        // An error means the clock was set back, which isn't sleep
+        let Ok(elapsed) = before.elapsed() else {
+            continue;
+        };
+        // Sleeping isn't exact, so only a big gap counts
+        if elapsed > TICK * 2 {
+            trace!("woke up after {:?}", elapsed - TICK);
+            callback(elapsed - TICK);
+        }
+    });
+}
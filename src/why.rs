@@ -0,0 +1,67 @@
+use crate::sleep_log;
+use caffeinate2::i18n::format_duration;
+use caffeinate2::power_log::{self, PowerEvent, SleepRecord};
+use caffeinate2::time_format;
+use std::process;
+
+fn format_time(event: &PowerEvent) -> String {
+    let time = event.time.with_timezone(&chrono::Local);
+    format!(
+        "{} at {}",
+        time.format("%B %-d"),
+        time_format::current().time(&time)
+    )
+}
+
+fn print_record(record: &SleepRecord) {
+    let how_long = record
+        .wake
+        .as_ref()
+        .map(|wake| format!(" for {}", format_duration(wake.time - record.sleep.time)))
+        .unwrap_or_default();
+    let because = match record.reason() {
+        Some(reason) => format!(", because {} ({reason})", power_log::explain(reason)),
+        None => String::new(),
+    };
+    println!(
+        "Slept on {}{how_long}{because}.",
+        format_time(&record.sleep)
+    );
+
+    for assertion in &record.assertions {
+        println!("  Just before: {}", assertion.message);
+    }
+    match &record.wake {
+        Some(wake) => println!(
+            "  Woke up on {}: {}",
+            format_time(wake),
+            power_log::wake_reason(&wake.message).unwrap_or(&wake.message)
+        ),
+        None => println!("  No wake recorded after it yet."),
+    }
+}
+
+/// Explain the last `last` times the Mac slept, from powerd's log.
+/// Going back to sleep after a maintenance wake is left out unless `all` is set.
+pub fn why_did_it_sleep(last: usize, all: bool) {
+    let events = sleep_log::pmset_events().unwrap_or_else(|err| {
+        eprintln!("Error: Couldn't read the power management log: {err}");
+        process::exit(1);
+    });
+    let records: Vec<SleepRecord> = power_log::sleep_records(&events)
+        .into_iter()
+        .filter(|record| all || !record.is_maintenance())
+        .collect();
+    if records.is_empty() {
+        println!("No sleeps in the power management log.");
+        return;
+    }
+
+    let shown = &records[records.len().saturating_sub(last)..];
+    for (index, record) in shown.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        print_record(record);
+    }
+}